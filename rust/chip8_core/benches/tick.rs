@@ -0,0 +1,82 @@
+// Instructions per second with and without the decode cache, and with the recompiler:
+//
+//     cargo bench --bench tick --features jit
+//
+// A few games from ROMS/ for a real mix of instructions, with the timers ticking every 10
+// instructions like the frontends do, and a tight counting loop for the best case.
+
+use chip8_core::EMU;
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use std::hint::black_box;
+
+const INSTRUCTIONS: u64 = 10_000;
+
+type Tick = fn(&mut EMU);
+
+const LOOP: [u8; 6] = [
+    0x70, 0x01, // 200: ADD V0, 1
+    0x81, 0x04, // 202: ADD V1, V0
+    0x12, 0x00, // 204: JP 200
+];
+
+fn run(emu: &mut EMU, tick: Tick) {
+    for n in 0..INSTRUCTIONS {
+        tick(emu);
+        if n % 10 == 9 {
+            emu.tick_timers();
+        }
+    }
+}
+
+fn tick(c: &mut Criterion) {
+    let roms: [(&str, &[u8]); 4] = [
+        ("loop", &LOOP),
+        ("BLINKY", include_bytes!("../../../ROMS/BLINKY")),
+        ("INVADERS", include_bytes!("../../../ROMS/INVADERS")),
+        ("TETRIS", include_bytes!("../../../ROMS/TETRIS")),
+    ];
+    let ways: [(&str, Tick); 2] = [("cached", EMU::tick), ("uncached", EMU::tick_uncached)];
+
+    let mut group = c.benchmark_group("tick");
+    group.throughput(Throughput::Elements(INSTRUCTIONS));
+    for (name, rom) in roms {
+        for (way, tick) in ways {
+            group.bench_with_input(BenchmarkId::new(way, name), &rom, |b, rom| {
+                // a fresh machine every time, the games would end up waiting for keys otherwise
+                b.iter_batched_ref(
+                    || {
+                        let mut emu = EMU::new();
+                        emu.seed(1);
+                        emu.load(rom);
+                        emu
+                    },
+                    |emu| run(black_box(emu), tick),
+                    BatchSize::LargeInput,
+                );
+            });
+        }
+        #[cfg(feature = "jit")]
+        group.bench_with_input(BenchmarkId::new("jit", name), &rom, |b, rom| {
+            // and a fresh recompiler for it, compiling the blocks is part of the run
+            b.iter_batched_ref(
+                || {
+                    let mut emu = EMU::new();
+                    emu.seed(1);
+                    emu.load(rom);
+                    (emu, chip8_core::jit::Jit::new())
+                },
+                |(emu, jit)| {
+                    for _ in 0..INSTRUCTIONS / 10 {
+                        jit.run(black_box(emu), 10);
+                        emu.tick_timers();
+                    }
+                },
+                BatchSize::LargeInput,
+            );
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, tick);
+criterion_main!(benches);
@@ -0,0 +1,355 @@
+// Guesses the platform and quirks a ROM was written for, for ROMs nobody has described
+//
+// Goes through the code reachable from the entry point through jumps, calls and skips (see
+// cfg.rs), so data after the program isn't mistaken for instructions, looking for tell-tale
+// signs:
+// - SUPER-CHIP or XO-CHIP only instructions
+// - 8XY6/8XYE with a VY that isn't VX, written for shifts that read VY
+// - loads and stores after FX55/FX65 without setting I, which count on I moving on or not
+// - sprites drawn across the screen edge at fixed positions, where clipping matters
+// - BNNN, whose target depends on the jump quirk
+//
+// It's only a guess. Code reached through BNNN isn't followed and self-modifying code
+// fools it, which the confidence tries to reflect.
+
+use crate::cfg::Cfg;
+use crate::{Quirks, Variant, SCREEN_WIDTH, START_ADDR};
+use std::fmt;
+
+// how far to look after FX55/FX65 for a use of I
+const LOAD_STORE_WINDOW: usize = 6;
+// the original CHIP-8 screen, which is what ROMs are written against
+const ROM_SCREEN_HEIGHT: usize = 32;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Finding {
+    pub addr: u16,
+    pub note: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct Analysis {
+    pub variant: Variant,
+    pub quirks: Quirks,
+    pub confidence: f32, // 0.0 - 1.0
+    pub instructions: usize, // reachable instructions found
+    pub findings: Vec<Finding>,
+}
+
+impl fmt::Display for Analysis {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let quirks = if self.quirks == Quirks::default() {"none".to_string()} else {self.quirks.to_string()};
+        writeln!(f, "variant {} ({:.0}% confidence), quirks: {}", self.variant, self.confidence * 100.0, quirks)?;
+        writeln!(f, "{} reachable instructions", self.instructions)?;
+        for finding in &self.findings {
+            writeln!(f, "  {:03X}: {}", finding.addr, finding.note)?;
+        }
+        Ok(())
+    }
+}
+
+// What the analysis has seen so far
+#[derive(Default)]
+struct Evidence {
+    schip: usize,
+    xochip: usize,
+    shift_reads_vy: usize,
+    uses_increment: usize,
+    keeps_i: usize,
+    edge_sprites: usize,
+    computed_jumps: usize,
+    unknown: usize,
+}
+
+pub fn analyze(rom: &[u8]) -> Analysis {
+    let start = START_ADDR as usize;
+    let byte_at = |addr: usize| -> Option<u8> { rom.get(addr.checked_sub(start)?).copied() };
+    let op_at = |addr: usize| -> Option<u16> { Some((byte_at(addr)? as u16) << 8 | byte_at(addr + 1)? as u16) };
+
+    let mut evidence = Evidence::default();
+    let mut findings = Vec::new();
+    let mut note = |addr: usize, note: String| findings.push(Finding { addr: addr as u16, note });
+
+    // only the code that can be reached, so data isn't mistaken for instructions
+    let cfg = Cfg::new(rom);
+    for (addr, op) in cfg.instructions() {
+        let addr = addr as usize;
+        let (x, y, n) = ((op >> 8 & 0xF) as usize, (op >> 4 & 0xF) as usize, op & 0xF);
+
+        match op {
+            0x00E0 | 0x0000 | 0x00EE => (),
+            0x00FD => {
+                evidence.schip += 1;
+                note(addr, "00FD exit (SUPER-CHIP)".to_string());
+            },
+            0x00FB | 0x00FC | 0x00FE | 0x00FF => {
+                evidence.schip += 1;
+                note(addr, format!("{:04X} scroll/resolution (SUPER-CHIP)", op));
+            },
+            0x00C0..=0x00CF => {
+                evidence.schip += 1;
+                note(addr, format!("{:04X} scroll down (SUPER-CHIP)", op));
+            },
+            0x00D0..=0x00DF => {
+                evidence.xochip += 1;
+                note(addr, format!("{:04X} scroll up (XO-CHIP)", op));
+            },
+            0xF000 => {
+                // I = the next 16 bits
+                evidence.xochip += 1;
+                note(addr, "F000 NNNN long I (XO-CHIP)".to_string());
+            },
+            0xF002 => {
+                evidence.xochip += 1;
+                note(addr, "F002 audio pattern (XO-CHIP)".to_string());
+            },
+            _ => match op >> 12 {
+                // 0NNN machine code routine, not run by any interpreter we emulate
+                0x0 => evidence.unknown += 1,
+                0x1..=0x4 => (), // followed by the CFG
+                0x5 | 0x9 if n == 0 => (),
+                0x5 if n == 2 || n == 3 => {
+                    evidence.xochip += 1;
+                    note(addr, format!("{:04X} register range save/load (XO-CHIP)", op));
+                },
+                0x8 if n == 6 || n == 0xE => {
+                    // shifting a register in place works the same either way, and 8X06 is
+                    // usually written for shifts that ignore VY
+                    if x != y && y != 0 {
+                        evidence.shift_reads_vy += 1;
+                        note(addr, format!("{:04X} shifts V{:X} into V{:X}", op, y, x));
+                    }
+                },
+                0x8 if n <= 7 => (),
+                0xB => {
+                    evidence.computed_jumps += 1;
+                    note(addr, format!("{:04X} computed jump, not followed", op));
+                },
+                0xD => {
+                    if n == 0 {
+                        evidence.schip += 1;
+                        note(addr, format!("{:04X} 16x16 sprite (SUPER-CHIP)", op));
+                    } else if let Some((px, py, i)) = known_draw(&op_at, &cfg, addr, x, y)
+                        && let Some(w) = sprite_width(&byte_at, i, n as usize)
+                        && w > 0
+                        // the starting position wraps everywhere, only the rest of the sprite may not
+                        && let (px, py) = (px as usize % SCREEN_WIDTH, py as usize % ROM_SCREEN_HEIGHT)
+                        && (px + w > SCREEN_WIDTH || py + n as usize > ROM_SCREEN_HEIGHT)
+                    {
+                        evidence.edge_sprites += 1;
+                        note(addr, format!("{:04X} draws at ({}, {}), across the screen edge", op, px, py));
+                    }
+                },
+                0xE if op & 0xFF == 0x9E || op & 0xFF == 0xA1 => (),
+                0xF => {
+                    match op & 0xFF {
+                        0x01 => {
+                            evidence.xochip += 1;
+                            note(addr, format!("{:04X} select plane (XO-CHIP)", op));
+                        },
+                        0x3A => {
+                            evidence.xochip += 1;
+                            note(addr, format!("{:04X} audio pitch (XO-CHIP)", op));
+                        },
+                        0x30 | 0x75 | 0x85 => {
+                            evidence.schip += 1;
+                            note(addr, format!("{:04X} big font/flag registers (SUPER-CHIP)", op));
+                        },
+                        0x55 | 0x65 => match uses_i_after(&op_at, addr) {
+                            Some(true) => {
+                                evidence.uses_increment += 1;
+                                note(addr, format!("{:04X} carries on where the last load/store left I", op));
+                            },
+                            Some(false) => {
+                                evidence.keeps_i += 1;
+                                note(addr, format!("{:04X} writes back to the same I", op));
+                            },
+                            None => (),
+                        },
+                        0x07 | 0x0A | 0x15 | 0x18 | 0x1E | 0x29 | 0x33 => (),
+                        _ => evidence.unknown += 1,
+                    }
+                },
+                0x6 | 0x7 | 0xA | 0xC => (),
+                _ => evidence.unknown += 1,
+            },
+        }
+    }
+
+    let (variant, quirks, confidence) = decide(&evidence, cfg.instructions().count());
+    Analysis { variant, quirks, confidence, instructions: cfg.instructions().count(), findings }
+}
+
+fn decide(e: &Evidence, instructions: usize) -> (Variant, Quirks, f32) {
+    let (variant, mut confidence) = if e.xochip > 0 {
+        (Variant::XoChip, 0.7 + 0.05 * e.xochip.min(5) as f32)
+    } else if e.schip > 0 {
+        (Variant::SuperChip, 0.7 + 0.05 * e.schip.min(5) as f32)
+    } else {
+        // nothing but plain instructions, the more of them the surer
+        (Variant::Chip8, if instructions < 20 {0.4} else {0.6})
+    };
+
+    // without SCHIP or XO-CHIP instructions start from what the emulator has always done,
+    // which suits most games, and only turn on what the code asks for
+    let mut quirks = match variant {
+        Variant::Chip8 => Quirks::default(),
+        _ => Quirks::for_variant(variant),
+    };
+    if e.shift_reads_vy > 0 && variant != Variant::SuperChip {
+        quirks.shift_uses_vy = true;
+        confidence += 0.05;
+    }
+    if e.uses_increment > e.keeps_i {
+        quirks.load_store_increments_i = true;
+        confidence += if variant == Variant::SuperChip {-0.1} else {0.05};
+    } else if e.keeps_i > e.uses_increment {
+        quirks.load_store_increments_i = false;
+        confidence += if variant == Variant::SuperChip {0.05} else {0.0};
+    }
+    if e.edge_sprites > 0 && variant == Variant::Chip8 {
+        quirks.clip_sprites = true;
+    }
+    if e.computed_jumps > 0 {
+        confidence -= 0.1; // code we couldn't follow
+    }
+    if e.unknown > 0 {
+        // probably data we walked into
+        confidence -= 0.05 * e.unknown.min(6) as f32;
+    }
+    (variant, quirks, confidence.clamp(0.0, 1.0))
+}
+
+// Looks up to LOAD_STORE_WINDOW instructions past FX55/FX65 along the straight line of code
+// for the next load or store without I being set in between. Another one of the same kind
+// walks through memory, so counts on the increment: Some(true). The other kind writes back
+// what was just read, so counts on I staying put: Some(false). None if there's no telling.
+fn uses_i_after(op_at: &impl Fn(usize) -> Option<u16>, addr: usize) -> Option<bool> {
+    let kind = op_at(addr)? & 0xFF;
+    let mut addr = addr + 2;
+    for _ in 0..LOAD_STORE_WINDOW {
+        let op = op_at(addr)?;
+        match (op >> 12, op & 0xFF) {
+            (0xF, 0x55) | (0xF, 0x65) => return Some(op & 0xFF == kind),
+            (0xA, _) | (0xF, 0x1E) | (0xF, 0x29) | (0xF, 0x30) | (0x0, _) | (0x1, _) | (0x2, _) | (0xB, _) => return None,
+            _ => (),
+        }
+        addr += 2;
+    }
+    None
+}
+
+// Position and sprite address of a DXYN if VX, VY and I were all set to constants by the
+// instructions just before it (6XNN then any 7XNN, ANNN), in the same straight line of code
+fn known_draw(op_at: &impl Fn(usize) -> Option<u16>, cfg: &Cfg, addr: usize, x: usize, y: usize) -> Option<(u8, u8, usize)> {
+    let mut values: [Option<u8>; 2] = [None, None];
+    let mut added = [0u8; 2];
+    let mut i = None;
+    let mut at = addr;
+    // walk back while the code before runs straight into this instruction
+    for _ in 0..8 {
+        if at < START_ADDR as usize + 2 || !cfg.contains((at - 2) as u16) {
+            break;
+        }
+        let op = op_at(at - 2)?;
+        if matches!(op >> 12, 0x1 | 0x2 | 0xB) || op == 0x00EE {
+            break;
+        }
+        at -= 2;
+        let reg = (op >> 8 & 0xF) as usize;
+        for (k, r) in [x, y].into_iter().enumerate() {
+            if values[k].is_some() || reg != r {
+                continue;
+            }
+            match op >> 12 {
+                0x6 => values[k] = Some((op as u8).wrapping_add(added[k])),
+                0x7 => added[k] = added[k].wrapping_add(op as u8),
+                0x8 | 0xC | 0xF => return None, // set some other way
+                _ => (),
+            }
+        }
+        match (op >> 12, op & 0xFF) {
+            (0xA, _) if i.is_none() => i = Some((op & 0xFFF) as usize),
+            (0xF, 0x1E) | (0xF, 0x29) | (0xF, 0x55) | (0xF, 0x65) if i.is_none() => return None,
+            _ => (),
+        }
+        // a skip could have jumped over what comes after it
+        if matches!(op >> 12, 0x3 | 0x4 | 0x5 | 0x9 | 0xE) {
+            break;
+        }
+    }
+    Some((values[0]?, values[1]?, i?))
+}
+
+// Columns a sprite actually uses, 1 - 8, from its rows in the ROM
+fn sprite_width(byte_at: &impl Fn(usize) -> Option<u8>, i: usize, rows: usize) -> Option<usize> {
+    let mut bits = 0u8;
+    for row in 0..rows {
+        bits |= byte_at(i + row)?;
+    }
+    Some(8 - bits.trailing_zeros().min(8) as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rom(ops: &[u16]) -> Vec<u8> {
+        ops.iter().flat_map(|op| op.to_be_bytes()).collect()
+    }
+
+    #[test]
+    fn plain_chip8() {
+        let a = analyze(&rom(&[0x6000, 0x6100, 0xA20A, 0xD015, 0x1208]));
+        assert_eq!(a.variant, Variant::Chip8);
+        assert!(a.findings.is_empty());
+    }
+
+    #[test]
+    fn schip_opcodes() {
+        let a = analyze(&rom(&[0x00FF, 0x6000, 0xD000, 0x1206]));
+        assert_eq!(a.variant, Variant::SuperChip);
+        assert!(a.confidence >= 0.7);
+    }
+
+    #[test]
+    fn xochip_opcodes() {
+        let a = analyze(&rom(&[0xF000, 0x0300, 0xF101, 0x1206]));
+        assert_eq!(a.variant, Variant::XoChip);
+    }
+
+    #[test]
+    fn data_after_the_program_is_ignored() {
+        // jumps over an 00FF that is only data
+        let a = analyze(&rom(&[0x1204, 0x00FF, 0x1204]));
+        assert_eq!(a.variant, Variant::Chip8);
+        assert_eq!(a.instructions, 2);
+    }
+
+    #[test]
+    fn shift_reading_vy() {
+        let a = analyze(&rom(&[0x8126, 0x1202]));
+        assert!(a.quirks.shift_uses_vy);
+        let a = analyze(&rom(&[0x8106, 0x1202]));
+        assert!(!a.quirks.shift_uses_vy);
+    }
+
+    #[test]
+    fn load_store_increment() {
+        // two loads in a row read on from where the first stopped
+        let a = analyze(&rom(&[0xA300, 0xF165, 0xF165, 0x1206]));
+        assert!(a.quirks.load_store_increments_i);
+        // read, change, write back
+        let a = analyze(&rom(&[0xA300, 0xF065, 0x7001, 0xF055, 0x1208]));
+        assert!(!a.quirks.load_store_increments_i);
+        let a = analyze(&rom(&[0xF000, 0x0300, 0xF065, 0x7001, 0xF055, 0x120A]));
+        assert!(!a.quirks.load_store_increments_i);
+    }
+
+    #[test]
+    fn sprite_across_the_edge() {
+        let a = analyze(&rom(&[0xA20C, 0x603C, 0x6100, 0x7002, 0xD011, 0x120A, 0xFF00]));
+        assert!(a.findings.iter().any(|f| f.addr == 0x208 && f.note.contains("(62, 0)")));
+        assert!(a.quirks.clip_sprites);
+    }
+}
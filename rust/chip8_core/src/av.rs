@@ -0,0 +1,249 @@
+// Raw video and audio dumps for muxing with ffmpeg
+//
+// Every emulated frame writes one raw RGBA picture to the video stream and exactly
+// SAMPLES_PER_FRAME samples of the beep to a 16-bit mono WAV stream. 44100 Hz divides
+// evenly by 60 fps, so both streams share the same timebase and never drift apart, no
+// matter how fast the emulator runs. Either stream can be a file or a named pipe:
+//
+//   ffmpeg -f rawvideo -pix_fmt rgba -video_size 256x256 -framerate 60 -i video.rgba \
+//          -i audio.wav -c:v libx264 -pix_fmt yuv420p -c:a aac out.mp4
+
+use crate::{render::rgba_len, Palette, RenderError, ScaleFilter, EMU, SCREEN_HEIGHT, SCREEN_WIDTH};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+pub const FRAMES_PER_SECOND: u32 = 60;
+pub const SAMPLE_RATE: u32 = 44100;
+pub const SAMPLES_PER_FRAME: usize = (SAMPLE_RATE / FRAMES_PER_SECOND) as usize;
+
+const BEEP_FREQUENCY: u64 = 440;
+const BEEP_LEVEL: i16 = (0.2 * i16::MAX as f32) as i16; // same volume as the desktop app
+const WAV_HEADER_LEN: u32 = 44;
+
+#[derive(Debug)]
+pub enum AvError {
+    Render(RenderError),
+    Io(io::Error),
+}
+
+impl fmt::Display for AvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AvError::Render(e) => write!(f, "{}", e),
+            AvError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for AvError {}
+
+impl From<RenderError> for AvError {
+    fn from(e: RenderError) -> Self {
+        AvError::Render(e)
+    }
+}
+
+impl From<io::Error> for AvError {
+    fn from(e: io::Error) -> Self {
+        AvError::Io(e)
+    }
+}
+
+// The beep as a square wave whose phase carries over from frame to frame
+pub struct Beeper {
+    sample: u64,
+}
+
+impl Beeper {
+    pub fn new() -> Self {
+        Beeper { sample: 0 }
+    }
+
+    // One frame of samples, silent unless `on`
+    pub fn frame(&mut self, on: bool) -> [i16; SAMPLES_PER_FRAME] {
+        let mut out = [0; SAMPLES_PER_FRAME];
+        for s in out.iter_mut() {
+            if on {
+                // integer maths, so hours of audio come out as exact as the first second
+                let half_periods = self.sample * BEEP_FREQUENCY * 2 / SAMPLE_RATE as u64;
+                *s = if half_periods.is_multiple_of(2) {BEEP_LEVEL} else {-BEEP_LEVEL};
+            }
+            self.sample += 1;
+        }
+        out
+    }
+}
+
+impl Default for Beeper {
+    fn default() -> Self {
+        Beeper::new()
+    }
+}
+
+pub struct AvDump<V: Write, A: Write> {
+    video: V,
+    audio: A,
+    palette: Palette,
+    scale: usize,
+    filter: ScaleFilter,
+    pixels: Vec<u8>,
+    beeper: Beeper,
+    audio_bytes: u64,
+}
+
+impl<V: Write, A: Write> AvDump<V, A> {
+    pub fn new(video: V, mut audio: A, palette: &Palette, scale: usize, filter: ScaleFilter) -> Result<Self, AvError> {
+        filter.check_scale(scale)?;
+        // the real sizes aren't known yet, pipes keep these "as long as it goes" values
+        write_wav_header(&mut audio, u32::MAX - WAV_HEADER_LEN)?;
+        Ok(AvDump {
+            video,
+            audio,
+            palette: *palette,
+            scale,
+            filter,
+            pixels: vec![0; rgba_len(SCREEN_WIDTH, SCREEN_HEIGHT, scale)],
+            beeper: Beeper::new(),
+            audio_bytes: 0,
+        })
+    }
+
+    pub fn width(&self) -> usize {
+        SCREEN_WIDTH * self.scale
+    }
+
+    pub fn height(&self) -> usize {
+        SCREEN_HEIGHT * self.scale
+    }
+
+    // Call once per emulated frame, after tick_timers()
+    pub fn frame(&mut self, emu: &EMU) -> Result<(), AvError> {
+        emu.render_rgba(&mut self.pixels, &self.palette, self.scale, self.filter)?;
+        self.video.write_all(&self.pixels)?;
+
+        let samples = self.beeper.frame(emu.get_sound_timer() > 0);
+        let mut bytes = [0; SAMPLES_PER_FRAME * 2];
+        for (out, s) in bytes.chunks_exact_mut(2).zip(samples) {
+            out.copy_from_slice(&s.to_le_bytes());
+        }
+        self.audio.write_all(&bytes)?;
+        self.audio_bytes += bytes.len() as u64;
+        Ok(())
+    }
+
+    // Flushes both streams and hands them back, with the number of WAV data bytes written
+    // for fix_wav_header
+    pub fn finish(mut self) -> Result<(V, A, u64), AvError> {
+        self.video.flush()?;
+        self.audio.flush()?;
+        Ok((self.video, self.audio, self.audio_bytes))
+    }
+}
+
+// Video to a file, a named pipe or "-" for stdout, audio to a file or a named pipe
+pub type FileDump = AvDump<Box<dyn Write>, BufWriter<File>>;
+
+impl FileDump {
+    pub fn create(video: &Path, audio: &Path, palette: &Palette, scale: usize, filter: ScaleFilter) -> Result<Self, AvError> {
+        filter.check_scale(scale)?;
+        // opening a pipe waits for its reader, so the video goes first like ffmpeg reads them
+        let video: Box<dyn Write> = if video == Path::new("-") {
+            Box::new(BufWriter::new(io::stdout().lock()))
+        } else {
+            Box::new(BufWriter::new(File::create(video)?))
+        };
+        let audio = BufWriter::new(File::create(audio)?);
+        AvDump::new(video, audio, palette, scale, filter)
+    }
+
+    // Finishes both streams, with the real length in the WAV header when it's a file
+    pub fn close(self) -> Result<(), AvError> {
+        let (_, audio, data_bytes) = self.finish()?;
+        let mut audio = audio.into_inner().map_err(|e| e.into_error())?;
+        if audio.metadata()?.is_file() {
+            fix_wav_header(&mut audio, data_bytes)?;
+        }
+        Ok(())
+    }
+}
+
+// Puts the real sizes into the header of a WAV file written by AvDump, for players that
+// don't cope with the open-ended header. Not possible on pipes.
+pub fn fix_wav_header<S: Write + Seek>(out: &mut S, data_bytes: u64) -> io::Result<()> {
+    let data_bytes = data_bytes.min((u32::MAX - WAV_HEADER_LEN) as u64) as u32;
+    out.seek(SeekFrom::Start(0))?;
+    write_wav_header(out, data_bytes)?;
+    out.seek(SeekFrom::End(0))?;
+    Ok(())
+}
+
+// 16-bit mono PCM
+fn write_wav_header<W: Write>(out: &mut W, data_bytes: u32) -> io::Result<()> {
+    let mut header = Vec::with_capacity(WAV_HEADER_LEN as usize);
+    header.extend_from_slice(b"RIFF");
+    header.extend_from_slice(&(data_bytes + WAV_HEADER_LEN - 8).to_le_bytes());
+    header.extend_from_slice(b"WAVEfmt ");
+    header.extend_from_slice(&16u32.to_le_bytes()); // fmt chunk size
+    header.extend_from_slice(&1u16.to_le_bytes()); // PCM
+    header.extend_from_slice(&1u16.to_le_bytes()); // channels
+    header.extend_from_slice(&SAMPLE_RATE.to_le_bytes());
+    header.extend_from_slice(&(SAMPLE_RATE * 2).to_le_bytes()); // bytes per second
+    header.extend_from_slice(&2u16.to_le_bytes()); // bytes per sample
+    header.extend_from_slice(&16u16.to_le_bytes()); // bits per sample
+    header.extend_from_slice(b"data");
+    header.extend_from_slice(&data_bytes.to_le_bytes());
+    out.write_all(&header)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Register;
+    use std::io::Cursor;
+
+    #[test]
+    fn frames_share_the_timebase() {
+        let mut emu = EMU::new();
+        let mut dump = AvDump::new(Vec::new(), Cursor::new(Vec::new()), &Palette::MONO, 2, ScaleFilter::Nearest).unwrap();
+        assert_eq!((dump.width(), dump.height()), (128, 128));
+        dump.frame(&emu).unwrap();
+        emu.poke().set(Register::St, 2).unwrap();
+        dump.frame(&emu).unwrap();
+        let (video, audio, data_bytes) = dump.finish().unwrap();
+
+        assert_eq!(SAMPLES_PER_FRAME, 735);
+        assert_eq!(video.len(), 2 * 128 * 128 * 4);
+        assert_eq!(data_bytes, 2 * 735 * 2);
+        let audio = audio.into_inner();
+        assert_eq!(audio.len(), 44 + 2 * 735 * 2);
+        let samples: Vec<i16> = audio[44..].chunks_exact(2).map(|s| i16::from_le_bytes([s[0], s[1]])).collect();
+        assert!(samples[..735].iter().all(|&s| s == 0));
+        // 440 Hz flips every 50.1 samples, carried on from the silent frame: sample 735 is
+        // in the 15th half period and the 16th starts at 752
+        assert_eq!((samples[735], samples[751], samples[752]), (BEEP_LEVEL, BEEP_LEVEL, -BEEP_LEVEL));
+        assert!(samples[735..].iter().all(|&s| s.abs() == BEEP_LEVEL));
+    }
+
+    #[test]
+    fn wav_header() {
+        let mut audio = Cursor::new(Vec::new());
+        write_wav_header(&mut audio, u32::MAX - WAV_HEADER_LEN).unwrap();
+        audio.write_all(&[0; 8]).unwrap();
+        let open_ended = audio.get_ref().clone();
+        assert_eq!(open_ended.len(), 52);
+        assert_eq!(&open_ended[0..4], b"RIFF");
+        assert_eq!(&open_ended[4..8], &(u32::MAX - 8).to_le_bytes());
+        assert_eq!(&open_ended[8..16], b"WAVEfmt ");
+        assert_eq!(&open_ended[24..28], &44100u32.to_le_bytes());
+        assert_eq!(&open_ended[34..36], &16u16.to_le_bytes());
+        assert_eq!(&open_ended[36..40], b"data");
+
+        fix_wav_header(&mut audio, 8).unwrap();
+        let fixed = audio.into_inner();
+        assert_eq!(fixed.len(), 52);
+        assert_eq!(&fixed[4..8], &44u32.to_le_bytes());
+        assert_eq!(&fixed[40..44], &8u32.to_le_bytes());
+    }
+}
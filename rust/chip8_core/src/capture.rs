@@ -0,0 +1,217 @@
+// PNG screenshots and animated GIF recordings of the display
+// (behind the `capture` feature)
+
+use crate::render::{render_indexed, rgba_len, scale_indexed};
+use crate::{Palette, RenderError, ScaleFilter, EMU, SCREEN_HEIGHT, SCREEN_WIDTH};
+use std::fmt;
+use std::io::Write;
+
+// The emulator draws 60 frames a second, GIF delays count hundredths of a second
+const FRAMES_PER_SECOND: u64 = 60;
+// browsers and most viewers play anything shorter as 10 hundredths
+const MIN_DELAY: u64 = 2;
+
+#[derive(Debug)]
+pub enum CaptureError {
+    Render(RenderError),
+    Png(png::EncodingError),
+    Gif(gif::EncodingError),
+}
+
+impl fmt::Display for CaptureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CaptureError::Render(e) => write!(f, "{}", e),
+            CaptureError::Png(e) => write!(f, "PNG: {}", e),
+            CaptureError::Gif(e) => write!(f, "GIF: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for CaptureError {}
+
+impl From<RenderError> for CaptureError {
+    fn from(e: RenderError) -> Self {
+        CaptureError::Render(e)
+    }
+}
+
+impl From<png::EncodingError> for CaptureError {
+    fn from(e: png::EncodingError) -> Self {
+        CaptureError::Png(e)
+    }
+}
+
+impl From<gif::EncodingError> for CaptureError {
+    fn from(e: gif::EncodingError) -> Self {
+        CaptureError::Gif(e)
+    }
+}
+
+// Writes the current screen as an RGBA PNG, `scale` times the CHIP-8 resolution
+pub fn write_png<W: Write>(out: W, emu: &EMU, palette: &Palette, scale: usize, filter: ScaleFilter) -> Result<(), CaptureError> {
+    let mut pixels = vec![0; rgba_len(SCREEN_WIDTH, SCREEN_HEIGHT, scale)];
+    render_indexed(&emu.palette_indices(), SCREEN_WIDTH, SCREEN_HEIGHT, &mut pixels, palette, scale, filter)?;
+
+    let mut encoder = png::Encoder::new(out, (SCREEN_WIDTH * scale) as u32, (SCREEN_HEIGHT * scale) as u32);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&pixels)?;
+    writer.finish()?;
+    Ok(())
+}
+
+// Records one frame per emulated frame into a looping GIF.
+// Runs of identical frames become one GIF frame with a longer delay, and delays are
+// rounded against the total running time, so the GIF never drifts from 60 fps. A frame has
+// to stay up for MIN_DELAY, if the screen changes sooner it's dropped for the next one, so
+// games that redraw every frame come out at 30 fps.
+pub struct GifRecorder<W: Write> {
+    encoder: gif::Encoder<W>,
+    scale: usize,
+    filter: ScaleFilter,
+    pending: Option<Vec<u8>>, // last frame, not written until we know how long it stays
+    frames: u64,              // emulated frames recorded so far
+    written_centis: u64,      // sum of the delays written so far
+}
+
+impl<W: Write> GifRecorder<W> {
+    pub fn new(out: W, palette: &Palette, scale: usize, filter: ScaleFilter) -> Result<Self, CaptureError> {
+        // fail early rather than on the first frame
+        filter.check_scale(scale)?;
+
+        let colors: Vec<u8> = palette.colors.iter().flat_map(|c| [c[0], c[1], c[2]]).collect();
+        let mut encoder = gif::Encoder::new(out, (SCREEN_WIDTH * scale) as u16, (SCREEN_HEIGHT * scale) as u16, &colors)?;
+        encoder.set_repeat(gif::Repeat::Infinite)?;
+        Ok(GifRecorder { encoder, scale, filter, pending: None, frames: 0, written_centis: 0 })
+    }
+
+    // Call once per emulated frame
+    pub fn add_frame(&mut self, emu: &EMU) -> Result<(), CaptureError> {
+        let pixels = scale_indexed(&emu.palette_indices(), SCREEN_WIDTH, SCREEN_HEIGHT, self.scale, self.filter)?;
+        if self.pending.as_ref() != Some(&pixels) {
+            if self.pending_centis() >= MIN_DELAY {
+                self.flush()?;
+            }
+            self.pending = Some(pixels);
+        }
+        self.frames += 1;
+        Ok(())
+    }
+
+    // Writes the last frame and the GIF trailer
+    pub fn finish(mut self) -> Result<W, CaptureError> {
+        self.flush()?;
+        Ok(self.encoder.into_inner()?)
+    }
+
+    fn flush(&mut self) -> Result<(), CaptureError> {
+        let Some(pixels) = self.pending.take() else {
+            return Ok(());
+        };
+
+        // only the last frame can be short, it's stretched
+        let delay = self.pending_centis().clamp(MIN_DELAY, u16::MAX as u64) as u16;
+        self.written_centis += delay as u64;
+
+        let frame = gif::Frame {
+            width: (SCREEN_WIDTH * self.scale) as u16,
+            height: (SCREEN_HEIGHT * self.scale) as u16,
+            buffer: pixels.into(),
+            delay,
+            ..Default::default()
+        };
+        self.encoder.write_frame(&frame)?;
+        Ok(())
+    }
+
+    // How long the pending frame has been up, in hundredths
+    fn pending_centis(&self) -> u64 {
+        let total_centis = (self.frames * 100 + FRAMES_PER_SECOND / 2) / FRAMES_PER_SECOND;
+        total_centis.saturating_sub(self.written_centis)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Flips the "0" from the font at the top left every other tick
+    fn flicker() -> EMU {
+        let mut emu = EMU::new();
+        emu.load(&[
+            0xD0, 0x05, // 200: DRW V0, V0, 5
+            0x12, 0x00, // 202: JP 200
+        ]);
+        emu
+    }
+
+    // The delay of every frame in the GIF, and its global palette
+    fn decode_gif(bytes: &[u8]) -> (Vec<u16>, Vec<u8>) {
+        let mut options = gif::DecodeOptions::new();
+        options.set_color_output(gif::ColorOutput::Indexed);
+        let mut decoder = options.read_info(bytes).unwrap();
+        assert_eq!((decoder.width() as usize, decoder.height() as usize), (SCREEN_WIDTH * 2, SCREEN_HEIGHT * 2));
+        let palette = decoder.global_palette().unwrap().to_vec();
+        let mut delays = Vec::new();
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            delays.push(frame.delay);
+        }
+        (delays, palette)
+    }
+
+    #[test]
+    fn screenshots() {
+        let mut emu = flicker();
+        emu.tick();
+        let mut bytes = Vec::new();
+        write_png(&mut bytes, &emu, &Palette::MONO, 2, ScaleFilter::Nearest).unwrap();
+
+        let mut reader = png::Decoder::new(std::io::Cursor::new(bytes)).read_info().unwrap();
+        let mut pixels = vec![0; reader.output_buffer_size().unwrap()];
+        let info = reader.next_frame(&mut pixels).unwrap();
+        assert_eq!((info.width as usize, info.height as usize), (SCREEN_WIDTH * 2, SCREEN_HEIGHT * 2));
+        assert_eq!(info.color_type, png::ColorType::Rgba);
+        let at = |x: usize, y: usize| &pixels[(y * info.width as usize + x) * 4..][..4];
+        assert_eq!(at(0, 0), Palette::MONO.colors[1]); // top row of the 0 is lit
+        assert_eq!(at(2, 2), Palette::MONO.colors[0]); // its middle isn't
+        assert_eq!(at(8, 0), Palette::MONO.colors[0]);
+
+        assert!(write_png(&mut Vec::new(), &emu, &Palette::MONO, 3, ScaleFilter::Scale2x).is_err());
+    }
+
+    #[test]
+    fn gif_frames_last_as_long_as_the_screen_does() {
+        let mut emu = flicker();
+        let mut recorder = GifRecorder::new(Vec::new(), &Palette::MONO, 2, ScaleFilter::Nearest).unwrap();
+        for frame in 0..120 {
+            // drawn at 60, jumped back and erased at 90
+            let ticks = match frame { 60 => 1, 90 => 2, _ => 0 };
+            for _ in 0..ticks {
+                emu.tick();
+            }
+            recorder.add_frame(&emu).unwrap();
+        }
+        let (delays, palette) = decode_gif(&recorder.finish().unwrap());
+        assert_eq!(delays, [100, 50, 50]);
+        let colors: Vec<u8> = Palette::MONO.colors.iter().flat_map(|c| [c[0], c[1], c[2]]).collect();
+        assert_eq!(palette[..12], colors[..]);
+    }
+
+    #[test]
+    fn gif_frames_are_never_too_short() {
+        let mut emu = flicker();
+        let mut recorder = GifRecorder::new(Vec::new(), &Palette::MONO, 2, ScaleFilter::Nearest).unwrap();
+        for _ in 0..60 {
+            // the 0 comes and goes every frame
+            emu.tick();
+            emu.tick();
+            recorder.add_frame(&emu).unwrap();
+        }
+        let (delays, _) = decode_gif(&recorder.finish().unwrap());
+        assert!(delays.iter().all(|&delay| delay >= 2), "{:?}", delays);
+        assert_eq!(delays.iter().sum::<u16>(), 100);
+        assert!(delays.len() >= 30);
+    }
+}
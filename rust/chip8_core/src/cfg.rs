@@ -0,0 +1,326 @@
+// Control flow graph of a ROM, recovered without running it: the code reachable from the
+// entry point through jumps, calls and skips, cut into basic blocks, and which subroutines
+// call which. Both can be written as Graphviz DOT with the disassembly in every block.
+//
+// BNNN jumps somewhere that depends on V0, so blocks ending in one are marked and their
+// targets aren't followed. Neither is self-modifying code, the graph is of the ROM as loaded.
+
+use crate::disasm::line;
+use crate::symbols::Symbols;
+use crate::START_ADDR;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+// How control gets from one block to the next
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Edge {
+    Next,      // runs straight into it
+    Jump,      // 1NNN
+    Skip,      // the instruction after a skip, when it skips
+    AfterCall, // where a 2NNN carries on once the subroutine returns
+}
+
+#[derive(Clone, Debug)]
+pub struct Block {
+    pub start: u16,
+    pub instructions: Vec<(u16, u16)>, // address, opcode
+    pub successors: Vec<(u16, Edge)>,
+    pub call: Option<u16>, // subroutine called by the last instruction
+    pub computed: bool,    // ends in BNNN, where it goes isn't known
+}
+
+pub struct Cfg {
+    instructions: BTreeMap<u16, u16>,
+    blocks: BTreeMap<u16, Block>,
+    calls: BTreeMap<u16, BTreeSet<u16>>, // subroutine -> the subroutines it calls
+}
+
+// What an instruction does to the flow of control
+enum Flow {
+    Next(u16), // length of the instruction
+    Jump(u16),
+    Call(u16),
+    Skip,
+    Return,
+    Exit,     // 00FD
+    Computed, // BNNN
+}
+
+fn flow(op: u16) -> Flow {
+    let n = op & 0xF;
+    match (op >> 12, op & 0xFF) {
+        _ if op == 0x00EE => Flow::Return,
+        _ if op == 0x00FD => Flow::Exit,
+        _ if op == 0xF000 => Flow::Next(4), // XO-CHIP long I, the address follows
+        (0x1, _) => Flow::Jump(op & 0xFFF),
+        (0x2, _) => Flow::Call(op & 0xFFF),
+        (0x3 | 0x4, _) | (0xE, 0x9E | 0xA1) => Flow::Skip,
+        (0x5 | 0x9, _) if n == 0 => Flow::Skip,
+        (0xB, _) => Flow::Computed,
+        _ => Flow::Next(2),
+    }
+}
+
+impl Cfg {
+    // The graph of a ROM loaded at 0x200, starting there
+    pub fn new(rom: &[u8]) -> Self {
+        let start = START_ADDR as usize;
+        let byte_at = |addr: u16| rom.get((addr as usize).checked_sub(start)?).copied();
+        let op_at = |addr: u16| Some((byte_at(addr)? as u16) << 8 | byte_at(addr.checked_add(1)?)? as u16);
+
+        // everything reachable, noting where blocks have to start
+        let mut instructions = BTreeMap::new();
+        let mut leaders = BTreeSet::from([START_ADDR]);
+        let mut work = vec![START_ADDR];
+        while let Some(addr) = work.pop() {
+            if instructions.contains_key(&addr) {
+                continue;
+            }
+            let Some(op) = op_at(addr) else {
+                continue;
+            };
+            instructions.insert(addr, op);
+            match flow(op) {
+                Flow::Next(len) => work.push(addr + len),
+                Flow::Jump(target) => {
+                    leaders.insert(target);
+                    work.push(target);
+                },
+                Flow::Call(target) => {
+                    leaders.extend([target, addr + 2]);
+                    work.extend([target, addr + 2]);
+                },
+                Flow::Skip => {
+                    leaders.extend([addr + 2, addr + 4]);
+                    work.extend([addr + 2, addr + 4]);
+                },
+                Flow::Return | Flow::Exit | Flow::Computed => (),
+            }
+        }
+
+        let mut blocks = BTreeMap::new();
+        for &leader in leaders.iter().filter(|addr| instructions.contains_key(addr)) {
+            let mut block = Block { start: leader, instructions: Vec::new(), successors: Vec::new(), call: None, computed: false };
+            let mut addr = leader;
+            loop {
+                let op = instructions[&addr];
+                block.instructions.push((addr, op));
+                match flow(op) {
+                    Flow::Next(len) => {
+                        let next = addr + len;
+                        if instructions.contains_key(&next) && !leaders.contains(&next) {
+                            addr = next;
+                            continue;
+                        }
+                        block.successors.push((next, Edge::Next));
+                    },
+                    Flow::Jump(target) => block.successors.push((target, Edge::Jump)),
+                    Flow::Call(target) => {
+                        block.call = Some(target);
+                        block.successors.push((addr + 2, Edge::AfterCall));
+                    },
+                    Flow::Skip => block.successors.extend([(addr + 2, Edge::Next), (addr + 4, Edge::Skip)]),
+                    Flow::Computed => block.computed = true,
+                    Flow::Return | Flow::Exit => (),
+                }
+                break;
+            }
+            // nothing to go to past the end of the ROM
+            block.successors.retain(|(to, _)| instructions.contains_key(to));
+            blocks.insert(leader, block);
+        }
+
+        let mut cfg = Cfg { instructions, blocks, calls: BTreeMap::new() };
+        let mut entries: BTreeSet<u16> = cfg.blocks.values().filter_map(|block| block.call).collect();
+        entries.insert(START_ADDR);
+        entries.retain(|entry| cfg.blocks.contains_key(entry));
+        for entry in entries {
+            let callees = cfg.reachable(entry).iter().filter_map(|start| cfg.blocks[start].call).collect();
+            cfg.calls.insert(entry, callees);
+        }
+        cfg
+    }
+
+    // Blocks reachable from `entry` without following calls
+    fn reachable(&self, entry: u16) -> BTreeSet<u16> {
+        let mut seen = BTreeSet::new();
+        let mut work = vec![entry];
+        while let Some(start) = work.pop() {
+            if seen.insert(start) {
+                work.extend(self.blocks[&start].successors.iter().map(|(to, _)| *to));
+            }
+        }
+        seen
+    }
+
+    // Every reachable instruction in address order, with its opcode
+    pub fn instructions(&self) -> impl Iterator<Item = (u16, u16)> + '_ {
+        self.instructions.iter().map(|(addr, op)| (*addr, *op))
+    }
+
+    // Whether a reachable instruction starts at `addr`
+    pub fn contains(&self, addr: u16) -> bool {
+        self.instructions.contains_key(&addr)
+    }
+
+    // In address order
+    pub fn blocks(&self) -> impl Iterator<Item = &Block> {
+        self.blocks.values()
+    }
+
+    pub fn block(&self, start: u16) -> Option<&Block> {
+        self.blocks.get(&start)
+    }
+
+    // Entry points of the subroutines, 0x200 included, with the ones each of them calls
+    pub fn calls(&self) -> impl Iterator<Item = (u16, &BTreeSet<u16>)> {
+        self.calls.iter().map(|(entry, callees)| (*entry, callees))
+    }
+
+    pub fn is_subroutine(&self, addr: u16) -> bool {
+        self.calls.contains_key(&addr)
+    }
+
+    // Addresses of the BNNN instructions
+    pub fn computed_jumps(&self) -> Vec<u16> {
+        self.blocks().filter(|block| block.computed).filter_map(|block| block.instructions.last()).map(|(addr, _)| *addr).collect()
+    }
+
+    // Starts of blocks that get jumped to
+    pub fn jump_targets(&self) -> BTreeSet<u16> {
+        self.blocks()
+            .flat_map(|block| &block.successors)
+            .filter(|(_, edge)| *edge == Edge::Jump)
+            .map(|(to, _)| *to)
+            .collect()
+    }
+
+    // What to call the code at `addr`: its label if there's one, otherwise "main" for the
+    // entry point, "sub_XXX" for subroutines and "L_XXX" for anything else
+    pub fn name(&self, addr: u16, symbols: &Symbols) -> String {
+        match symbols.get(addr) {
+            Some(name) => name.to_string(),
+            None if addr == START_ADDR => "main".to_string(),
+            None if self.is_subroutine(addr) => format!("sub_{:03X}", addr),
+            None => format!("L_{:03X}", addr),
+        }
+    }
+
+    // The control flow graph, a box per block with its disassembly
+    pub fn dot(&self, symbols: &Symbols) -> String {
+        let mut out = "digraph cfg {\n    node [shape=box fontname=\"monospace\"];\n".to_string();
+        let targets = self.jump_targets();
+        for block in self.blocks() {
+            let mut label = String::new();
+            if block.start == START_ADDR || self.is_subroutine(block.start) || targets.contains(&block.start) || symbols.get(block.start).is_some() {
+                label += &format!("{}:\\l", escape(&self.name(block.start, symbols)));
+            }
+            // not a listing from the start, F000 NNNN is 4 bytes long
+            for &(addr, op) in &block.instructions {
+                label += &format!("{}\\l", escape(&line(addr, op)));
+            }
+            let _ = writeln!(out, "    b{:03X} [label=\"{}\"];", block.start, label);
+            for (to, edge) in &block.successors {
+                let style = match edge {
+                    Edge::Next => "",
+                    Edge::Jump => " [label=\"jump\"]",
+                    Edge::Skip => " [label=\"skip\"]",
+                    Edge::AfterCall => " [label=\"return\" style=dotted]",
+                };
+                let _ = writeln!(out, "    b{:03X} -> b{:03X}{};", block.start, to, style);
+            }
+            if let Some(target) = block.call.filter(|target| self.blocks.contains_key(target)) {
+                let _ = writeln!(out, "    b{:03X} -> b{:03X} [label=\"call\" style=dashed];", block.start, target);
+            }
+            if block.computed {
+                let _ = writeln!(out, "    c{:03X} [label=\"computed jump\" shape=plaintext];", block.start);
+                let _ = writeln!(out, "    b{:03X} -> c{:03X} [style=dashed];", block.start, block.start);
+            }
+        }
+        out += "}\n";
+        out
+    }
+
+    // Which subroutine calls which, the ones with computed jumps in them drawn dashed
+    pub fn call_graph_dot(&self, symbols: &Symbols) -> String {
+        let mut out = "digraph calls {\n    node [shape=box fontname=\"monospace\"];\n".to_string();
+        for (entry, callees) in self.calls() {
+            let computed = self.reachable(entry).iter().any(|start| self.blocks[start].computed);
+            let style = if computed {" style=dashed"} else {""};
+            let _ = writeln!(out, "    s{:03X} [label=\"{}\"{}];", entry, escape(&self.name(entry, symbols)), style);
+            for callee in callees {
+                let _ = writeln!(out, "    s{:03X} -> s{:03X};", entry, callee);
+            }
+        }
+        out += "}\n";
+        out
+    }
+}
+
+// The ROM where it would be in memory, for the disassembler
+pub(crate) fn ram(rom: &[u8]) -> Vec<u8> {
+    let mut ram = vec![0; START_ADDR as usize];
+    ram.extend_from_slice(rom);
+    ram
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROM: [u8; 16] = [
+        0x22, 0x08, // 200: CALL 208
+        0x30, 0x00, // 202: SE V0, 0
+        0x12, 0x00, // 204: JP 200
+        0xB2, 0x00, // 206: JP V0, 200
+        0x60, 0x01, // 208: LD V0, 1
+        0x70, 0x01, // 20A: ADD V0, 1
+        0x00, 0xEE, // 20C: RET
+        0xFF, 0xFF, // 20E: data, never reached
+    ];
+
+    #[test]
+    fn blocks_and_edges() {
+        let cfg = Cfg::new(&ROM);
+        let starts: Vec<u16> = cfg.blocks().map(|block| block.start).collect();
+        assert_eq!(starts, vec![0x200, 0x202, 0x204, 0x206, 0x208]);
+        assert_eq!(cfg.instructions().count(), 7);
+        assert!(!cfg.contains(0x20E));
+
+        let entry = cfg.block(0x200).unwrap();
+        assert_eq!(entry.call, Some(0x208));
+        assert_eq!(entry.successors, vec![(0x202, Edge::AfterCall)]);
+        assert_eq!(cfg.block(0x202).unwrap().successors, vec![(0x204, Edge::Next), (0x206, Edge::Skip)]);
+        assert_eq!(cfg.block(0x204).unwrap().successors, vec![(0x200, Edge::Jump)]);
+        assert_eq!(cfg.block(0x208).unwrap().instructions.len(), 3);
+        assert_eq!(cfg.computed_jumps(), vec![0x206]);
+        assert_eq!(cfg.calls().collect::<Vec<_>>(), vec![(0x200, &BTreeSet::from([0x208])), (0x208, &BTreeSet::new())]);
+    }
+
+    #[test]
+    fn dot_output() {
+        let cfg = Cfg::new(&ROM);
+        let symbols = Symbols::parse("0x208 count").unwrap();
+        let dot = cfg.dot(&symbols);
+        assert!(dot.contains("b208 [label=\"count:\\l0x208  6001  LD V0, #01\\l0x20A  7001  ADD V0, #01\\l0x20C  00EE  RET\\l\"];"));
+        assert!(dot.contains("b200 -> b208 [label=\"call\" style=dashed];"));
+        assert!(dot.contains("b206 -> c206 [style=dashed];"));
+        let calls = cfg.call_graph_dot(&symbols);
+        assert!(calls.contains("s200 [label=\"main\" style=dashed];\n    s200 -> s208;\n"));
+    }
+
+    #[test]
+    fn long_instructions_in_dot() {
+        let rom = [
+            0xF0, 0x00, 0x12, 0x34, // 200: LD I, #1234 (XO-CHIP)
+            0x60, 0x01,             // 204: LD V0, 1
+            0x00, 0xFD,             // 206: EXIT
+        ];
+        let dot = Cfg::new(&rom).dot(&Symbols::default());
+        assert!(dot.contains("b200 [label=\"main:\\l0x200  F000  DW #F000\\l0x204  6001  LD V0, #01\\l0x206  00FD  SYS #0FD\\l\"];"), "{}", dot);
+    }
+}
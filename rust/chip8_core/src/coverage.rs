@@ -0,0 +1,197 @@
+// Code coverage for test ROMs: which instructions ran and which sprite bytes DXYN drew, so
+// what a test never got to stands out. Reports come as text or in the lcov format, with the
+// ROM's addresses for line numbers, for genhtml and editors that read lcov.
+
+use crate::symbols::Symbols;
+use crate::{EMU, RAM_SIZE, START_ADDR};
+use std::fmt::Write;
+use std::ops::Range;
+
+pub struct Coverage {
+    executed: Vec<u64>, // per address, times an instruction starting there ran
+    drawn: Vec<u64>,    // per address, times DXYN read it as sprite data
+}
+
+// What a byte of the ROM was used for. Bytes that were both drawn and run count as code.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Usage {
+    Code,
+    Sprite,
+    Unused,
+}
+
+impl Default for Coverage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Coverage {
+    pub fn new() -> Self {
+        Coverage { executed: vec![0; RAM_SIZE], drawn: vec![0; RAM_SIZE] }
+    }
+
+    // Notes the instruction that's about to run
+    pub fn record(&mut self, emu: &EMU) {
+        let pc = emu.program_counter as usize;
+        let op = emu.state().opcode(pc as u16);
+        if let Some(count) = self.executed.get_mut(pc) {
+            *count += 1;
+        }
+        if op >> 12 == 0xD {
+            let read = emu.memory_access(op).read;
+            for count in &mut self.drawn[read.start.min(RAM_SIZE)..read.end.min(RAM_SIZE)] {
+                *count += 1;
+            }
+        }
+    }
+
+    // Runs one instruction and notes it
+    pub fn tick(&mut self, emu: &mut EMU) {
+        self.record(emu);
+        emu.tick();
+    }
+
+    pub fn usage(&self, addr: u16) -> Usage {
+        if self.hits(addr as usize).0 > 0 {
+            Usage::Code
+        } else if self.hits(addr as usize).1 > 0 {
+            Usage::Sprite
+        } else {
+            Usage::Unused
+        }
+    }
+
+    // Times the byte was run as part of an instruction and times it was drawn
+    fn hits(&self, addr: usize) -> (u64, u64) {
+        let second_byte = addr.checked_sub(1).map_or(0, |addr| self.executed[addr]);
+        (self.executed[addr] + second_byte, self.drawn[addr])
+    }
+
+    // Addresses a ROM of `len` bytes was loaded at
+    fn rom(len: usize) -> Range<u16> {
+        START_ADDR..(START_ADDR as usize + len).min(RAM_SIZE) as u16
+    }
+
+    // Summary, the stretches of the ROM that were never used and, with symbols, how much
+    // of the code under each label was
+    pub fn report(&self, rom_len: usize, symbols: &Symbols) -> String {
+        let rom = Self::rom(rom_len);
+        let count = |range: Range<u16>, usage: Usage| range.filter(|&addr| self.usage(addr) == usage).count();
+        let code = count(rom.clone(), Usage::Code);
+        let sprites = count(rom.clone(), Usage::Sprite);
+        let share = |used: usize, of: usize| 100.0 * used as f64 / of.max(1) as f64;
+        let mut out = format!(
+            "{} of {} ROM bytes used ({:.1}%): {} ran as code, {} were drawn as sprites\n",
+            code + sprites, rom.len(), share(code + sprites, rom.len()), code, sprites
+        );
+
+        let mut unused: Vec<Range<u16>> = Vec::new();
+        for addr in rom.clone().filter(|&addr| self.usage(addr) == Usage::Unused) {
+            if let Some(last) = unused.last_mut() && last.end == addr {
+                last.end += 1;
+            } else {
+                unused.push(addr..addr + 1);
+            }
+        }
+        if !unused.is_empty() {
+            out += "\nnever used\n";
+        }
+        for range in unused {
+            let _ = write!(out, "  {:#05X}-{:#05X}  {:>4} bytes", range.start, range.end - 1, range.len());
+            match symbols.containing(range.start) {
+                Some((addr, name)) if addr == range.start => out += &format!("  {}\n", name),
+                Some((addr, name)) => out += &format!("  {}+{:#X}\n", name, range.start - addr),
+                None => out += "\n",
+            }
+        }
+
+        if !symbols.is_empty() {
+            out += "\nby label\n";
+        }
+        for (range, name) in Self::labelled(&rom, symbols) {
+            let used = range.len() - count(range.clone(), Usage::Unused);
+            let _ = writeln!(out, "  {:<24} {:>5} of {:>5} bytes {:>6.1}%", name, used, range.len(), share(used, range.len()));
+        }
+        out
+    }
+
+    // The ROM split up by label, including anything before the first one
+    fn labelled<'a>(rom: &Range<u16>, symbols: &'a Symbols) -> Vec<(Range<u16>, &'a str)> {
+        let starts: Vec<(u16, &str)> = symbols.iter().filter(|(addr, _)| rom.contains(addr)).collect();
+        let mut parts = Vec::new();
+        match starts.first() {
+            Some(&(first, _)) if first > rom.start => parts.push((rom.start..first, "(before the first label)")),
+            None if !symbols.is_empty() => parts.push((rom.clone(), "(no label)")),
+            _ => (),
+        }
+        for (n, &(addr, name)) in starts.iter().enumerate() {
+            let end = starts.get(n + 1).map_or(rom.end, |next| next.0);
+            parts.push((addr..end, name));
+        }
+        parts
+    }
+
+    // lcov tracefile: a line per ROM byte numbered by its address, hit as often as it was run
+    // or drawn, and every label as a function
+    pub fn lcov(&self, source: &str, rom_len: usize, symbols: &Symbols) -> String {
+        let rom = Self::rom(rom_len);
+        let mut out = format!("TN:\nSF:{}\n", source);
+        let labels = Self::labelled(&rom, symbols);
+        for (range, name) in &labels {
+            let _ = writeln!(out, "FN:{},{}", range.start, name);
+        }
+        for (range, name) in &labels {
+            let _ = writeln!(out, "FNDA:{},{}", self.executed[range.start as usize], name);
+        }
+        let functions_hit = labels.iter().filter(|(range, _)| self.executed[range.start as usize] > 0).count();
+        let _ = writeln!(out, "FNF:{}\nFNH:{}", labels.len(), functions_hit);
+
+        let mut hit = 0;
+        for addr in rom.clone() {
+            let (ran, drawn) = self.hits(addr as usize);
+            hit += (ran + drawn > 0) as usize;
+            let _ = writeln!(out, "DA:{},{}", addr, ran + drawn);
+        }
+        let _ = writeln!(out, "LF:{}\nLH:{}\nend_of_record", rom.len(), hit);
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn code_and_sprites() {
+        let rom = [
+            0xA2, 0x08, // 200: LD I, 208
+            0xD0, 0x02, // 202: DRW V0, V0, 2
+            0x12, 0x04, // 204: JP 204
+            0x00, 0xE0, // 206: CLS, never reached
+            0xFF, 0x81, // 208: sprite
+            0x00,       // 20A: never used
+        ];
+        let mut emu = EMU::new();
+        emu.load(&rom);
+        let mut coverage = Coverage::new();
+        for _ in 0..4 {
+            coverage.tick(&mut emu);
+        }
+        assert_eq!(coverage.usage(0x205), Usage::Code);
+        assert_eq!(coverage.usage(0x206), Usage::Unused);
+        assert_eq!(coverage.usage(0x209), Usage::Sprite);
+
+        let symbols = Symbols::parse("0x206 unused\n0x208 ball").unwrap();
+        let report = coverage.report(rom.len(), &symbols);
+        assert!(report.starts_with("8 of 11 ROM bytes used (72.7%): 6 ran as code, 2 were drawn as sprites\n"));
+        assert!(report.contains("0x206-0x207     2 bytes  unused\n"));
+        assert!(report.contains("0x20A-0x20A     1 bytes  ball+0x2\n"));
+        assert!(report.contains("(before the first label)     6 of     6 bytes  100.0%"));
+
+        let lcov = coverage.lcov("test.ch8", rom.len(), &symbols);
+        assert!(lcov.contains("FNDA:0,unused\nFNDA:0,ball\nFNF:3\nFNH:1\n"));
+        assert!(lcov.contains("DA:516,2\n")); // 0x204 ran twice
+        assert!(lcov.ends_with("LF:11\nLH:8\nend_of_record\n"));
+    }
+}
@@ -0,0 +1,530 @@
+// Debugger for monitors and other tools: breakpoints, watchpoints and stepping, with the
+// timers kept at 60 Hz like the frontends do. Breakpoints and watchpoints can have a condition
+// (see expr.rs) and can log a message and keep going instead of stopping. With the journal
+// switched on, instructions can be taken back again.
+//
+// The interpreter has no hooks, so it stays as fast as before. Instead the debugger works out
+// which memory an instruction is about to touch before running it, and refuses to run
+// instructions that would crash the machine, so a monitor can show what went wrong.
+
+use crate::disasm::disassemble;
+use crate::expr::{Expr, Message};
+use crate::journal::{Hits, Journal};
+use crate::*;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::Range;
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    Access, // read or write
+}
+
+#[derive(Clone, Debug)]
+pub struct Breakpoint {
+    pub addr: u16,
+    pub when: Option<Expr>,   // only stops (or logs) when this is true
+    pub log: Option<Message>, // logs this instead of stopping
+    pub hits: u64,            // times the PC got here, whatever the condition said
+}
+
+#[derive(Clone, Debug)]
+pub struct Watchpoint {
+    pub kind: WatchKind,
+    pub start: u16,
+    pub end: u16, // inclusive
+    pub when: Option<Expr>,
+    pub log: Option<Message>,
+    pub hits: u64,
+}
+
+// Why running stopped
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Stop {
+    Done, // ran as many instructions as asked for
+    Breakpoint(u16),
+    // right after the instruction at `pc` touched `addr`
+    Watchpoint { index: usize, pc: u16, addr: u16, write: bool },
+    // the instruction at `pc` was not run
+    Fault { pc: u16, op: u16, reason: &'static str },
+    // nothing left to take back
+    JournalStart,
+}
+
+// RAM an instruction reads and writes, not counting its own fetch
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MemoryAccess {
+    pub read: Range<usize>,
+    pub write: Range<usize>,
+}
+
+pub struct Debugger {
+    breakpoints: BTreeMap<u16, Breakpoint>,
+    watchpoints: Vec<Option<Watchpoint>>, // deleted ones stay as None so numbers don't change
+    ticks_per_frame: usize,
+    instructions: u64,
+    resume_at: Option<u16>, // stopped at a breakpoint here, hit counted, don't stop again right away
+    log: Vec<String>,
+    journal: Option<Journal>,
+}
+
+impl Breakpoint {
+    pub fn at(addr: u16) -> Self {
+        Breakpoint { addr, when: None, log: None, hits: 0 }
+    }
+}
+
+impl Watchpoint {
+    pub fn new(kind: WatchKind, start: u16, end: u16) -> Self {
+        Watchpoint { kind, start, end, when: None, log: None, hits: 0 }
+    }
+}
+
+// What a breakpoint or watchpoint that was reached does
+enum Action {
+    Ignore,
+    Log(String),
+    Stop,
+}
+
+// Counts the hit and checks the condition
+fn action(when: &Option<Expr>, log: &Option<Message>, hits: &mut u64, emu: &EMU) -> Action {
+    *hits += 1;
+    if when.as_ref().is_some_and(|when| !when.is_true(emu, *hits)) {
+        return Action::Ignore;
+    }
+    match log {
+        Some(message) => Action::Log(message.format(emu, *hits)),
+        None => Action::Stop,
+    }
+}
+
+impl Debugger {
+    // `ticks_per_frame` instructions run between two timer ticks
+    pub fn new(ticks_per_frame: usize) -> Self {
+        Debugger {
+            breakpoints: BTreeMap::new(),
+            watchpoints: Vec::new(),
+            ticks_per_frame: ticks_per_frame.max(1),
+            instructions: 0,
+            resume_at: None,
+            log: Vec::new(),
+            journal: None,
+        }
+    }
+
+    pub fn instructions(&self) -> u64 {
+        self.instructions
+    }
+
+    pub fn frames(&self) -> u64 {
+        self.instructions / self.ticks_per_frame as u64
+    }
+
+    // false if it replaced one at the same address
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) -> bool {
+        self.breakpoints.insert(breakpoint.addr, breakpoint).is_none()
+    }
+
+    pub fn remove_breakpoint(&mut self, addr: u16) -> bool {
+        self.breakpoints.remove(&addr).is_some()
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = &Breakpoint> {
+        self.breakpoints.values()
+    }
+
+    // Returns the watchpoint's number
+    pub fn add_watchpoint(&mut self, watch: Watchpoint) -> usize {
+        self.watchpoints.push(Some(watch));
+        self.watchpoints.len() - 1
+    }
+
+    pub fn remove_watchpoint(&mut self, index: usize) -> bool {
+        self.watchpoints.get_mut(index).and_then(Option::take).is_some()
+    }
+
+    pub fn watchpoints(&self) -> impl Iterator<Item = (usize, &Watchpoint)> {
+        self.watchpoints.iter().enumerate().filter_map(|(i, w)| w.as_ref().map(|w| (i, w)))
+    }
+
+    // Keeps what the last `limit` instructions changed so they can be taken back, 0 turns
+    // the journal off. Starts a new journal either way.
+    pub fn set_journal(&mut self, limit: usize) {
+        self.journal = (limit > 0).then(|| Journal::new(limit));
+    }
+
+    // Instructions that can be taken back, and how many could be at most
+    pub fn journal_len(&self) -> (usize, usize) {
+        self.journal.as_ref().map_or((0, 0), |j| (j.len(), j.limit()))
+    }
+
+    // Takes back up to `count` instructions, and the breakpoint and watchpoint hits they counted
+    pub fn reverse_step(&mut self, emu: &mut EMU, count: u64) -> Stop {
+        for _ in 0..count {
+            let Some((instruction, hits)) = self.journal.as_mut().and_then(|j| j.undo(emu)) else {
+                return Stop::JournalStart;
+            };
+            // stopping at a breakpoint counted a hit for an instruction that never ran
+            let stopped = Hits { breakpoint: self.resume_at.take(), watchpoints: Vec::new() };
+            self.uncount(&stopped);
+            self.uncount(&hits);
+            self.instructions = instruction;
+        }
+        Stop::Done
+    }
+
+    // Takes back instructions until the PC is at a breakpoint whose condition holds, ending up
+    // the same as stopping there going forwards, hit count included. Logging breakpoints don't
+    // stop it, just like going forwards.
+    pub fn reverse_continue(&mut self, emu: &mut EMU) -> Stop {
+        loop {
+            if let Stop::JournalStart = self.reverse_step(emu, 1) {
+                return Stop::JournalStart;
+            }
+            let pc = emu.program_counter;
+            if let Some(bp) = self.breakpoints.get_mut(&pc)
+                && bp.log.is_none()
+                && bp.when.as_ref().is_none_or(|when| when.is_true(emu, bp.hits + 1))
+            {
+                bp.hits += 1;
+                self.resume_at = Some(pc);
+                return Stop::Breakpoint(pc);
+            }
+        }
+    }
+
+    fn uncount(&mut self, hits: &Hits) {
+        if let Some(bp) = hits.breakpoint.and_then(|addr| self.breakpoints.get_mut(&addr)) {
+            bp.hits = bp.hits.saturating_sub(1);
+        }
+        for &index in &hits.watchpoints {
+            if let Some(Some(watch)) = self.watchpoints.get_mut(index) {
+                watch.hits = watch.hits.saturating_sub(1);
+            }
+        }
+    }
+
+    // The PC and number of the last instruction that wrote `addr`, as far as the journal goes
+    pub fn last_write(&self, addr: u16) -> Option<(u16, u64)> {
+        self.journal.as_ref().and_then(|j| j.last_write(addr))
+    }
+
+    // Messages from logging breakpoints and watchpoints since the last call
+    pub fn take_log(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.log)
+    }
+
+    // Runs up to `count` instructions. Stops early before an instruction at a breakpoint, after
+    // one that touched a watched address, or before one that would crash the machine. Running
+    // on after stopping at a breakpoint doesn't stop at it again straight away.
+    pub fn run(&mut self, emu: &mut EMU, count: u64) -> Stop {
+        let mut resume_at = self.resume_at.take();
+        for _ in 0..count {
+            let pc = emu.program_counter;
+            if resume_at.take() != Some(pc) && let Some(bp) = self.breakpoints.get_mut(&pc) {
+                match action(&bp.when, &bp.log, &mut bp.hits, emu) {
+                    Action::Ignore => (),
+                    Action::Log(message) => self.log.push(format!("{:#05X}: {}", pc, message)),
+                    Action::Stop => {
+                        self.resume_at = Some(pc);
+                        return Stop::Breakpoint(pc);
+                    },
+                }
+            }
+            let breakpoint = self.breakpoints.contains_key(&pc).then_some(pc);
+            let op = match emu.fault() {
+                Ok(op) => op,
+                // the hit is counted already, running into the fault again doesn't count another
+                Err(reason) => {
+                    self.resume_at = breakpoint;
+                    return Stop::Fault { pc, op: emu.state().opcode(pc), reason };
+                },
+            };
+            let access = emu.memory_access(op);
+            let hits = self.watch_hits(&access);
+            let pending = self.journal.is_some().then(|| Journal::before(emu, op, &access, self.instructions));
+
+            emu.tick();
+            self.instructions += 1;
+            if self.instructions.is_multiple_of(self.ticks_per_frame as u64) {
+                emu.tick_timers();
+            }
+
+            // conditions see the machine after the access
+            let mut stop = None;
+            for &(index, addr, write) in &hits {
+                let Some(Some(watch)) = self.watchpoints.get_mut(index) else {
+                    continue;
+                };
+                match action(&watch.when, &watch.log, &mut watch.hits, emu) {
+                    Action::Ignore => (),
+                    Action::Log(message) => self.log.push(format!("{:#05X}: {}", pc, message)),
+                    Action::Stop => {
+                        stop.get_or_insert(Stop::Watchpoint { index, pc, addr, write });
+                    },
+                }
+            }
+            if let (Some(journal), Some(pending)) = (&mut self.journal, pending) {
+                let watchpoints = hits.iter().map(|&(index, _, _)| index).collect();
+                journal.commit(pending, emu, Hits { breakpoint, watchpoints });
+            }
+            if let Some(stop) = stop {
+                return stop;
+            }
+        }
+        Stop::Done
+    }
+
+    // Watchpoints the access touches, with the first address and whether it was a write
+    fn watch_hits(&self, access: &MemoryAccess) -> Vec<(usize, u16, bool)> {
+        let mut hits = Vec::new();
+        for (index, watch) in self.watchpoints() {
+            let range = watch.start as usize..watch.end as usize + 1;
+            let overlap = |r: &Range<usize>| {
+                let start = r.start.max(range.start);
+                (start < r.end.min(range.end)).then_some(start as u16)
+            };
+            if watch.kind != WatchKind::Read && let Some(addr) = overlap(&access.write) {
+                hits.push((index, addr, true));
+            } else if watch.kind != WatchKind::Write && let Some(addr) = overlap(&access.read) {
+                hits.push((index, addr, false));
+            }
+        }
+        hits
+    }
+
+    // The journal can't take back changes made by hand, so these start a new one
+    pub fn set_register(&mut self, emu: &mut EMU, reg: Register, value: u16) -> Result<(), PokeError> {
+        emu.poke().set(reg, value)?;
+        self.restart_journal();
+        Ok(())
+    }
+
+    pub fn write_memory(&mut self, emu: &mut EMU, addr: u16, bytes: &[u8]) -> Result<(), PokeError> {
+        emu.poke().write_ram(addr, bytes)?;
+        self.restart_journal();
+        Ok(())
+    }
+
+    fn restart_journal(&mut self) {
+        if let Some(journal) = &mut self.journal {
+            *journal = Journal::new(journal.limit());
+        }
+    }
+}
+
+impl EMU {
+    // The next opcode, or why running it would panic
+    fn fault(&self) -> Result<u16, &'static str> {
+        let pc = self.program_counter as usize;
+        if pc + 1 >= RAM_SIZE {
+            return Err("PC is past the end of RAM");
+        }
+        let op = self.state().opcode(self.program_counter);
+        let sys = op & 0xF000 == 0 && !matches!(op, 0x0000 | 0x00E0 | 0x00EE);
+        if sys || disassemble(op).is_none() {
+            return Err("unknown instruction");
+        }
+        match op & 0xF0FF {
+            0x00EE if self.sp == 0 => return Err("return with an empty call stack"),
+            0xE09E if self.v_reg[(op >> 8 & 0xF) as usize] as usize >= NUM_KEYS => return Err("key number above F"),
+            // EXA1 reads the key number from VY, see execute()
+            0xE0A1 if self.v_reg[(op >> 4 & 0xF) as usize] as usize >= NUM_KEYS => return Err("key number above F"),
+            _ => (),
+        }
+        if op & 0xF000 == 0x2000 && self.sp as usize >= STACK_SIZE {
+            return Err("call stack overflow");
+        }
+        let access = self.memory_access(op);
+        if access.read.end > RAM_SIZE || access.write.end > RAM_SIZE {
+            return Err("memory access past the end of RAM");
+        }
+        Ok(op)
+    }
+
+    // What `op` would read and write if it ran now, the way execute() does it
+    pub(crate) fn memory_access(&self, op: u16) -> MemoryAccess {
+        let i = self.i_reg as usize;
+        let x = (op >> 8 & 0xF) as usize;
+        let at = |len: usize| i..i + len;
+        match (op >> 12, op & 0xFF) {
+            (0xD, _) => MemoryAccess { read: at((op & 0xF) as usize), ..Default::default() },
+            (0xF, 0x33) => MemoryAccess { write: at(3), ..Default::default() },
+            // V0 to VX-1, see execute()
+            (0xF, 0x55) => MemoryAccess { write: at(x), ..Default::default() },
+            (0xF, 0x65) => MemoryAccess { read: at(x), ..Default::default() },
+            _ => MemoryAccess::default(),
+        }
+    }
+}
+
+impl FromStr for WatchKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "r" => Ok(WatchKind::Read),
+            "w" => Ok(WatchKind::Write),
+            "rw" => Ok(WatchKind::Access),
+            _ => Err(format!("Unknown watch kind '{}' (expected r, w or rw)", s)),
+        }
+    }
+}
+
+impl fmt::Display for WatchKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            WatchKind::Read => "r",
+            WatchKind::Write => "w",
+            WatchKind::Access => "rw",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl fmt::Display for Stop {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Stop::Done => write!(f, "done"),
+            Stop::Breakpoint(pc) => write!(f, "breakpoint at {:#05X}", pc),
+            Stop::Watchpoint { index, pc, addr, write } => {
+                let what = if *write {"written"} else {"read"};
+                write!(f, "watchpoint {}: {:#05X} {} by the instruction at {:#05X}", index, addr, what, pc)
+            },
+            Stop::Fault { pc, op, reason } => write!(f, "{} at {:#05X} ({:04X})", reason, pc, op),
+            Stop::JournalStart => write!(f, "reached the start of the journal"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn machine(ops: &[u16]) -> EMU {
+        let mut emu = EMU::new();
+        emu.load(&ops.iter().flat_map(|op| op.to_be_bytes()).collect::<Vec<_>>());
+        emu
+    }
+
+    #[test]
+    fn breakpoints_stop_before_the_instruction() {
+        let mut emu = machine(&[0x6001, 0x6102, 0x6203, 0x1206]);
+        let mut debugger = Debugger::new(10);
+        debugger.add_breakpoint(Breakpoint::at(0x204));
+        assert_eq!(debugger.run(&mut emu, 100), Stop::Breakpoint(0x204));
+        assert_eq!(emu.state().v[1], 2);
+        assert_eq!(emu.state().v[2], 0);
+        // continuing from the breakpoint runs it
+        assert_eq!(debugger.run(&mut emu, 2), Stop::Done);
+        assert_eq!(emu.state().v[2], 3);
+    }
+
+    #[test]
+    fn watchpoints_stop_after_the_access() {
+        // I = 0x300, V0 = 42, store BCD of V0 at I
+        let mut emu = machine(&[0xA300, 0x602A, 0xF033, 0x1206]);
+        let mut debugger = Debugger::new(10);
+        let index = debugger.add_watchpoint(Watchpoint::new(WatchKind::Write, 0x301, 0x310));
+        assert_eq!(debugger.run(&mut emu, 100), Stop::Watchpoint { index, pc: 0x204, addr: 0x301, write: true });
+        assert_eq!(emu.state().ram[0x301], 4);
+    }
+
+    #[test]
+    fn conditions_and_logging() {
+        // V0 counts up in a loop
+        let mut emu = machine(&[0x7001, 0x1200]);
+        let mut debugger = Debugger::new(10);
+        debugger.add_breakpoint(Breakpoint { when: Some(Expr::parse("v0 == 3").unwrap()), ..Breakpoint::at(0x202) });
+        assert_eq!(debugger.run(&mut emu, 100), Stop::Breakpoint(0x202));
+        assert_eq!(emu.state().v[0], 3);
+
+        let mut debugger = Debugger::new(10);
+        let log = Message::parse("v0={v0}").unwrap();
+        debugger.add_breakpoint(Breakpoint {
+            when: Some(Expr::parse("hits % 2 == 0").unwrap()),
+            log: Some(log),
+            ..Breakpoint::at(0x202)
+        });
+        assert_eq!(debugger.run(&mut emu, 8), Stop::Done);
+        assert_eq!(debugger.take_log(), ["0x202: v0=4", "0x202: v0=6"]);
+        assert_eq!(debugger.breakpoints().next().unwrap().hits, 4);
+    }
+
+    #[test]
+    fn reverse_stepping() {
+        // V0 = random, store BCD of V0 at 0x300, call a routine that draws and returns
+        let mut emu = machine(&[0xC0FF, 0xA300, 0xF033, 0x220A, 0x1208, 0xA300, 0xD001, 0x00EE]);
+        emu.seed(1);
+        let mut debugger = Debugger::new(10);
+        debugger.set_journal(100);
+        debugger.add_breakpoint(Breakpoint::at(0x206));
+        assert_eq!(debugger.run(&mut emu, 100), Stop::Breakpoint(0x206));
+        let before = emu.save_state();
+        debugger.run(&mut emu, 20);
+        assert_ne!(emu.save_state(), before);
+
+        assert_eq!(debugger.reverse_continue(&mut emu), Stop::Breakpoint(0x206));
+        assert_eq!(emu.save_state(), before);
+        assert_eq!(debugger.instructions(), 3);
+        assert_eq!(debugger.last_write(0x302), Some((0x204, 2)));
+
+        // the random number comes out the same again
+        let v0 = emu.state().v[0];
+        assert_eq!(debugger.reverse_step(&mut emu, 5), Stop::JournalStart);
+        debugger.run(&mut emu, 1);
+        assert_eq!(emu.state().v[0], v0);
+    }
+
+    #[test]
+    fn reverse_stepping_takes_back_hits() {
+        // V0 counts up and is stored at 0x300 every time round
+        let mut emu = machine(&[0x7001, 0xA300, 0xF155, 0x1200]);
+        let mut debugger = Debugger::new(10);
+        debugger.set_journal(100);
+        debugger.add_breakpoint(Breakpoint { when: Some(Expr::parse("hits == 3").unwrap()), ..Breakpoint::at(0x200) });
+        debugger.add_watchpoint(Watchpoint {
+            log: Some(Message::parse("{v0}").unwrap()),
+            ..Watchpoint::new(WatchKind::Write, 0x300, 0x300)
+        });
+        let hits = |debugger: &Debugger| (debugger.breakpoints().next().unwrap().hits, debugger.watchpoints().next().unwrap().1.hits);
+        assert_eq!(debugger.run(&mut emu, 100), Stop::Breakpoint(0x200));
+        assert_eq!(hits(&debugger), (3, 2));
+        let before = emu.save_state();
+
+        debugger.run(&mut emu, 8);
+        assert_eq!(hits(&debugger), (4, 4));
+        assert_eq!(debugger.reverse_continue(&mut emu), Stop::Breakpoint(0x200));
+        assert_eq!(emu.save_state(), before);
+        assert_eq!(hits(&debugger), (3, 2));
+
+        // the hit counted when it stopped goes back too, and comes again going forwards
+        assert_eq!(debugger.reverse_step(&mut emu, 1), Stop::Done);
+        assert_eq!(hits(&debugger), (2, 2));
+        assert_eq!(debugger.run(&mut emu, 100), Stop::Breakpoint(0x200));
+        assert_eq!(hits(&debugger), (3, 2));
+        assert_eq!(emu.save_state(), before);
+        assert_eq!(debugger.take_log(), ["0x204: 1", "0x204: 2", "0x204: 3", "0x204: 4"]);
+    }
+
+    #[test]
+    fn faults_are_caught() {
+        let mut emu = machine(&[0x00EE]);
+        let mut debugger = Debugger::new(10);
+        assert!(matches!(debugger.run(&mut emu, 1), Stop::Fault { pc: 0x200, op: 0x00EE, .. }));
+        let mut emu = machine(&[0x0123]);
+        assert!(matches!(debugger.run(&mut emu, 1), Stop::Fault { reason: "unknown instruction", .. }));
+    }
+
+    #[test]
+    fn timers_tick_every_frame() {
+        let mut emu = machine(&[0x603C, 0xF015, 0x1204]);
+        let mut debugger = Debugger::new(10);
+        debugger.run(&mut emu, 2 + 30);
+        assert_eq!(emu.state().dt, 60 - 3);
+    }
+}
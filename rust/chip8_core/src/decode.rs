@@ -0,0 +1,255 @@
+// Predecoded instructions. tick() looks the opcode at PC up here instead of fetching two
+// bytes and splitting them into nibbles every time, which adds up in tight loops. There's an
+// entry per address, odd ones too since nothing stops a program from jumping to one.
+//
+// Anything that writes RAM has to call invalidate() for the bytes it wrote, programs that
+// patch their own code (FX33 and FX55 over an instruction) would run the old one otherwise.
+// With the `jit` feature the writes are also collected for the recompiler, see jit.rs.
+
+use crate::RAM_SIZE;
+use std::ops::Range;
+
+// Which instruction an opcode is, one per arm of EMU::execute()
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Kind {
+    Nop,
+    Cls,
+    Ret,
+    Jump,
+    Call,
+    SkipEq,
+    SkipNe,
+    SkipEqReg,
+    Load,
+    Add,
+    Move,
+    Or,
+    And,
+    Xor,
+    AddReg,
+    Sub,
+    Shr,
+    SubN,
+    Shl,
+    SkipNeReg,
+    LoadI,
+    JumpV0,
+    Rand,
+    Draw,
+    SkipKey,
+    SkipNotKey,
+    GetDelay,
+    WaitKey,
+    SetDelay,
+    SetSound,
+    AddI,
+    Font,
+    Bcd,
+    Store,
+    Restore,
+    Unknown,
+}
+
+// An opcode split up ahead of time, the fields an instruction doesn't use are just whatever
+// the nibbles were
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Decoded {
+    pub kind: Kind,
+    pub x: u8,
+    pub y: u8,
+    pub n: u8,
+    pub nn: u8,
+    pub nnn: u16,
+    pub op: u16,
+}
+
+impl Decoded {
+    pub fn new(op: u16) -> Self {
+        let digit1 = (op & 0xF000) >> 12;
+        let digit2 = (op & 0x0F00) >> 8;
+        let digit3 = (op & 0x00F0) >> 4;
+        let digit4 = op & 0x000F;
+        let kind = match (digit1, digit2, digit3, digit4) {
+            (0, 0, 0, 0) => Kind::Nop,
+            (0, 0, 0xE, 0) => Kind::Cls,
+            (0, 0, 0xE, 0xE) => Kind::Ret,
+            (1, _, _, _) => Kind::Jump,
+            (2, _, _, _) => Kind::Call,
+            (3, _, _, _) => Kind::SkipEq,
+            (4, _, _, _) => Kind::SkipNe,
+            (5, _, _, 0) => Kind::SkipEqReg,
+            (6, _, _, _) => Kind::Load,
+            (7, _, _, _) => Kind::Add,
+            (8, _, _, 0) => Kind::Move,
+            (8, _, _, 1) => Kind::Or,
+            (8, _, _, 2) => Kind::And,
+            (8, _, _, 3) => Kind::Xor,
+            (8, _, _, 4) => Kind::AddReg,
+            (8, _, _, 5) => Kind::Sub,
+            (8, _, _, 6) => Kind::Shr,
+            (8, _, _, 7) => Kind::SubN,
+            (8, _, _, 0xE) => Kind::Shl,
+            (9, _, _, 0) => Kind::SkipNeReg,
+            (0xA, _, _, _) => Kind::LoadI,
+            (0xB, _, _, _) => Kind::JumpV0,
+            (0xC, _, _, _) => Kind::Rand,
+            (0xD, _, _, _) => Kind::Draw,
+            (0xE, _, 9, 0xE) => Kind::SkipKey,
+            (0xE, _, 0xA, 1) => Kind::SkipNotKey,
+            (0xF, _, 0, 7) => Kind::GetDelay,
+            (0xF, _, 0, 0xA) => Kind::WaitKey,
+            (0xF, _, 1, 5) => Kind::SetDelay,
+            (0xF, _, 1, 8) => Kind::SetSound,
+            (0xF, _, 1, 0xE) => Kind::AddI,
+            (0xF, _, 2, 9) => Kind::Font,
+            (0xF, _, 3, 3) => Kind::Bcd,
+            (0xF, _, 5, 5) => Kind::Store,
+            (0xF, _, 6, 5) => Kind::Restore,
+            _ => Kind::Unknown,
+        };
+        Decoded {
+            kind,
+            x: digit2 as u8,
+            y: digit3 as u8,
+            n: digit4 as u8,
+            nn: (op & 0xFF) as u8,
+            nnn: op & 0xFFF,
+            op,
+        }
+    }
+}
+
+pub(crate) struct DecodeCache {
+    entries: Vec<Option<Decoded>>, // by address, None until it's run the first time
+    #[cfg(feature = "jit")]
+    written: Option<Range<usize>>, // everything written since the recompiler last asked
+}
+
+impl DecodeCache {
+    pub fn new() -> Self {
+        DecodeCache {
+            entries: vec![None; RAM_SIZE],
+            #[cfg(feature = "jit")]
+            written: None,
+        }
+    }
+
+    // The instruction at `pc`, decoding it if it hasn't been yet
+    #[inline]
+    pub fn get(&mut self, ram: &[u8; RAM_SIZE], pc: u16) -> Decoded {
+        let pc = pc as usize;
+        match self.entries[pc] {
+            Some(decoded) => decoded,
+            None => {
+                let decoded = Decoded::new((ram[pc] as u16) << 8 | ram[pc + 1] as u16);
+                self.entries[pc] = Some(decoded);
+                decoded
+            },
+        }
+    }
+
+    // Forgets the instructions that overlap the bytes in `written`, which includes the one
+    // starting a byte before
+    pub fn invalidate(&mut self, written: Range<usize>) {
+        let start = written.start.saturating_sub(1).min(RAM_SIZE);
+        let end = written.end.min(RAM_SIZE);
+        if start < end {
+            self.entries[start..end].fill(None);
+        }
+        #[cfg(feature = "jit")]
+        if !written.is_empty() {
+            self.written = Some(match self.written.take() {
+                Some(before) => before.start.min(written.start)..before.end.max(written.end),
+                None => written,
+            });
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.fill(None);
+        #[cfg(feature = "jit")]
+        {
+            self.written = Some(0..RAM_SIZE);
+        }
+    }
+
+    // The bytes written since the last call, all of them in one range
+    #[cfg(feature = "jit")]
+    pub fn take_written(&mut self) -> Option<Range<usize>> {
+        self.written.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EMU;
+
+    #[test]
+    fn decodes_fields() {
+        let decoded = Decoded::new(0xD12F);
+        assert_eq!(decoded.kind, Kind::Draw);
+        assert_eq!((decoded.x, decoded.y, decoded.n, decoded.nn, decoded.nnn), (1, 2, 0xF, 0x2F, 0x12F));
+        assert_eq!(Decoded::new(0x00EE).kind, Kind::Ret);
+        assert_eq!(Decoded::new(0x0123).kind, Kind::Unknown);
+        assert_eq!(Decoded::new(0xF155).kind, Kind::Store);
+    }
+
+    #[test]
+    fn invalidates_overlapping_entries() {
+        let mut ram = [0; RAM_SIZE];
+        ram[0x200..0x204].copy_from_slice(&[0x60, 0x01, 0x61, 0x02]);
+        let mut cache = DecodeCache::new();
+        cache.get(&ram, 0x200);
+        cache.get(&ram, 0x201);
+        cache.get(&ram, 0x202);
+        ram[0x202] = 0x62;
+        cache.invalidate(0x202..0x203);
+        assert_eq!(cache.entries[0x200].map(|d| d.op), Some(0x6001));
+        assert_eq!(cache.entries[0x201], None);
+        assert_eq!(cache.get(&ram, 0x202).op, 0x6202);
+    }
+
+    #[test]
+    fn self_modifying_code() {
+        let rom = [
+            0x7B, 0x01, // 200: ADD VB, 1, becomes ADD VB, 5
+            0xA2, 0x00, // 202: LD I, 200
+            0x60, 0x7B, // 204: LD V0, #7B
+            0x61, 0x05, // 206: LD V1, #05
+            0xF2, 0x55, // 208: LD [I], V2, stores V0 and V1
+            0x12, 0x00, // 20A: JP 200
+        ];
+        let mut emu = EMU::new();
+        emu.load(&rom);
+        for _ in 0..7 {
+            emu.tick();
+        }
+        assert_eq!(emu.state().v[0xB], 6);
+
+        let rom = [
+            0xA2, 0x05, // 200: LD I, 205
+            0x71, 0x01, // 202: ADD V1, 1
+            0x31, 0xFF, // 204: SE V1, #FF
+            0x72, 0x01, // 206: ADD V2, 1, becomes a NOP
+            0xF0, 0x33, // 208: LD B, V0, writes 00 00 00 at 205
+            0x12, 0x02, // 20A: JP 202
+        ];
+        let mut emu = EMU::new();
+        emu.load(&rom);
+        for _ in 0..9 {
+            emu.tick();
+        }
+        assert_eq!(emu.state().opcode(0x206), 0x0000);
+        assert_eq!((emu.state().v[1], emu.state().v[2]), (2, 1));
+
+        // and from outside
+        let mut emu = EMU::new();
+        emu.load(&[0x7B, 0x01, 0x12, 0x00]);
+        emu.tick();
+        emu.poke().write_ram(0x201, &[0x10]).unwrap();
+        emu.tick();
+        emu.tick();
+        assert_eq!(emu.state().v[0xB], 0x11);
+    }
+}
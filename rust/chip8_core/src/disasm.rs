@@ -0,0 +1,131 @@
+// Disassembler, with the mnemonics from Cowgod's technical reference
+// (http://devernay.free.fr/hacks/chip8/C8TECH10.HTM#3.1), the same one the interpreter follows
+
+use crate::cfg::{self, Cfg};
+use crate::symbols::Symbols;
+use crate::START_ADDR;
+
+// The instruction as text, or None for opcodes the interpreter doesn't know
+pub fn disassemble(op: u16) -> Option<String> {
+    let x = op >> 8 & 0xF;
+    let y = op >> 4 & 0xF;
+    let n = op & 0xF;
+    let nn = op & 0xFF;
+    let nnn = op & 0xFFF;
+
+    let text = match (op >> 12, x, y, n) {
+        (0, 0, 0, 0) => "NOP".to_string(),
+        (0, 0, 0xE, 0) => "CLS".to_string(),
+        (0, 0, 0xE, 0xE) => "RET".to_string(),
+        (0, _, _, _) => format!("SYS #{:03X}", nnn),
+        (1, _, _, _) => format!("JP #{:03X}", nnn),
+        (2, _, _, _) => format!("CALL #{:03X}", nnn),
+        (3, _, _, _) => format!("SE V{:X}, #{:02X}", x, nn),
+        (4, _, _, _) => format!("SNE V{:X}, #{:02X}", x, nn),
+        (5, _, _, 0) => format!("SE V{:X}, V{:X}", x, y),
+        (6, _, _, _) => format!("LD V{:X}, #{:02X}", x, nn),
+        (7, _, _, _) => format!("ADD V{:X}, #{:02X}", x, nn),
+        (8, _, _, 0) => format!("LD V{:X}, V{:X}", x, y),
+        (8, _, _, 1) => format!("OR V{:X}, V{:X}", x, y),
+        (8, _, _, 2) => format!("AND V{:X}, V{:X}", x, y),
+        (8, _, _, 3) => format!("XOR V{:X}, V{:X}", x, y),
+        (8, _, _, 4) => format!("ADD V{:X}, V{:X}", x, y),
+        (8, _, _, 5) => format!("SUB V{:X}, V{:X}", x, y),
+        (8, _, _, 6) => format!("SHR V{:X}, V{:X}", x, y),
+        (8, _, _, 7) => format!("SUBN V{:X}, V{:X}", x, y),
+        (8, _, _, 0xE) => format!("SHL V{:X}, V{:X}", x, y),
+        (9, _, _, 0) => format!("SNE V{:X}, V{:X}", x, y),
+        (0xA, _, _, _) => format!("LD I, #{:03X}", nnn),
+        (0xB, _, _, _) => format!("JP V0, #{:03X}", nnn),
+        (0xC, _, _, _) => format!("RND V{:X}, #{:02X}", x, nn),
+        (0xD, _, _, _) => format!("DRW V{:X}, V{:X}, {}", x, y, n),
+        (0xE, _, 9, 0xE) => format!("SKP V{:X}", x),
+        (0xE, _, 0xA, 1) => format!("SKNP V{:X}", x),
+        (0xF, _, 0, 7) => format!("LD V{:X}, DT", x),
+        (0xF, _, 0, 0xA) => format!("LD V{:X}, K", x),
+        (0xF, _, 1, 5) => format!("LD DT, V{:X}", x),
+        (0xF, _, 1, 8) => format!("LD ST, V{:X}", x),
+        (0xF, _, 1, 0xE) => format!("ADD I, V{:X}", x),
+        (0xF, _, 2, 9) => format!("LD F, V{:X}", x),
+        (0xF, _, 3, 3) => format!("LD B, V{:X}", x),
+        (0xF, _, 5, 5) => format!("LD [I], V{:X}", x),
+        (0xF, _, 6, 5) => format!("LD V{:X}, [I]", x),
+        _ => return None,
+    };
+    Some(text)
+}
+
+// One listing line per instruction from `addr` on, "0x200  6A02  LD VA, #02"
+pub fn listing(ram: &[u8], addr: u16, count: usize) -> Vec<String> {
+    let mut lines = Vec::with_capacity(count);
+    let mut addr = addr as usize;
+    while lines.len() < count && addr + 1 < ram.len() {
+        let op = (ram[addr] as u16) << 8 | ram[addr + 1] as u16;
+        lines.push(line(addr as u16, op));
+        addr += 2;
+    }
+    lines
+}
+
+// A single listing line
+pub(crate) fn line(addr: u16, op: u16) -> String {
+    let text = disassemble(op).unwrap_or_else(|| format!("DW #{:04X}", op));
+    format!("0x{:03X}  {:04X}  {}", addr, op, text)
+}
+
+// A whole ROM: the code the control flow graph reaches, with a label wherever it's called or
+// jumped to, and everything else as data bytes
+pub fn program(rom: &[u8], symbols: &Symbols) -> Vec<String> {
+    let cfg = Cfg::new(rom);
+    let ram = cfg::ram(rom);
+    let targets = cfg.jump_targets();
+    let end = ram.len();
+    let mut lines = Vec::new();
+    let mut addr = START_ADDR as usize;
+    while addr < end {
+        let at = addr as u16;
+        if cfg.contains(at) {
+            if cfg.is_subroutine(at) && !lines.is_empty() {
+                lines.push(String::new());
+            }
+            if cfg.is_subroutine(at) || targets.contains(&at) || symbols.get(at).is_some() {
+                lines.push(format!("{}:", cfg.name(at, symbols)));
+            }
+            lines.extend(listing(&ram, at, 1));
+            addr += 2;
+            continue;
+        }
+        if let Some(name) = symbols.get(at) {
+            lines.push(format!("{}:", name));
+        }
+        // up to 8 bytes a line, stopping at the next instruction or label
+        let stop = (addr + 1..end.min(addr + 8))
+            .find(|&a| cfg.contains(a as u16) || symbols.get(a as u16).is_some())
+            .unwrap_or(end.min(addr + 8));
+        let bytes: Vec<String> = ram[addr..stop].iter().map(|b| format!("#{:02X}", b)).collect();
+        lines.push(format!("0x{:03X}  DB {}", addr, bytes.join(", ")));
+        addr = stop;
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn program_listing() {
+        let rom = [0x22, 0x06, 0x12, 0x02, 0xF0, 0x80, 0x00, 0xEE];
+        let lines = program(&rom, &Symbols::parse("0x206 done").unwrap());
+        assert_eq!(lines, vec![
+            "main:",
+            "0x200  2206  CALL #206",
+            "L_202:",
+            "0x202  1202  JP #202",
+            "0x204  DB #F0, #80",
+            "",
+            "done:",
+            "0x206  00EE  RET",
+        ]);
+    }
+}
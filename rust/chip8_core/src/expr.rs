@@ -0,0 +1,378 @@
+// Small expression language for breakpoint conditions and log messages, like
+// `v0 == 3 && mem[i] > 0x40` or `hits % 100 == 0`.
+//
+//   numbers      decimal, or hex with 0x, also in mem[], unlike addresses typed on their own
+//   v0-vf, i, pc, sp, dt, st
+//   mem[ADDR]    a byte of RAM, 0 outside of it
+//   key[N]       1 if key N is down
+//   hits         how often the breakpoint was reached, this time included
+//   operators    || && | ^ & == != < <= > >= << >> + - * / % and unary ! - ~, C precedence
+//
+// Everything is a signed 64 bit number, comparisons give 0 or 1 and dividing by 0 gives 0.
+
+use crate::{EMU, NUM_KEYS};
+use std::fmt;
+
+#[derive(Clone, Debug)]
+pub struct Expr {
+    node: Node,
+    source: String,
+}
+
+#[derive(Clone, Debug)]
+enum Node {
+    Num(i64),
+    Var(Var),
+    Mem(Box<Node>),
+    Key(Box<Node>),
+    Unary(char, Box<Node>),
+    Binary(&'static str, Box<Node>, Box<Node>),
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Var {
+    V(usize),
+    I,
+    Pc,
+    Sp,
+    Dt,
+    St,
+    Hits,
+}
+
+// Lowest first, C's order
+const BINARY: [&[&str]; 10] = [
+    &["||"],
+    &["&&"],
+    &["|"],
+    &["^"],
+    &["&"],
+    &["==", "!="],
+    &["<=", ">=", "<", ">"],
+    &["<<", ">>"],
+    &["+", "-"],
+    &["*", "/", "%"],
+];
+
+impl Expr {
+    pub fn parse(text: &str) -> Result<Expr, String> {
+        let tokens = tokenize(text)?;
+        let mut parser = Parser { tokens, pos: 0 };
+        let node = parser.binary(0)?;
+        if let Some(token) = parser.tokens.get(parser.pos) {
+            return Err(format!("unexpected '{}' in '{}'", token, text));
+        }
+        Ok(Expr { node, source: text.trim().to_string() })
+    }
+
+    pub fn eval(&self, emu: &EMU, hits: u64) -> i64 {
+        eval(&self.node, emu, hits)
+    }
+
+    pub fn is_true(&self, emu: &EMU, hits: u64) -> bool {
+        self.eval(emu, hits) != 0
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+fn eval(node: &Node, emu: &EMU, hits: u64) -> i64 {
+    let e = |node: &Node| eval(node, emu, hits);
+    match node {
+        Node::Num(n) => *n,
+        Node::Var(var) => match var {
+            Var::V(x) => emu.v_reg[*x] as i64,
+            Var::I => emu.i_reg as i64,
+            Var::Pc => emu.program_counter as i64,
+            Var::Sp => emu.sp as i64,
+            Var::Dt => emu.dt as i64,
+            Var::St => emu.st as i64,
+            Var::Hits => hits as i64,
+        },
+        Node::Mem(addr) => usize::try_from(e(addr)).ok().and_then(|a| emu.ram.get(a)).map_or(0, |&b| b as i64),
+        Node::Key(key) => usize::try_from(e(key)).ok().filter(|&k| k < NUM_KEYS).is_some_and(|k| emu.keys[k]) as i64,
+        Node::Unary(op, a) => match op {
+            '!' => (e(a) == 0) as i64,
+            '-' => e(a).wrapping_neg(),
+            _ => !e(a),
+        },
+        // short circuit, so `sp > 0 && mem[...]` style guards work
+        Node::Binary("&&", a, b) => (e(a) != 0 && e(b) != 0) as i64,
+        Node::Binary("||", a, b) => (e(a) != 0 || e(b) != 0) as i64,
+        Node::Binary(op, a, b) => {
+            let (a, b) = (e(a), e(b));
+            match *op {
+                "|" => a | b,
+                "^" => a ^ b,
+                "&" => a & b,
+                "==" => (a == b) as i64,
+                "!=" => (a != b) as i64,
+                "<" => (a < b) as i64,
+                "<=" => (a <= b) as i64,
+                ">" => (a > b) as i64,
+                ">=" => (a >= b) as i64,
+                "<<" => a.wrapping_shl(b as u32),
+                ">>" => a.wrapping_shr(b as u32),
+                "+" => a.wrapping_add(b),
+                "-" => a.wrapping_sub(b),
+                "*" => a.wrapping_mul(b),
+                "/" => a.checked_div(b).unwrap_or(0),
+                _ => a.checked_rem(b).unwrap_or(0),
+            }
+        },
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Num(i64),
+    Name(String),
+    Op(&'static str),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Num(n) => write!(f, "{}", n),
+            Token::Name(name) => write!(f, "{}", name),
+            Token::Op(op) => write!(f, "{}", op),
+        }
+    }
+}
+
+// longest first so "<=" isn't read as "<"
+const OPERATORS: [&str; 24] = [
+    "||", "&&", "==", "!=", "<=", ">=", "<<", ">>",
+    "|", "^", "&", "<", ">", "+", "-", "*", "/", "%", "!", "~", "(", ")", "[", "]",
+];
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = text.trim_start();
+    while let Some(c) = rest.chars().next() {
+        if c.is_ascii_digit() {
+            let end = rest.find(|c: char| !c.is_ascii_alphanumeric()).unwrap_or(rest.len());
+            let word = &rest[..end];
+            let value = match word.strip_prefix("0x").or_else(|| word.strip_prefix("0X")) {
+                Some(hex) => i64::from_str_radix(hex, 16),
+                None => word.parse(),
+            };
+            tokens.push(Token::Num(value.map_err(|_| format!("'{}' is not a number", word))?));
+            rest = &rest[end..];
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let end = rest.find(|c: char| !c.is_ascii_alphanumeric() && c != '_').unwrap_or(rest.len());
+            tokens.push(Token::Name(rest[..end].to_ascii_lowercase()));
+            rest = &rest[end..];
+        } else if let Some(op) = OPERATORS.iter().find(|op| rest.starts_with(*op)) {
+            tokens.push(Token::Op(op));
+            rest = &rest[op.len()..];
+        } else {
+            return Err(format!("unexpected '{}' in '{}'", c, text));
+        }
+        rest = rest.trim_start();
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn expect(&mut self, op: &str) -> Result<(), String> {
+        match self.next() {
+            Some(Token::Op(found)) if found == op => Ok(()),
+            Some(token) => Err(format!("expected '{}' but found '{}'", op, token)),
+            None => Err(format!("expected '{}' at the end", op)),
+        }
+    }
+
+    // Operators of precedence `level` and up
+    fn binary(&mut self, level: usize) -> Result<Node, String> {
+        if level == BINARY.len() {
+            return self.unary();
+        }
+        let mut node = self.binary(level + 1)?;
+        while let Some(Token::Op(op)) = self.tokens.get(self.pos)
+            && BINARY[level].contains(op)
+        {
+            let op = *op;
+            self.pos += 1;
+            let right = self.binary(level + 1)?;
+            node = Node::Binary(op, Box::new(node), Box::new(right));
+        }
+        Ok(node)
+    }
+
+    fn unary(&mut self) -> Result<Node, String> {
+        match self.next() {
+            Some(Token::Op(op @ ("!" | "-" | "~"))) => {
+                let operand = self.unary()?;
+                Ok(Node::Unary(op.chars().next().unwrap_or('!'), Box::new(operand)))
+            },
+            Some(Token::Op("(")) => {
+                let node = self.binary(0)?;
+                self.expect(")")?;
+                Ok(node)
+            },
+            Some(Token::Num(n)) => Ok(Node::Num(n)),
+            Some(Token::Name(name)) => self.name(&name),
+            Some(token) => Err(format!("unexpected '{}'", token)),
+            None => Err("the expression ends too early".to_string()),
+        }
+    }
+
+    fn name(&mut self, name: &str) -> Result<Node, String> {
+        let var = match name {
+            "mem" | "key" => {
+                self.expect("[")?;
+                let index = Box::new(self.binary(0)?);
+                self.expect("]")?;
+                return Ok(if name == "mem" {Node::Mem(index)} else {Node::Key(index)});
+            },
+            "i" => Var::I,
+            "pc" => Var::Pc,
+            "sp" => Var::Sp,
+            "dt" => Var::Dt,
+            "st" => Var::St,
+            "hits" => Var::Hits,
+            _ => match name.strip_prefix('v').filter(|x| x.len() == 1).and_then(|x| usize::from_str_radix(x, 16).ok()) {
+                Some(x) => Var::V(x),
+                None => return Err(format!("unknown name '{}'", name)),
+            },
+        };
+        Ok(Node::Var(var))
+    }
+}
+
+// Text with {EXPR} values in it, {EXPR:x} for hex and {{ }} for braces:
+// "v0={v0} at {pc:x}"
+#[derive(Clone, Debug)]
+pub struct Message {
+    parts: Vec<Part>,
+    source: String,
+}
+
+#[derive(Clone, Debug)]
+enum Part {
+    Text(String),
+    Value(Expr, bool), // hex
+}
+
+impl Message {
+    pub fn parse(text: &str) -> Result<Message, String> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut rest = text;
+        while let Some(at) = rest.find(['{', '}']) {
+            literal += &rest[..at];
+            let brace = &rest[at..at + 1];
+            rest = &rest[at + 1..];
+            if rest.starts_with(brace) {
+                literal += brace;
+                rest = &rest[1..];
+                continue;
+            }
+            if brace == "}" {
+                return Err(format!("unmatched '}}' in \"{}\"", text));
+            }
+            let end = rest.find('}').ok_or_else(|| format!("unmatched '{{' in \"{}\"", text))?;
+            let (expr, hex) = match rest[..end].strip_suffix(":x") {
+                Some(expr) => (expr, true),
+                None => (&rest[..end], false),
+            };
+            parts.push(Part::Text(std::mem::take(&mut literal)));
+            parts.push(Part::Value(Expr::parse(expr)?, hex));
+            rest = &rest[end + 1..];
+        }
+        literal += rest;
+        parts.push(Part::Text(literal));
+        Ok(Message { parts, source: text.to_string() })
+    }
+
+    pub fn format(&self, emu: &EMU, hits: u64) -> String {
+        self.parts
+            .iter()
+            .map(|part| match part {
+                Part::Text(text) => text.clone(),
+                Part::Value(expr, true) => format!("{:#X}", expr.eval(emu, hits)),
+                Part::Value(expr, false) => expr.eval(emu, hits).to_string(),
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for Message {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn machine() -> EMU {
+        let mut emu = EMU::new();
+        emu.v_reg[0] = 3;
+        emu.v_reg[0xA] = 0x10;
+        emu.i_reg = 0x300;
+        emu.ram[0x300] = 0x41;
+        emu.keys[5] = true;
+        emu
+    }
+
+    fn eval(text: &str) -> i64 {
+        Expr::parse(text).unwrap().eval(&machine(), 7)
+    }
+
+    #[test]
+    fn values() {
+        assert_eq!(eval("v0"), 3);
+        assert_eq!(eval("VA"), 0x10);
+        assert_eq!(eval("i"), 0x300);
+        assert_eq!(eval("mem[i]"), 0x41);
+        assert_eq!(eval("mem[0x10000]"), 0);
+        assert_eq!(eval("key[5] + key[6]"), 1);
+        assert_eq!(eval("hits"), 7);
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(eval("1 + 2 * 3"), 7);
+        assert_eq!(eval("(1 + 2) * 3"), 9);
+        assert_eq!(eval("v0 == 3 && mem[i] > 0x40"), 1);
+        assert_eq!(eval("v0 == 2 || !key[5]"), 0);
+        assert_eq!(eval("1 << 4 | 1"), 17);
+        assert_eq!(eval("-v0 + ~0"), -4);
+        assert_eq!(eval("hits % 0"), 0);
+    }
+
+    #[test]
+    fn errors() {
+        assert!(Expr::parse("v0 ==").is_err());
+        assert!(Expr::parse("vg").is_err());
+        assert!(Expr::parse("mem[1").is_err());
+        assert!(Expr::parse("1 2").is_err());
+        assert!(Expr::parse("v0 $ 1").is_err());
+    }
+
+    #[test]
+    fn messages() {
+        let message = Message::parse("v0={v0} at {i:x} {{ok}}").unwrap();
+        assert_eq!(message.format(&machine(), 0), "v0=3 at 0x300 {ok}");
+        assert!(Message::parse("{v0").is_err());
+        assert!(Message::parse("v0}").is_err());
+    }
+}
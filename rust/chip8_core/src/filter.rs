@@ -0,0 +1,155 @@
+// Flicker reduction
+//
+// Games erase and redraw sprites with XOR, so a moving sprite is missing from every
+// other frame. These filters post-process the screen once per frame into a brightness
+// per pixel (0 = off, 255 = fully lit) that frontends blend between their two colors.
+
+use crate::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use std::fmt;
+use std::str::FromStr;
+
+const NUM_PIXELS: usize = SCREEN_WIDTH * SCREEN_HEIGHT;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FlickerFilter {
+    Off,
+    Persistence(u8), // pixels fade out over this many frames after going dark, like phosphor
+    Merge,           // a pixel is lit if it was lit in this frame or the one before
+}
+
+pub struct DisplayFilter {
+    mode: FlickerFilter,
+    levels: [u8; NUM_PIXELS],
+    previous: [bool; NUM_PIXELS],
+}
+
+impl DisplayFilter {
+    pub fn new(mode: FlickerFilter) -> Self {
+        DisplayFilter {
+            mode,
+            levels: [0; NUM_PIXELS],
+            previous: [false; NUM_PIXELS],
+        }
+    }
+
+    pub fn mode(&self) -> FlickerFilter {
+        self.mode
+    }
+
+    pub fn set_mode(&mut self, mode: FlickerFilter) {
+        self.mode = mode;
+    }
+
+    // What the last apply() put out
+    pub fn levels(&self) -> &[u8] {
+        &self.levels
+    }
+
+    // Call once per frame with EMU::get_display()
+    pub fn apply(&mut self, screen: &[bool]) -> &[u8] {
+        match self.mode {
+            FlickerFilter::Off | FlickerFilter::Persistence(0) => {
+                for (level, &pixel) in self.levels.iter_mut().zip(screen) {
+                    *level = if pixel {255} else {0};
+                }
+            },
+            FlickerFilter::Persistence(frames) => {
+                // rounded up so the pixel is dark after exactly `frames` frames
+                let fade: u8 = 255u16.div_ceil(frames as u16) as u8;
+                for (level, &pixel) in self.levels.iter_mut().zip(screen) {
+                    *level = if pixel {255} else {level.saturating_sub(fade)};
+                }
+            },
+            FlickerFilter::Merge => {
+                for ((level, previous), &pixel) in self.levels.iter_mut().zip(self.previous.iter()).zip(screen) {
+                    *level = if pixel || *previous {255} else {0};
+                }
+            },
+        }
+        self.previous.copy_from_slice(screen);
+        &self.levels
+    }
+}
+
+// "off", "merge", "persistence" or "persistence:N" (N frames, 4 by default)
+impl FromStr for FlickerFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, frames) = match s.split_once(':') {
+            Some((name, frames)) => (name, Some(frames)),
+            None => (s, None),
+        };
+        match (name, frames) {
+            ("off", None) => Ok(FlickerFilter::Off),
+            ("merge", None) => Ok(FlickerFilter::Merge),
+            ("persistence", None) => Ok(FlickerFilter::Persistence(4)),
+            ("persistence", Some(frames)) => frames
+                .parse()
+                .map(FlickerFilter::Persistence)
+                .map_err(|_| format!("Invalid persistence '{}' (expected 0-255 frames)", frames)),
+            _ => Err(format!("Unknown filter '{}' (expected off, merge or persistence[:frames])", s)),
+        }
+    }
+}
+
+impl fmt::Display for FlickerFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FlickerFilter::Off => write!(f, "off"),
+            FlickerFilter::Persistence(frames) => write!(f, "persistence:{}", frames),
+            FlickerFilter::Merge => write!(f, "merge"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Pixel 0 lit in the first frame only, pixel 1 in every other frame
+    fn frames(filter: &mut DisplayFilter, count: usize) -> Vec<[u8; 3]> {
+        let mut screen = [false; NUM_PIXELS];
+        (0..count)
+            .map(|frame| {
+                screen[0] = frame == 0;
+                screen[1] = frame % 2 == 0;
+                let levels = filter.apply(&screen);
+                [levels[0], levels[1], levels[2]]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn phosphor_fades_out() {
+        let mut filter = DisplayFilter::new(FlickerFilter::Persistence(4));
+        let levels = frames(&mut filter, 6);
+        let first: Vec<u8> = levels.iter().map(|l| l[0]).collect();
+        assert_eq!(first, [255, 191, 127, 63, 0, 0]);
+        let second: Vec<u8> = levels.iter().map(|l| l[1]).collect();
+        assert_eq!(second, [255, 191, 255, 191, 255, 191]);
+        assert!(levels.iter().all(|l| l[2] == 0));
+
+        let mut off = DisplayFilter::new(FlickerFilter::Persistence(0));
+        assert_eq!(frames(&mut off, 2), [[255, 255, 0], [0, 0, 0]]);
+    }
+
+    #[test]
+    fn merge_keeps_the_frame_before() {
+        let mut filter = DisplayFilter::new(FlickerFilter::Merge);
+        assert_eq!(frames(&mut filter, 4), [[255, 255, 0], [255, 255, 0], [0, 255, 0], [0, 255, 0]]);
+
+        filter.set_mode(FlickerFilter::Off);
+        assert_eq!(frames(&mut filter, 2), [[255, 255, 0], [0, 0, 0]]);
+        assert_eq!(filter.levels()[1], 0);
+    }
+
+    #[test]
+    fn parses_filters() {
+        assert_eq!("persistence".parse(), Ok(FlickerFilter::Persistence(4)));
+        assert_eq!("persistence:12".parse(), Ok(FlickerFilter::Persistence(12)));
+        assert_eq!("merge".parse::<FlickerFilter>().unwrap().to_string(), "merge");
+        assert!("persistence:300".parse::<FlickerFilter>().is_err());
+        assert!("blur".parse::<FlickerFilter>().is_err());
+    }
+}
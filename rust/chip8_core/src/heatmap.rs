@@ -0,0 +1,112 @@
+// Memory heatmap: which bytes of RAM were written, read and run lately. Every access lights
+// the byte up fully and it fades a little every frame, so whatever a game is busy with
+// glows. The last frames are kept so a viewer can go back in time.
+//
+// Heat comes as [write, read, execute], which is also red, green and blue in a picture.
+
+use crate::{EMU, RAM_SIZE};
+use std::collections::VecDeque;
+
+pub const WRITE: usize = 0;
+pub const READ: usize = 1;
+pub const EXECUTE: usize = 2;
+
+// heat left after a frame, a byte nobody touches fades out within a couple of seconds
+const DECAY: f32 = 0.92;
+
+pub struct Heatmap {
+    heat: Vec<[f32; 3]>,
+    history: VecDeque<Vec<[u8; 3]>>, // the newest frame last
+    limit: usize,
+    frames: u64,
+}
+
+impl Heatmap {
+    // Keeps `history` frames to go back to
+    pub fn new(history: usize) -> Self {
+        Heatmap { heat: vec![[0.0; 3]; RAM_SIZE], history: VecDeque::new(), limit: history.max(1), frames: 0 }
+    }
+
+    // Notes what the instruction that's about to run touches
+    pub fn record(&mut self, emu: &EMU) {
+        let pc = emu.program_counter as usize;
+        let access = emu.memory_access(emu.state().opcode(pc as u16));
+        for addr in pc..(pc + 2).min(RAM_SIZE) {
+            self.heat[addr][EXECUTE] = 1.0;
+        }
+        for addr in access.read.start.min(RAM_SIZE)..access.read.end.min(RAM_SIZE) {
+            self.heat[addr][READ] = 1.0;
+        }
+        for addr in access.write.start.min(RAM_SIZE)..access.write.end.min(RAM_SIZE) {
+            self.heat[addr][WRITE] = 1.0;
+        }
+    }
+
+    // Runs one instruction and notes it
+    pub fn tick(&mut self, emu: &mut EMU) {
+        self.record(emu);
+        emu.tick();
+    }
+
+    // Keeps the frame that just ended and lets everything cool down a bit
+    pub fn end_frame(&mut self) {
+        let frame = self.heat.iter().map(|heat| heat.map(|h| (h * 255.0).round() as u8)).collect();
+        if self.history.len() == self.limit {
+            self.history.pop_front();
+        }
+        self.history.push_back(frame);
+        for heat in &mut self.heat {
+            *heat = heat.map(|h| h * DECAY);
+        }
+        self.frames += 1;
+    }
+
+    // Frames ended so far
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    // Frames that can be looked at
+    pub fn history_len(&self) -> usize {
+        self.history.len()
+    }
+
+    // Heat per byte, 0 to 255, at the end of the frame `back` frames ago (0 is the last one)
+    pub fn frame(&self, back: usize) -> Option<&[[u8; 3]]> {
+        let index = self.history.len().checked_sub(back + 1)?;
+        Some(&self.history[index])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accesses_light_up_and_fade() {
+        let rom = [
+            0xA3, 0x00, // 200: LD I, 300
+            0xF0, 0x33, // 202: LD B, V0
+            0xD0, 0x01, // 204: DRW V0, V0, 1
+        ];
+        let mut emu = EMU::new();
+        emu.load(&rom);
+        let mut heatmap = Heatmap::new(2);
+        for _ in 0..3 {
+            heatmap.tick(&mut emu);
+        }
+        heatmap.end_frame();
+        heatmap.end_frame();
+        heatmap.end_frame();
+        assert_eq!(heatmap.history_len(), 2);
+        assert_eq!(heatmap.frames(), 3);
+        assert!(heatmap.frame(2).is_none());
+
+        let before = heatmap.frame(1).unwrap();
+        assert_eq!(before[0x203], [0, 0, 235]);
+        assert_eq!(before[0x300], [235, 235, 0]);
+        assert_eq!(before[0x302], [235, 0, 0]);
+        assert_eq!(before[0x206], [0, 0, 0]);
+        assert!(heatmap.frame(0).unwrap()[0x203][EXECUTE] < 235);
+    }
+}
@@ -0,0 +1,323 @@
+// Recompiler for bulk runs (behind the `jit` feature). Straight-line runs of instructions,
+// basic blocks, are turned into a list of closures with the register numbers, constants and
+// quirks baked in, so running a block is a call per instruction with no fetching, decoding or
+// dispatching in between.
+//
+// A block ends after the first jump, call, return or skip, and after FX33 and FX55 since they
+// can write over the rest of the block. DXYN and FX0A are never compiled, they and anything
+// the interpreter doesn't know run through EMU::tick() instead. Blocks are thrown away when
+// something writes the bytes they were made from, the decode cache collects those writes for
+// us (see decode.rs), and all of them when the quirks change or another machine is run.
+//
+// Running the same number of instructions through Jit::run() and EMU::tick() ends in the same
+// machine, the tests below check that against the interpreter.
+
+use crate::decode::{Decoded, Kind};
+use crate::{Quirks, EMU, RAM_SIZE};
+use std::ops::Range;
+
+const MAX_BLOCK: usize = 32; // instructions
+
+type Op = Box<dyn Fn(&mut EMU)>;
+
+struct Block {
+    ops: Vec<Op>, // empty if the first instruction has to be interpreted
+    end: u16,     // address after the last instruction
+}
+
+pub struct Jit {
+    blocks: Vec<Option<Block>>, // by start address
+    quirks: Quirks,             // the blocks were compiled for
+    machine: u64,               // EMU::id of the machine they were compiled from
+}
+
+impl Default for Jit {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Jit {
+    pub fn new() -> Self {
+        Jit { blocks: (0..RAM_SIZE).map(|_| None).collect(), quirks: Quirks::default(), machine: 0 }
+    }
+
+    // Compiled blocks there are right now
+    pub fn blocks(&self) -> usize {
+        self.blocks.iter().filter(|block| block.is_some()).count()
+    }
+
+    // Runs `instructions` instructions, the same as calling emu.tick() that many times
+    pub fn run(&mut self, emu: &mut EMU, instructions: u64) {
+        if emu.quirks != self.quirks || emu.id != self.machine {
+            self.blocks.iter_mut().for_each(|block| *block = None);
+            self.quirks = emu.quirks;
+            self.machine = emu.id;
+        }
+        let mut left = instructions;
+        while left > 0 {
+            if let Some(written) = emu.decoded.take_written() {
+                self.invalidate(written);
+            }
+            let start = emu.program_counter as usize;
+            if start >= RAM_SIZE {
+                emu.tick(); // panics the same way
+                left -= 1;
+                continue;
+            }
+            let block = self.blocks[start].get_or_insert_with(|| compile(emu, start));
+            if block.ops.is_empty() {
+                emu.tick();
+                left -= 1;
+                continue;
+            }
+
+            // only the last instruction can change the PC, so running part of a block is fine
+            let n = block.ops.len().min(left as usize);
+            emu.program_counter = if n == block.ops.len() { block.end } else { (start + 2 * n) as u16 };
+            for op in &block.ops[..n] {
+                op(emu);
+            }
+            left -= n as u64;
+        }
+    }
+
+    // Drops the blocks made from any of the bytes in `written`
+    fn invalidate(&mut self, written: Range<usize>) {
+        let first = written.start.saturating_sub(2 * MAX_BLOCK - 1);
+        for start in first..written.end.min(RAM_SIZE) {
+            if let Some(block) = &self.blocks[start] && block.end as usize > written.start {
+                self.blocks[start] = None;
+            }
+        }
+    }
+}
+
+fn compile(emu: &EMU, start: usize) -> Block {
+    let mut ops = Vec::new();
+    let mut addr = start;
+    while ops.len() < MAX_BLOCK && addr + 1 < RAM_SIZE {
+        let op = Decoded::new(u16::from_be_bytes([emu.ram[addr], emu.ram[addr + 1]]));
+        if matches!(op.kind, Kind::Draw | Kind::WaitKey | Kind::Unknown) {
+            break;
+        }
+        ops.push(compile_op(op, emu.quirks));
+        addr += 2;
+        if ends_block(op.kind) {
+            break;
+        }
+    }
+    Block { ops, end: addr as u16 }
+}
+
+fn ends_block(kind: Kind) -> bool {
+    matches!(
+        kind,
+        Kind::Jump | Kind::Call | Kind::Ret | Kind::JumpV0
+            | Kind::SkipEq | Kind::SkipNe | Kind::SkipEqReg | Kind::SkipNeReg | Kind::SkipKey | Kind::SkipNotKey
+            | Kind::Bcd | Kind::Store
+    )
+}
+
+// What `op` does, the PC already pointing past it. Whatever isn't common enough in loops to
+// be worth its own closure goes through execute(), still without decoding it again.
+fn compile_op(op: Decoded, quirks: Quirks) -> Op {
+    let (x, y, nn, nnn) = (op.x as usize, op.y as usize, op.nn, op.nnn);
+    match op.kind {
+        Kind::Jump => Box::new(move |emu| emu.program_counter = nnn),
+        Kind::SkipEq => Box::new(move |emu| {
+            if emu.v_reg[x] == nn {
+                emu.program_counter += 2;
+            }
+        }),
+        Kind::SkipNe => Box::new(move |emu| {
+            if emu.v_reg[x] != nn {
+                emu.program_counter += 2;
+            }
+        }),
+        Kind::SkipNeReg => Box::new(move |emu| {
+            if emu.v_reg[x] != emu.v_reg[y] {
+                emu.program_counter += 2;
+            }
+        }),
+        Kind::Load => Box::new(move |emu| emu.v_reg[x] = nn),
+        Kind::Add => Box::new(move |emu| emu.v_reg[x] = emu.v_reg[x].wrapping_add(nn)),
+        Kind::Move => Box::new(move |emu| emu.v_reg[x] = emu.v_reg[y]),
+        Kind::Or => bitwise(x, y, quirks.vf_reset, |a, b| a | b),
+        Kind::And => bitwise(x, y, quirks.vf_reset, |a, b| a & b),
+        Kind::Xor => bitwise(x, y, quirks.vf_reset, |a, b| a ^ b),
+        Kind::AddReg => Box::new(move |emu| {
+            let (value, carry) = emu.v_reg[x].overflowing_add(emu.v_reg[y]);
+            emu.v_reg[x] = value;
+            emu.v_reg[0xF] = carry as u8;
+        }),
+        Kind::Sub => Box::new(move |emu| {
+            let (value, borrow) = emu.v_reg[x].overflowing_sub(emu.v_reg[y]);
+            emu.v_reg[x] = value;
+            emu.v_reg[0xF] = !borrow as u8;
+        }),
+        Kind::SubN => Box::new(move |emu| {
+            let (value, borrow) = emu.v_reg[y].overflowing_sub(emu.v_reg[x]);
+            emu.v_reg[x] = value;
+            emu.v_reg[0xF] = !borrow as u8;
+        }),
+        Kind::Shr => {
+            let from = if quirks.shift_uses_vy { y } else { x };
+            Box::new(move |emu| {
+                let value = emu.v_reg[from];
+                emu.v_reg[x] = value >> 1;
+                emu.v_reg[0xF] = value & 1;
+            })
+        },
+        Kind::Shl => {
+            let from = if quirks.shift_uses_vy { y } else { x };
+            Box::new(move |emu| {
+                let value = emu.v_reg[from];
+                emu.v_reg[x] = value << 1;
+                emu.v_reg[0xF] = value >> 7;
+            })
+        },
+        Kind::LoadI => Box::new(move |emu| emu.i_reg = nnn),
+        Kind::AddI => Box::new(move |emu| emu.i_reg = emu.i_reg.wrapping_add(emu.v_reg[x] as u16)),
+        _ => Box::new(move |emu| emu.execute(op)),
+    }
+}
+
+fn bitwise(x: usize, y: usize, vf_reset: bool, f: fn(u8, u8) -> u8) -> Op {
+    if vf_reset {
+        Box::new(move |emu| {
+            emu.v_reg[x] = f(emu.v_reg[x], emu.v_reg[y]);
+            emu.v_reg[0xF] = 0;
+        })
+    } else {
+        Box::new(move |emu| emu.v_reg[x] = f(emu.v_reg[x], emu.v_reg[y]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Variant;
+
+    const ROMS: [(&str, &[u8]); 6] = [
+        ("BLINKY", include_bytes!("../../../ROMS/BLINKY")),
+        ("INVADERS", include_bytes!("../../../ROMS/INVADERS")),
+        ("PONG", include_bytes!("../../../ROMS/PONG")),
+        ("TETRIS", include_bytes!("../../../ROMS/TETRIS")),
+        ("UFO", include_bytes!("../../../ROMS/UFO")),
+        ("WIPEOFF", include_bytes!("../../../ROMS/WIPEOFF")),
+    ];
+
+    fn machine(rom: &[u8], quirks: Quirks) -> EMU {
+        let mut emu = EMU::new();
+        emu.seed(8);
+        emu.set_quirks(quirks);
+        emu.load(rom);
+        emu
+    }
+
+    // Plays `frames` frames of 10 instructions on both, pressing keys now and then
+    fn compare(name: &str, rom: &[u8], quirks: Quirks, frames: usize) {
+        let mut interpreted = machine(rom, quirks);
+        let mut compiled = machine(rom, quirks);
+        let mut jit = Jit::new();
+        for frame in 0..frames {
+            let key = frame / 20 % 16;
+            for emu in [&mut interpreted, &mut compiled] {
+                emu.keypress(key, frame % 20 < 10);
+            }
+            for _ in 0..10 {
+                interpreted.tick();
+            }
+            jit.run(&mut compiled, 10);
+            interpreted.tick_timers();
+            compiled.tick_timers();
+            assert!(interpreted.save_state() == compiled.save_state(), "{} ({:?}) differs in frame {}", name, quirks, frame);
+        }
+    }
+
+    #[test]
+    fn games_match_the_interpreter() {
+        for (name, rom) in ROMS {
+            compare(name, rom, Quirks::default(), 1000);
+        }
+    }
+
+    #[test]
+    fn quirks_match_the_interpreter() {
+        for variant in [Variant::Chip8, Variant::SuperChip, Variant::XoChip] {
+            for (name, rom) in ROMS {
+                compare(name, rom, Quirks::for_variant(variant), 300);
+            }
+        }
+    }
+
+    #[test]
+    fn self_modifying_code() {
+        let rom = [
+            0x7B, 0x01, // 200: ADD VB, 1, becomes ADD VB, 5
+            0xA2, 0x00, // 202: LD I, 200
+            0x60, 0x7B, // 204: LD V0, #7B
+            0x61, 0x05, // 206: LD V1, #05
+            0xF2, 0x55, // 208: LD [I], V2, stores V0 and V1
+            0x12, 0x00, // 20A: JP 200
+        ];
+        let mut emu = machine(&rom, Quirks::default());
+        let mut jit = Jit::new();
+        jit.run(&mut emu, 13);
+        assert_eq!(emu.state().v[0xB], 11);
+
+        compare("self modifying", &rom, Quirks::default(), 10);
+
+        // and from outside, between two runs
+        let mut emu = machine(&[0x7B, 0x01, 0x12, 0x00], Quirks::default());
+        let mut jit = Jit::new();
+        jit.run(&mut emu, 2);
+        emu.poke().write_ram(0x201, &[0x10]).unwrap();
+        jit.run(&mut emu, 2);
+        assert_eq!(emu.state().v[0xB], 0x11);
+    }
+
+    #[test]
+    fn another_machine_gets_its_own_blocks() {
+        let mut jit = Jit::new();
+        let mut first = machine(&[
+            0x12, 0x04, // 200: JP 204
+            0x00, 0x00, // 202
+            0x60, 0x05, // 204: LD V0, 5
+            0x12, 0x04, // 206: JP 204
+        ], Quirks::default());
+        jit.run(&mut first, 3);
+        assert_eq!(first.state().v[0], 5);
+
+        // 204 is empty here, the block from the first machine mustn't run
+        let rom = [0x12, 0x04];
+        let mut interpreted = machine(&rom, Quirks::default());
+        let mut compiled = machine(&rom, Quirks::default());
+        for _ in 0..3 {
+            interpreted.tick();
+        }
+        jit.run(&mut compiled, 3);
+        assert_eq!((compiled.state().pc, compiled.state().v[0]), (0x208, 0));
+        assert!(interpreted.save_state() == compiled.save_state());
+    }
+
+    #[test]
+    fn blocks_stop_at_draws_and_key_waits() {
+        let rom = [
+            0x60, 0x01, // 200: LD V0, 1
+            0xD0, 0x05, // 202: DRW V0, V0, 5
+            0x70, 0x01, // 204: ADD V0, 1
+            0xF1, 0x0A, // 206: LD V1, K
+            0x12, 0x00, // 208: JP 200
+        ];
+        let mut emu = machine(&rom, Quirks::default());
+        let mut jit = Jit::new();
+        jit.run(&mut emu, 20);
+        assert_eq!(emu.state().pc, 0x206); // still waiting
+        assert_eq!(jit.blocks(), 4); // 200 and 204, and 202 and 206 to interpret
+        emu.keypress(7, true);
+        jit.run(&mut emu, 2);
+        assert_eq!((emu.state().pc, emu.state().v[1]), (0x200, 7));
+    }
+}
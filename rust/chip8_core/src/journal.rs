@@ -0,0 +1,120 @@
+// Undo journal for reverse stepping: what every instruction changed, for the last `limit`
+// instructions. Registers are small enough to keep whole; of RAM only the bytes the
+// instruction overwrote are kept, of the screen only the pixels it flipped. The breakpoint and
+// watchpoint hits it counted are kept too, so conditions on `hits` still work going backwards.
+
+use crate::debug::MemoryAccess;
+use crate::*;
+use std::collections::VecDeque;
+
+pub(crate) struct Journal {
+    entries: VecDeque<Entry>,
+    limit: usize,
+}
+
+struct Entry {
+    instruction: u64, // number of the instruction, counting from the first one the debugger ran
+    pc: u16,
+    i_reg: u16,
+    sp: u16,
+    v_reg: [u8; NUM_REGS],
+    dt: u8,
+    st: u8,
+    stack_slot: Option<(usize, u16)>, // what a call overwrote
+    ram: Vec<(u16, u8)>,              // old bytes
+    screen: Vec<u16>,                 // flipped pixels
+    rng: Option<SmallRng>,            // before CXNN
+    hits: Hits,
+}
+
+// Breakpoint and watchpoint hits counted for an instruction
+#[derive(Default)]
+pub(crate) struct Hits {
+    pub breakpoint: Option<u16>, // the one at its address
+    pub watchpoints: Vec<usize>,
+}
+
+// Taken before the instruction runs, finished by Journal::commit afterwards
+pub(crate) struct Pending {
+    entry: Entry,
+    screen: Option<Box<[bool]>>,
+}
+
+impl Journal {
+    pub fn new(limit: usize) -> Self {
+        Journal { entries: VecDeque::new(), limit }
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn before(emu: &EMU, op: u16, access: &MemoryAccess, instruction: u64) -> Pending {
+        let draws = op == 0x00E0 || op >> 12 == 0xD;
+        let entry = Entry {
+            instruction,
+            pc: emu.program_counter,
+            i_reg: emu.i_reg,
+            sp: emu.sp,
+            v_reg: emu.v_reg,
+            dt: emu.dt,
+            st: emu.st,
+            stack_slot: (op >> 12 == 2).then(|| (emu.sp as usize, emu.stack[emu.sp as usize])),
+            ram: access.write.clone().map(|addr| (addr as u16, emu.ram[addr])).collect(),
+            screen: Vec::new(),
+            rng: (op >> 12 == 0xC).then(|| emu.rng.clone()),
+            hits: Hits::default(),
+        };
+        Pending { entry, screen: draws.then(|| emu.screen.into()) }
+    }
+
+    pub fn commit(&mut self, pending: Pending, emu: &EMU, hits: Hits) {
+        let mut entry = pending.entry;
+        entry.hits = hits;
+        if let Some(old) = pending.screen {
+            entry.screen = (0..old.len()).filter(|&p| old[p] != emu.screen[p]).map(|p| p as u16).collect();
+        }
+        if self.entries.len() == self.limit {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+    }
+
+    // Takes back the last instruction, returning its number and the hits it counted
+    pub fn undo(&mut self, emu: &mut EMU) -> Option<(u64, Hits)> {
+        let entry = self.entries.pop_back()?;
+        emu.program_counter = entry.pc;
+        emu.i_reg = entry.i_reg;
+        emu.sp = entry.sp;
+        emu.v_reg = entry.v_reg;
+        emu.dt = entry.dt;
+        emu.st = entry.st;
+        if let Some((slot, addr)) = entry.stack_slot {
+            emu.stack[slot] = addr;
+        }
+        for (addr, byte) in entry.ram {
+            emu.ram[addr as usize] = byte;
+            emu.decoded.invalidate(addr as usize..addr as usize + 1);
+        }
+        for pixel in entry.screen {
+            emu.screen[pixel as usize] ^= true;
+        }
+        if let Some(rng) = entry.rng {
+            emu.rng = rng;
+        }
+        Some((entry.instruction, entry.hits))
+    }
+
+    // PC and number of the last instruction in the journal that wrote `addr`
+    pub fn last_write(&self, addr: u16) -> Option<(u16, u64)> {
+        self.entries
+            .iter()
+            .rev()
+            .find(|entry| entry.ram.iter().any(|&(a, _)| a == addr))
+            .map(|entry| (entry.pc, entry.instruction))
+    }
+}
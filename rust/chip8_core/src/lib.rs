@@ -1,8 +1,44 @@
-use rand::random;
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
+use decode::{DecodeCache, Decoded, Kind};
+
+mod analyze;
+pub mod av;
+#[cfg(feature = "capture")]
+pub mod capture;
+pub mod cfg;
+pub mod coverage;
+pub mod debug;
+mod decode;
+pub mod disasm;
+pub mod expr;
+pub mod heatmap;
+#[cfg(feature = "jit")]
+pub mod jit;
+mod filter;
+mod journal;
+mod machine;
+pub mod profile;
+mod quirks;
+mod render;
+mod state;
+pub mod symbols;
+pub mod trace;
+
+pub use analyze::{analyze, Analysis, Finding};
+pub use filter::{DisplayFilter, FlickerFilter};
+pub use machine::{parse_address, MachineState, Poke, PokeError, Register};
+pub use quirks::{Quirks, Variant};
+pub use render::{render_indexed, rgba_len, scale_indexed, Palette, RenderError, ScaleFilter};
+pub use state::StateError;
 
 // constants
 const RAM_SIZE: usize = 4096;
 
+// hands out EMU::id, 0 is left for "no machine yet"
+#[cfg(feature = "jit")]
+static NEXT_ID: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(1);
+
 // display
 pub const SCREEN_WIDTH: usize = 64;
 pub const SCREEN_HEIGHT: usize = 64;
@@ -16,6 +52,9 @@ const STACK_SIZE: usize = 16;
 //addrers to start loading the ram -> 512 bytes   
 const START_ADDR: u16 = 0x200; // let because of the old architecture of chip8, to do required calculations, to run 
 
+// biggest ROM that fits between START_ADDR and the end of ram
+pub const MAX_ROM_SIZE: usize = RAM_SIZE - START_ADDR as usize;
+
 const NUM_KEYS: usize = 16;
 
 // Defined font set
@@ -63,6 +102,11 @@ pub struct EMU{
     keys: [bool; NUM_KEYS],
     st: u8, //sound timer
     dt: u8, //delay timer
+    quirks: Quirks,
+    rng: SmallRng, // for CXNN
+    decoded: DecodeCache, // see decode.rs
+    #[cfg(feature = "jit")]
+    id: u64, // so a Jit can tell it's been handed another machine
 }
 
 
@@ -79,6 +123,11 @@ impl EMU{
             keys: [false; NUM_KEYS],
             st: 0,
             dt: 0,
+            quirks: Quirks::default(),
+            rng: SmallRng::from_os_rng(),
+            decoded: DecodeCache::new(),
+            #[cfg(feature = "jit")]
+            id: NEXT_ID.fetch_add(1, std::sync::atomic::Ordering::Relaxed),
         };
 
         new_emu.ram[0..(FONTSET_SIZE as usize)].copy_from_slice(&FONTSET);
@@ -100,6 +149,21 @@ impl EMU{
         self.st = 0;
         self.dt = 0;
         self.ram[0..(FONTSET_SIZE as usize)].copy_from_slice(&FONTSET);
+        self.decoded.clear();
+    }
+
+    // Quirks and the random seed survive reset()
+    pub fn set_quirks(&mut self, quirks: Quirks){
+        self.quirks = quirks;
+    }
+
+    pub fn quirks(&self) -> Quirks{
+        self.quirks
+    }
+
+    // Makes CXNN repeat the same numbers on every run
+    pub fn seed(&mut self, seed: u64){
+        self.rng = SmallRng::seed_from_u64(seed);
     }
 
     //Basis of LIFO
@@ -126,13 +190,24 @@ impl EMU{
         let start: usize = START_ADDR as usize;
         let end: usize = (START_ADDR as usize) + data.len();
         self.ram[start..end].copy_from_slice(data);
+        self.decoded.invalidate(start..end);
     }
 
     pub fn tick(&mut self){
+        // already decoded unless it's the first time here or the code changed since
+        let op: Decoded = self.decoded.get(&self.ram, self.program_counter);
+        self.program_counter += 2;
+        self.execute(op);
+    }
+
+    // tick() without the decode cache, for comparing the two in benches/tick.rs. Not part of
+    // the API, it's only public because benches are a crate of their own.
+    #[doc(hidden)]
+    pub fn tick_uncached(&mut self){
         //fetch
         let op: u16 = self.fetch(); // opcode
         // Decode & Execute
-        self.execute(op);
+        self.execute(Decoded::new(op));
     }
 
         fn fetch(&mut self) -> u16{
@@ -164,117 +239,122 @@ impl EMU{
         }
     }
     
-    fn execute(&mut self, op: u16){
-        let digit1: u16 = (op & 0xF000) >> 12;
-        let digit2: u16 = (op & 0x0F00) >> 8;
-        let digit3: u16 = (op & 0x00F0) >> 4;
-        let digit4: u16 = op & 0x000F;
+    fn execute(&mut self, op: Decoded){
 
-        match(digit1,digit2,digit3,digit4){
+        match op.kind{
 
             //  Time for OP \\
 
             // 0000 - Nop \\
-            (0,0,0,0) => return,
+            Kind::Nop => return,
 
             // 00E0 - Clear Screen \\
-            (0,0,0xE,0) => {
+            Kind::Cls => {
                 self.screen = [false; SCREEN_WIDTH*SCREEN_HEIGHT];
             },
 
             // 00EE - Return from Subroutine \\
-            (0,0,0xE,0xE) => {
+            Kind::Ret => {
                 let ret_addr: u16 = self.pop();
 
                 self.program_counter = ret_addr;
             },
 
             // 1NNN - Jump \\
-            (1,_,_,_) => {
-                let nnn: u16 = op & 0xFFF;
+            Kind::Jump => {
+                let nnn: u16 = op.nnn;
                 self.program_counter = nnn;
             },
 
             // 2NNNN - Call Subroutine \\
-            (2,_,_,_) => {
-                let nnn: u16 = op & 0xFFF;
+            Kind::Call => {
+                let nnn: u16 = op.nnn;
                 self.push(self.program_counter);
                 self.program_counter = nnn;
             },
 
             // 3XNN - Skip next if VX == NN \\
-            (3,_,_,_) => {
-                let x: usize = digit2 as usize;
-                let nn: u8 = (op & 0xFF) as u8;
+            Kind::SkipEq => {
+                let x: usize = op.x as usize;
+                let nn: u8 = op.nn;
                 if self.v_reg[x] == nn{
                     self.program_counter += 2;
                 }
             },
 
             // 4XNN - Skip next if VX == NN \\
-            (4,_,_,_) => {
-                let x: usize = digit2 as usize;
-                let nn: u8 = (op & 0xFF) as u8;
+            Kind::SkipNe => {
+                let x: usize = op.x as usize;
+                let nn: u8 = op.nn;
                 if self.v_reg[x] != nn{
                     self.program_counter += 2;
                 }
             },
 
             // 5XY0 - Skip next if VX == VY \\
-            (5,_,_,0) => {
-                let x: usize = digit2 as usize;
-                let y: usize = digit2 as usize;
+            Kind::SkipEqReg => {
+                let x: usize = op.x as usize;
+                let y: usize = op.x as usize;
                 if self.v_reg[x] == self.v_reg[y]{
                     self.program_counter += 2;
                 }
             },
 
             // 6XNN - VX = NN \\
-            (6,_,_,_) => {
-                let x: usize = digit2 as usize;
-                let nn: u8 = (op & 0xFF) as u8;
+            Kind::Load => {
+                let x: usize = op.x as usize;
+                let nn: u8 = op.nn;
                 self.v_reg[x] = nn;
             },
 
             // 7XNN - VX += NN \\
-            (7,_,_,_) => {
-                let x: usize = digit2 as usize;
-                let nn: u8 = (op & 0xFF) as u8;
+            Kind::Add => {
+                let x: usize = op.x as usize;
+                let nn: u8 = op.nn;
                 self.v_reg[x] = self.v_reg[x].wrapping_add(nn);
             },
 
             // 8XY0 - Skip next if VX == VY \\
-            (8,_,_,0) => {
-                let x: usize = digit2 as usize;
-                let y: usize = digit3 as usize;
+            Kind::Move => {
+                let x: usize = op.x as usize;
+                let y: usize = op.y as usize;
                 self.v_reg[x] = self.v_reg[y]
             },
             // BITWISE \\
             // 8XY1 - Bitwise OR VX |= VY \\
-            (8,_,_,1) => {
-                let x: usize = digit2 as usize;
-                let y: usize = digit3 as usize;
-                self.v_reg[x] |= self.v_reg[y]
+            Kind::Or => {
+                let x: usize = op.x as usize;
+                let y: usize = op.y as usize;
+                self.v_reg[x] |= self.v_reg[y];
+                if self.quirks.vf_reset{
+                    self.v_reg[0xF] = 0;
+                }
             },
 
             // 8XY2 - Bitwise AND VX &= VY \\
-            (8,_,_,2) => {
-                let x: usize = digit2 as usize;
-                let y: usize = digit3 as usize;
-                self.v_reg[x] &= self.v_reg[y]
+            Kind::And => {
+                let x: usize = op.x as usize;
+                let y: usize = op.y as usize;
+                self.v_reg[x] &= self.v_reg[y];
+                if self.quirks.vf_reset{
+                    self.v_reg[0xF] = 0;
+                }
             },
 
             // 8XY3 - Bitwise XOR VX ^= VY \\
-            (8,_,_,3) => {
-                let x: usize = digit2 as usize;
-                let y: usize = digit3 as usize;
-                self.v_reg[x] ^= self.v_reg[y]
+            Kind::Xor => {
+                let x: usize = op.x as usize;
+                let y: usize = op.y as usize;
+                self.v_reg[x] ^= self.v_reg[y];
+                if self.quirks.vf_reset{
+                    self.v_reg[0xF] = 0;
+                }
             },
 
             // 8XY4 - VX += VY \\
-            (8,_,_,4) => {
-                let x: usize = digit2 as usize;
-                let y: usize = digit3 as usize;
+            Kind::AddReg => {
+                let x: usize = op.x as usize;
+                let y: usize = op.y as usize;
                 
                 let (new_vx, carry) = self.v_reg[x].overflowing_add(self.v_reg[y]);
                 let new_vf = if carry{1}else{0}; // returns if overflow while operation is 1/0
@@ -284,9 +364,9 @@ impl EMU{
             },
 
             // 8XY5 - VX -= VY \\
-            (8,_,_,5) => {
-                let x: usize = digit2 as usize;
-                let y: usize = digit3 as usize;
+            Kind::Sub => {
+                let x: usize = op.x as usize;
+                let y: usize = op.y as usize;
                 
                 let (new_vx, borrow) = self.v_reg[x].overflowing_sub(self.v_reg[y]);
                 let new_vf = if borrow{0}else{1}; // returns if underflow while operation is 0/1
@@ -296,8 +376,11 @@ impl EMU{
             },
 
             // 8XY6 - VX >>= 1 \\
-            (8,_,_,6) => {
-                let x: usize = digit2 as usize;
+            Kind::Shr => {
+                let x: usize = op.x as usize;
+                if self.quirks.shift_uses_vy{
+                    self.v_reg[x] = self.v_reg[op.y as usize];
+                }
                 let lsb: u8 = self.v_reg[x] & 1; // least sig bit
 
                 self.v_reg[x] >>= 1;
@@ -305,9 +388,9 @@ impl EMU{
             },
 
            // 8XY7 - VX = VY - VX \\
-            (8,_,_,7) => {
-                let x: usize = digit2 as usize;
-                let y: usize = digit3 as usize;
+            Kind::SubN => {
+                let x: usize = op.x as usize;
+                let y: usize = op.y as usize;
                 
                 let (new_vx, borrow) = self.v_reg[y].overflowing_sub(self.v_reg[x]);
                 let new_vf = if borrow{0}else{1}; // returns if underflow while operation is 0/1
@@ -317,8 +400,11 @@ impl EMU{
             }, 
 
             // 8XYE - VX <<= 1 \\
-            (8,_,_,0xE) => {
-                let x: usize = digit2 as usize;
+            Kind::Shl => {
+                let x: usize = op.x as usize;
+                if self.quirks.shift_uses_vy{
+                    self.v_reg[x] = self.v_reg[op.y as usize];
+                }
                 // most sig bit
                 let msb: u8 = (self.v_reg[x] >> 7) & 1;
                 self.v_reg[x] = self.v_reg[x] << 1;
@@ -326,9 +412,9 @@ impl EMU{
             },
 
             // 9XY0 - Skip if VX != VY \\
-            (9,_,_,0) => {
-                let x: usize = digit2 as usize;
-                let y: usize = digit3 as usize;
+            Kind::SkipNeReg => {
+                let x: usize = op.x as usize;
+                let y: usize = op.y as usize;
 
                 if self.v_reg[x] != self.v_reg[y]{
                     self.program_counter += 2;
@@ -336,33 +422,40 @@ impl EMU{
             },
 
             // ANNN - I = NNN \\
-            (0xA,_,_,_) => {
-                let nnn: u16 = op & 0xFFF;
+            Kind::LoadI => {
+                let nnn: u16 = op.nnn;
                 self.i_reg = nnn;
             },
 
             // BNNN - Jump to V0 + NNN \\
-            (0xB,_,_,_) => {
-                let nnn: u16 = op & 0xFFF;
-                self.program_counter = (self.v_reg[0] as u16) + nnn;
+            Kind::JumpV0 => {
+                let nnn: u16 = op.nnn;
+                // BXNN - Jump to VX + XNN on SCHIP
+                let offset: u8 = if self.quirks.jump_uses_vx {self.v_reg[op.x as usize]} else {self.v_reg[0]};
+                self.program_counter = (offset as u16) + nnn;
             },
 
             // CXNN - VX = rand() & NN \\
-            (0xC,_,_,_) => {
-                let x: usize = digit2 as usize;
-                let nn: u8 = (op & 0xFF) as u8;
+            Kind::Rand => {
+                let x: usize = op.x as usize;
+                let nn: u8 = op.nn;
 
-                let rng: u8 = random();
+                let rng: u8 = self.rng.random();
                 self.v_reg[x] = rng & nn;
             },
 
             // DXYV - Draw Sprite \\
-            (0xD,_,_,_) => {
+            Kind::Draw => {
                 // at (x,y) co - ords
-                let x_cord: u16 = self.v_reg[digit2 as usize] as u16;
-                let y_cord: u16 = self.v_reg[digit3 as usize] as u16;
+                let mut x_cord: u16 = self.v_reg[op.x as usize] as u16;
+                let mut y_cord: u16 = self.v_reg[op.y as usize] as u16;
+                if self.quirks.clip_sprites{
+                    // only the starting point wraps, the rest of the sprite is cut off
+                    x_cord %= SCREEN_WIDTH as u16;
+                    y_cord %= SCREEN_HEIGHT as u16;
+                }
 
-                let num_rows: u16 = digit4;
+                let num_rows: u16 = op.n as u16;
                 
                 let mut flipped: bool = false; // to track flipped pixels
 
@@ -371,6 +464,10 @@ impl EMU{
                     let pixels: u8 = self.ram[addr as usize];
                     
                     for x_line in 0..8{
+                        if self.quirks.clip_sprites
+                            && (x_cord as usize + x_line >= SCREEN_WIDTH || (y_cord + y_line) as usize >= SCREEN_HEIGHT){
+                            continue;
+                        }
                         if (pixels & (0b1000_0000 >> x_line)) != 0{
                             let x: usize = (x_cord as usize + x_line) % SCREEN_WIDTH;
                             let y: usize = (y_cord as usize + y_line as usize) % SCREEN_HEIGHT;
@@ -391,8 +488,8 @@ impl EMU{
             },
 
             // EX9E - Skip if Key Pressed \\
-            (0xE,_,9,0xE) => {
-                let x: usize = digit2 as usize;
+            Kind::SkipKey => {
+                let x: usize = op.x as usize;
                 let vx = self.v_reg[x];
                 let key = self.keys[vx as usize];
                 if key{
@@ -401,8 +498,8 @@ impl EMU{
             },
 
             // EXA1 - Skip if Key Not Pressed \\
-            (0xE,_,0xA,1) => {
-                let x: usize = digit3 as usize;
+            Kind::SkipNotKey => {
+                let x: usize = op.y as usize;
                 let vx = self.v_reg[x];
                 let key: bool = self.keys[vx as usize];
                 if !key{
@@ -411,14 +508,14 @@ impl EMU{
             },
 
             // FX07 - VX = DT \\
-            (0xF,_,0,7) => {
-                let x: usize = digit2 as usize;
+            Kind::GetDelay => {
+                let x: usize = op.x as usize;
                 self.v_reg[x] = self.dt;
             },
 
             // FX0A - Wait for a Key Press \\
-            (0xF,_,0,0xA) => {
-                let x: usize = digit2 as usize;
+            Kind::WaitKey => {
+                let x: usize = op.x as usize;
                 let mut key_pressed: bool = false;
                 for i in 0..self.keys.len(){
                     if self.keys[i]{
@@ -433,35 +530,34 @@ impl EMU{
             },
 
             // FX15 - DT = VX \\
-            (0xF,_,1,5) => {
-                let x: usize = digit2 as usize;
+            Kind::SetDelay => {
+                let x: usize = op.x as usize;
                 self.dt = self.v_reg[x];
             },
 
             // FX18 - ST = VX \\
-            (0xF,_,1,8) => {
-                let x: usize = digit2 as usize;
+            Kind::SetSound => {
+                let x: usize = op.x as usize;
                 self.st = self.v_reg[x];
-                println!("Sound timer set to: {}", self.st); // Add this debug line
             },
 
             // FX1E - I += VX \\
-            (0xF,_,1,0xE) => {
-                let x: usize = digit2 as usize;
+            Kind::AddI => {
+                let x: usize = op.x as usize;
                 let vx: u16 = self.v_reg[x] as u16;
                 self.i_reg = self.i_reg.wrapping_add(vx);
             },
 
             // FX29 - Set I to Font Addr \\
-            (0xF,_,2,9) => {
-                let x: usize = digit2 as usize;
+            Kind::Font => {
+                let x: usize = op.x as usize;
                 let c: u16 = self.v_reg[x] as u16;
                 self.i_reg = c*5;
             },
 
             // FX33 - I = BCD of VX
-            (0xF,_,3,3) => {
-                let x: usize = digit2 as usize;
+            Kind::Bcd => {
+                let x: usize = op.x as usize;
                 let vx: u16 = self.v_reg[x] as u16; 
                 // BCD - Binary Coded Decimal
                 let hundreds: u8 = (vx/100) as u8;
@@ -471,29 +567,37 @@ impl EMU{
                 self.ram[self.i_reg as usize] = hundreds;
                 self.ram[(self.i_reg + 1) as usize] = tens;
                 self.ram[(self.i_reg + 2) as usize] = ones;
+                self.decoded.invalidate(self.i_reg as usize..self.i_reg as usize + 3);
             },
 
             // FX55 - Store V0 - VX into I \\
-            (0xF,_,5,5) => {
-                let x: usize = digit2 as usize;
+            Kind::Store => {
+                let x: usize = op.x as usize;
                 let i: usize = self.i_reg as usize;
                 for idx in 0..x{
                     self.ram[i+idx] = self.v_reg[idx];
                 }
+                self.decoded.invalidate(i..i + x);
+                if self.quirks.load_store_increments_i{
+                    self.i_reg += x as u16 + 1;
+                }
             },
             
             // FX65 - Load I into V0-VX
-            (0xF,_,6,5) => {
-                let x: usize = digit2 as usize;
+            Kind::Restore => {
+                let x: usize = op.x as usize;
                 let i: usize = self.i_reg as usize;
                 for idx in 0..x{
                     self.v_reg[idx] = self.ram[i+idx];
                 }
+                if self.quirks.load_store_increments_i{
+                    self.i_reg += x as u16 + 1;
+                }
             },
 
             // Finally \\
 
-            (_,_,_,_) => unimplemented!("Error: Unimplimented opcode: {}", op),
+            Kind::Unknown => unimplemented!("Error: Unimplimented opcode: {}", op.op),
         }
     }
 
@@ -0,0 +1,228 @@
+// Looking inside the machine, for debuggers, cheat engines and tests.
+//
+// EMU::state() is a read-only view of everything a running program can see. Changing the
+// machine from outside goes through EMU::poke() instead, so it's obvious where that happens
+// and values that would crash the interpreter are turned away.
+
+use crate::*;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Clone, Copy)]
+pub struct MachineState<'a> {
+    pub pc: u16,
+    pub i: u16,
+    pub v: &'a [u8; NUM_REGS],
+    pub sp: u16,
+    pub stack: &'a [u16], // return addresses, the innermost call last
+    pub dt: u8,
+    pub st: u8,
+    pub keys: &'a [bool; NUM_KEYS],
+    pub ram: &'a [u8; RAM_SIZE],
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Register {
+    V(usize),
+    I,
+    Pc,
+    Sp,
+    Dt,
+    St,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum PokeError {
+    OutsideRam(usize),  // first address that doesn't exist
+    StackTooDeep(u16),
+    NotAByte(u16),
+    NoSuchRegister(usize),
+}
+
+impl fmt::Display for PokeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PokeError::OutsideRam(addr) => write!(f, "{:#X} is outside of RAM", addr),
+            PokeError::StackTooDeep(sp) => write!(f, "SP {} is deeper than the {} entry stack", sp, STACK_SIZE),
+            PokeError::NotAByte(value) => write!(f, "{:#X} doesn't fit in a byte", value),
+            PokeError::NoSuchRegister(x) => write!(f, "there is no V{}", x),
+        }
+    }
+}
+
+impl std::error::Error for PokeError {}
+
+impl MachineState<'_> {
+    pub fn register(&self, reg: Register) -> u16 {
+        match reg {
+            Register::V(x) => self.v[x] as u16,
+            Register::I => self.i,
+            Register::Pc => self.pc,
+            Register::Sp => self.sp,
+            Register::Dt => self.dt as u16,
+            Register::St => self.st as u16,
+        }
+    }
+
+    // The two bytes at `addr` as an opcode, 0 past the end of RAM
+    pub fn opcode(&self, addr: u16) -> u16 {
+        let byte = |a: usize| self.ram.get(a).copied().unwrap_or(0) as u16;
+        byte(addr as usize) << 8 | byte(addr as usize + 1)
+    }
+}
+
+// Changes the machine from outside, see EMU::poke()
+pub struct Poke<'a> {
+    emu: &'a mut EMU,
+}
+
+impl Poke<'_> {
+    pub fn set(&mut self, reg: Register, value: u16) -> Result<(), PokeError> {
+        let byte = || u8::try_from(value).map_err(|_| PokeError::NotAByte(value));
+        match reg {
+            Register::V(x) if x >= NUM_REGS => return Err(PokeError::NoSuchRegister(x)),
+            Register::V(x) => self.emu.v_reg[x] = byte()?,
+            // sprites and FX55/FX65 read and write from I on
+            Register::I if value as usize >= RAM_SIZE => return Err(PokeError::OutsideRam(value as usize)),
+            Register::I => self.emu.i_reg = value,
+            // the fetch reads two bytes
+            Register::Pc if value as usize + 1 >= RAM_SIZE => return Err(PokeError::OutsideRam(value as usize)),
+            Register::Pc => self.emu.program_counter = value,
+            Register::Sp if value as usize > STACK_SIZE => return Err(PokeError::StackTooDeep(value)),
+            Register::Sp => self.emu.sp = value,
+            Register::Dt => self.emu.dt = byte()?,
+            Register::St => self.emu.st = byte()?,
+        }
+        Ok(())
+    }
+
+    pub fn write_ram(&mut self, addr: u16, bytes: &[u8]) -> Result<(), PokeError> {
+        let start = addr as usize;
+        let end = start + bytes.len();
+        if end > RAM_SIZE {
+            return Err(PokeError::OutsideRam(start.max(RAM_SIZE)));
+        }
+        self.emu.ram[start..end].copy_from_slice(bytes);
+        self.emu.decoded.invalidate(start..end);
+        Ok(())
+    }
+
+    // Replaces the call stack, the innermost call last
+    pub fn set_stack(&mut self, stack: &[u16]) -> Result<(), PokeError> {
+        if stack.len() > STACK_SIZE {
+            return Err(PokeError::StackTooDeep(stack.len() as u16));
+        }
+        self.emu.stack[..stack.len()].copy_from_slice(stack);
+        self.emu.sp = stack.len() as u16;
+        Ok(())
+    }
+}
+
+impl EMU {
+    pub fn state(&self) -> MachineState<'_> {
+        MachineState {
+            pc: self.program_counter,
+            i: self.i_reg,
+            v: &self.v_reg,
+            sp: self.sp,
+            stack: &self.stack[..(self.sp as usize).min(STACK_SIZE)],
+            dt: self.dt,
+            st: self.st,
+            keys: &self.keys,
+            ram: &self.ram,
+        }
+    }
+
+    // For tools only: nothing a game does needs this
+    pub fn poke(&mut self) -> Poke<'_> {
+        Poke { emu: self }
+    }
+}
+
+// An address the way tools take them, in hex with or without 0x: "2A4" or "0x2A4"
+pub fn parse_address(text: &str) -> Result<u16, String> {
+    let digits = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")).unwrap_or(text);
+    // from_str_radix() takes a sign too
+    let addr = Some(digits)
+        .filter(|digits| digits.bytes().all(|b| b.is_ascii_hexdigit()))
+        .and_then(|digits| u32::from_str_radix(digits, 16).ok())
+        .ok_or_else(|| format!("'{}' is not an address", text))?;
+    if addr as usize >= RAM_SIZE {
+        return Err(format!("{:#X} is outside of RAM", addr));
+    }
+    Ok(addr as u16)
+}
+
+impl FromStr for Register {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let lower = s.to_ascii_lowercase();
+        match lower.as_str() {
+            "i" => Ok(Register::I),
+            "pc" => Ok(Register::Pc),
+            "sp" => Ok(Register::Sp),
+            "dt" => Ok(Register::Dt),
+            "st" => Ok(Register::St),
+            _ => lower
+                .strip_prefix('v')
+                .filter(|x| x.len() == 1)
+                .and_then(|x| usize::from_str_radix(x, 16).ok())
+                .map(Register::V)
+                .ok_or_else(|| format!("Unknown register '{}' (expected V0-VF, I, PC, SP, DT or ST)", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn poke_and_look() {
+        let mut emu = EMU::new();
+        emu.poke().set(Register::V(3), 0x10).unwrap();
+        emu.poke().set(Register::Pc, 0x300).unwrap();
+        emu.poke().write_ram(0x300, &[0x12, 0x34]).unwrap();
+        emu.poke().set_stack(&[0x202, 0x250]).unwrap();
+        let state = emu.state();
+        assert_eq!(state.v[3], 0x10);
+        assert_eq!(state.register(Register::Pc), 0x300);
+        assert_eq!(state.opcode(0x300), 0x1234);
+        assert_eq!(state.stack, &[0x202, 0x250]);
+        assert_eq!(state.sp, 2);
+    }
+
+    #[test]
+    fn bad_values_are_refused() {
+        let mut emu = EMU::new();
+        let mut poke = emu.poke();
+        assert_eq!(poke.set(Register::V(0), 0x100), Err(PokeError::NotAByte(0x100)));
+        assert_eq!(poke.set(Register::V(16), 0), Err(PokeError::NoSuchRegister(16)));
+        assert_eq!(poke.set(Register::Sp, 17), Err(PokeError::StackTooDeep(17)));
+        assert_eq!(poke.set(Register::Pc, 0xFFF), Err(PokeError::OutsideRam(0xFFF)));
+        assert_eq!(poke.set(Register::I, 0x1000), Err(PokeError::OutsideRam(0x1000)));
+        assert_eq!(poke.set(Register::I, 0xFFF), Ok(()));
+        assert_eq!(poke.write_ram(0xFFE, &[1, 2, 3]), Err(PokeError::OutsideRam(0x1000)));
+        assert_eq!(emu.state().ram[0xFFE], 0);
+    }
+
+    #[test]
+    fn addresses_parse() {
+        assert_eq!(parse_address("2a4"), Ok(0x2A4));
+        assert_eq!(parse_address("0x2A4"), Ok(0x2A4));
+        assert_eq!(parse_address("0XFFF"), Ok(0xFFF));
+        assert_eq!(parse_address("0x1000"), Err("0x1000 is outside of RAM".to_string()));
+        assert_eq!(parse_address("pc"), Err("'pc' is not an address".to_string()));
+        assert!(parse_address("").is_err());
+        assert!(parse_address("+200").is_err());
+    }
+
+    #[test]
+    fn registers_parse() {
+        assert_eq!("v3".parse(), Ok(Register::V(3)));
+        assert_eq!("VF".parse(), Ok(Register::V(15)));
+        assert_eq!("pc".parse(), Ok(Register::Pc));
+        assert!("v10".parse::<Register>().is_err());
+    }
+}
@@ -0,0 +1,373 @@
+// Execution profiler: how often every instruction ran, which kinds of instructions the time
+// goes to, which subroutines it's spent in and which loops take the most of it.
+//
+// Time is counted in instructions, the interpreter runs a fixed number of them per frame.
+// Subroutines are followed through 2NNN and 00EE, so a ROM that leaves a subroutine with a
+// jump or changes SP by hand will confuse the call stack. A return without a matching call
+// is ignored.
+
+use crate::disasm::listing;
+use crate::{EMU, RAM_SIZE, START_ADDR};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+
+// lines in each table of the report
+const REPORT_LINES: usize = 10;
+
+pub struct Profiler {
+    counts: Vec<u64>, // per address
+    classes: BTreeMap<&'static str, u64>,
+    // Call tree, node 0 being the code outside any subroutine. Every call path gets its own
+    // node, so counting an instruction is just bumping the current one.
+    nodes: Vec<Node>,
+    children: HashMap<(usize, u16), usize>,
+    current: usize,
+    back_edges: HashMap<(u16, u16), u64>, // (loop start, jump back from) -> times taken
+    instructions: u64,
+}
+
+struct Node {
+    parent: usize,
+    entry: u16, // of the subroutine
+    calls: u64,
+    instructions: u64, // run in this subroutine itself
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Subroutine {
+    pub entry: u16,
+    pub calls: u64,
+    pub own: u64,   // instructions run in the subroutine itself
+    pub total: u64, // including the subroutines it called
+}
+
+// Code between a backwards jump (or FX0A waiting for a key) and where it lands
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Loop {
+    pub start: u16,
+    pub end: u16, // address of the instruction jumping back
+    pub iterations: u64,
+    pub instructions: u64, // run between start and end, not counting subroutines called
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Profiler {
+            counts: vec![0; RAM_SIZE],
+            classes: BTreeMap::new(),
+            nodes: vec![Node { parent: 0, entry: START_ADDR, calls: 1, instructions: 0 }],
+            children: HashMap::new(),
+            current: 0,
+            back_edges: HashMap::new(),
+            instructions: 0,
+        }
+    }
+
+    // Runs one instruction and counts it
+    pub fn tick(&mut self, emu: &mut EMU) {
+        let pc = emu.program_counter;
+        let op = emu.state().opcode(pc);
+        emu.tick();
+        self.record(pc, op, emu.program_counter);
+    }
+
+    // Counts the instruction `op` at `pc`, after which the machine went on at `next`
+    pub fn record(&mut self, pc: u16, op: u16, next: u16) {
+        self.instructions += 1;
+        if let Some(count) = self.counts.get_mut(pc as usize) {
+            *count += 1;
+        }
+        *self.classes.entry(class(op)).or_default() += 1;
+        self.nodes[self.current].instructions += 1;
+
+        if op >> 12 == 2 {
+            let entry = op & 0xFFF;
+            let parent = self.current;
+            let next_node = self.nodes.len();
+            let node = *self.children.entry((parent, entry)).or_insert(next_node);
+            if node == next_node {
+                self.nodes.push(Node { parent, entry, calls: 0, instructions: 0 });
+            }
+            self.nodes[node].calls += 1;
+            self.current = node;
+        } else if op == 0x00EE {
+            self.current = self.nodes[self.current].parent;
+        } else if next <= pc {
+            *self.back_edges.entry((next, pc)).or_default() += 1;
+        }
+    }
+
+    pub fn instructions(&self) -> u64 {
+        self.instructions
+    }
+
+    // Times the instruction at each address ran
+    pub fn counts(&self) -> &[u64] {
+        &self.counts
+    }
+
+    // The busiest addresses first
+    pub fn hottest(&self) -> Vec<(u16, u64)> {
+        let mut hot: Vec<(u16, u64)> = self.counts.iter().enumerate()
+            .filter(|(_, count)| **count > 0)
+            .map(|(addr, count)| (addr as u16, *count))
+            .collect();
+        hot.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        hot
+    }
+
+    // Instructions run per opcode class, like "DXYN" or "8XY4"
+    pub fn classes(&self) -> Vec<(&'static str, u64)> {
+        let mut classes: Vec<(&'static str, u64)> = self.classes.iter().map(|(class, count)| (*class, *count)).collect();
+        classes.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        classes
+    }
+
+    // Every subroutine that was called, the most expensive first. The code outside any
+    // subroutine comes first as the entry point.
+    pub fn subroutines(&self) -> Vec<Subroutine> {
+        // children always come after their parents, so one pass backwards adds up the subtrees
+        let mut subtree: Vec<u64> = self.nodes.iter().map(|node| node.instructions).collect();
+        for node in (1..self.nodes.len()).rev() {
+            subtree[self.nodes[node].parent] += subtree[node];
+        }
+
+        let mut subroutines: BTreeMap<u16, Subroutine> = BTreeMap::new();
+        for (index, node) in self.nodes.iter().enumerate() {
+            let sub = subroutines.entry(node.entry).or_insert(Subroutine { entry: node.entry, calls: 0, own: 0, total: 0 });
+            sub.calls += node.calls;
+            sub.own += node.instructions;
+            // recursive calls are already counted in the outermost one
+            if !self.inside(index, node.entry) {
+                sub.total += subtree[index];
+            }
+        }
+        let main = subroutines.remove(&START_ADDR).filter(|_| self.nodes.iter().skip(1).all(|node| node.entry != START_ADDR));
+        let mut subroutines: Vec<Subroutine> = subroutines.into_values().collect();
+        subroutines.sort_by(|a, b| b.total.cmp(&a.total).then(a.entry.cmp(&b.entry)));
+        match main {
+            Some(main) => std::iter::once(main).chain(subroutines).collect(),
+            None => subroutines,
+        }
+    }
+
+    // Whether an ancestor of the node is the subroutine at `entry` as well
+    fn inside(&self, mut node: usize, entry: u16) -> bool {
+        while node != 0 {
+            node = self.nodes[node].parent;
+            if self.nodes[node].entry == entry {
+                return true;
+            }
+        }
+        false
+    }
+
+    // The loops that took the most instructions first
+    pub fn loops(&self) -> Vec<Loop> {
+        let mut loops: Vec<Loop> = self.back_edges.iter()
+            .map(|(&(start, end), &iterations)| Loop {
+                start,
+                end,
+                iterations,
+                instructions: self.counts[start as usize..=end as usize].iter().sum(),
+            })
+            .collect();
+        loops.sort_by(|a, b| b.instructions.cmp(&a.instructions).then(a.start.cmp(&b.start)));
+        loops
+    }
+
+    // Call stacks in the folded format flamegraph.pl and inferno read,
+    // "main;sub_2A4;sub_2F0 1234" per line
+    pub fn folded(&self) -> String {
+        let mut lines = Vec::new();
+        for (index, node) in self.nodes.iter().enumerate() {
+            if node.instructions == 0 {
+                continue;
+            }
+            let mut names = vec![];
+            let mut at = index;
+            while at != 0 {
+                names.push(name(self.nodes[at].entry));
+                at = self.nodes[at].parent;
+            }
+            names.push("main".to_string());
+            names.reverse();
+            lines.push(format!("{} {}", names.join(";"), node.instructions));
+        }
+        lines.sort();
+        lines.iter().map(|line| format!("{}\n", line)).collect()
+    }
+
+    // Disassembly of the code that ran, each instruction with how often it ran and its share
+    // of the time, and a label above every subroutine
+    pub fn annotate(&self, ram: &[u8]) -> String {
+        let ran = |addr: usize| self.counts.get(addr).is_some_and(|&count| count > 0);
+        let (Some(first), Some(last)) = ((0..RAM_SIZE).find(|&a| ran(a)), (0..RAM_SIZE).rfind(|&a| ran(a))) else {
+            return String::new();
+        };
+        let entries: Vec<u16> = self.nodes.iter().skip(1).map(|node| node.entry).collect();
+
+        let mut out = String::new();
+        let mut addr = first;
+        while addr <= last {
+            // code at odd addresses: get back in step with what actually ran
+            if !ran(addr) && ran(addr + 1) {
+                addr += 1;
+            }
+            if entries.contains(&(addr as u16)) {
+                let _ = writeln!(out, "\n{}:", name(addr as u16));
+            }
+            let line = listing(ram, addr as u16, 1).pop().unwrap_or_default();
+            let count = self.counts[addr];
+            if count == 0 {
+                let _ = writeln!(out, "{:>22}  {}", "", line);
+            } else {
+                let share = 100.0 * count as f64 / self.instructions as f64;
+                let _ = writeln!(out, "{:>12} {:>6.2}%   {}", count, share, line);
+            }
+            addr += 2;
+        }
+        out
+    }
+
+    // Summary of the tables above, for people
+    pub fn report(&self, ram: &[u8]) -> String {
+        let share = |count: u64| 100.0 * count as f64 / self.instructions.max(1) as f64;
+        let mut out = format!("{} instructions\n", self.instructions);
+
+        out += "\nhottest instructions\n";
+        for (addr, count) in self.hottest().into_iter().take(REPORT_LINES) {
+            let line = listing(ram, addr, 1).pop().unwrap_or_default();
+            let _ = writeln!(out, "{:>12} {:>6.2}%   {}", count, share(count), line);
+        }
+
+        out += "\ninstruction classes\n";
+        for (class, count) in self.classes().into_iter().take(REPORT_LINES) {
+            let _ = writeln!(out, "{:>12} {:>6.2}%   {}", count, share(count), class);
+        }
+
+        let _ = writeln!(out, "\n{:<14} {:>8} {:>20} {:>20}", "subroutines", "calls", "own", "total");
+        for sub in self.subroutines().into_iter().take(REPORT_LINES) {
+            let name = if sub.entry == START_ADDR {"main".to_string()} else {name(sub.entry)};
+            let _ = writeln!(
+                out,
+                "  {:<12} {:>8} {:>12} {:>6.2}% {:>12} {:>6.2}%",
+                name, sub.calls, sub.own, share(sub.own), sub.total, share(sub.total)
+            );
+        }
+
+        out += "\nhottest loops\n";
+        for l in self.loops().into_iter().take(REPORT_LINES) {
+            let _ = writeln!(
+                out,
+                "{:>12} {:>6.2}%   {:#05X}-{:#05X}, {} times round",
+                l.instructions, share(l.instructions), l.start, l.end, l.iterations
+            );
+        }
+        out
+    }
+}
+
+fn name(entry: u16) -> String {
+    format!("sub_{:03X}", entry)
+}
+
+// The opcode pattern the instruction belongs to
+fn class(op: u16) -> &'static str {
+    match (op >> 12, op >> 4 & 0xF, op & 0xF) {
+        _ if op == 0x0000 => "0000",
+        _ if op == 0x00E0 => "00E0",
+        _ if op == 0x00EE => "00EE",
+        (0x0, _, _) => "0NNN",
+        (0x1, _, _) => "1NNN",
+        (0x2, _, _) => "2NNN",
+        (0x3, _, _) => "3XNN",
+        (0x4, _, _) => "4XNN",
+        (0x5, _, 0) => "5XY0",
+        (0x6, _, _) => "6XNN",
+        (0x7, _, _) => "7XNN",
+        (0x8, _, 0x0) => "8XY0",
+        (0x8, _, 0x1) => "8XY1",
+        (0x8, _, 0x2) => "8XY2",
+        (0x8, _, 0x3) => "8XY3",
+        (0x8, _, 0x4) => "8XY4",
+        (0x8, _, 0x5) => "8XY5",
+        (0x8, _, 0x6) => "8XY6",
+        (0x8, _, 0x7) => "8XY7",
+        (0x8, _, 0xE) => "8XYE",
+        (0x9, _, 0) => "9XY0",
+        (0xA, _, _) => "ANNN",
+        (0xB, _, _) => "BNNN",
+        (0xC, _, _) => "CXNN",
+        (0xD, _, _) => "DXYN",
+        (0xE, 0x9, 0xE) => "EX9E",
+        (0xE, 0xA, 0x1) => "EXA1",
+        (0xF, 0x0, 0x7) => "FX07",
+        (0xF, 0x0, 0xA) => "FX0A",
+        (0xF, 0x1, 0x5) => "FX15",
+        (0xF, 0x1, 0x8) => "FX18",
+        (0xF, 0x1, 0xE) => "FX1E",
+        (0xF, 0x2, 0x9) => "FX29",
+        (0xF, 0x3, 0x3) => "FX33",
+        (0xF, 0x5, 0x5) => "FX55",
+        (0xF, 0x6, 0x5) => "FX65",
+        _ => "other",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_calls_and_loops() {
+        let rom = [
+            0x60, 0x03, // 200: LD V0, 3
+            0x22, 0x0A, // 202: CALL 20A
+            0x70, 0xFF, // 204: ADD V0, FF
+            0x30, 0x00, // 206: SE V0, 0
+            0x12, 0x02, // 208: JP 202
+            0x71, 0x01, // 20A: ADD V1, 1
+            0x00, 0xEE, // 20C: RET
+        ];
+        let mut emu = EMU::new();
+        emu.load(&rom);
+        let mut profiler = Profiler::new();
+        // three times round the loop, then stop at the final skip
+        for _ in 0..1 + 3 * 6 - 1 {
+            profiler.tick(&mut emu);
+        }
+        assert_eq!(profiler.instructions(), 18);
+        assert_eq!(profiler.counts()[0x20A], 3);
+        assert_eq!(profiler.counts()[0x208], 2);
+        assert_eq!(profiler.classes()[0], ("7XNN", 6));
+
+        let subs = profiler.subroutines();
+        assert_eq!(subs[0], Subroutine { entry: 0x200, calls: 1, own: 12, total: 18 });
+        assert_eq!(subs[1], Subroutine { entry: 0x20A, calls: 3, own: 6, total: 6 });
+        assert_eq!(profiler.folded(), "main 12\nmain;sub_20A 6\n");
+
+        let loops = profiler.loops();
+        assert_eq!(loops, vec![Loop { start: 0x202, end: 0x208, iterations: 2, instructions: 11 }]);
+        assert!(profiler.annotate(&emu.state().ram[..]).contains("\nsub_20A:\n"));
+    }
+
+    #[test]
+    fn recursion_is_counted_once() {
+        let mut profiler = Profiler::new();
+        profiler.record(0x200, 0x2300, 0x300);
+        profiler.record(0x300, 0x2300, 0x300);
+        profiler.record(0x300, 0x00EE, 0x302);
+        profiler.record(0x302, 0x00EE, 0x202);
+        profiler.record(0x202, 0x00EE, 0x202); // no call to return from
+        let subs = profiler.subroutines();
+        assert_eq!(subs[0], Subroutine { entry: 0x200, calls: 1, own: 2, total: 5 });
+        assert_eq!(subs[1], Subroutine { entry: 0x300, calls: 2, own: 3, total: 3 });
+    }
+}
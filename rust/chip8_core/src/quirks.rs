@@ -0,0 +1,171 @@
+use std::fmt;
+use std::str::FromStr;
+
+// Platforms whose behaviour can be emulated.
+// Only the quirk profile differs between them for now, SCHIP and XO-CHIP
+// instructions are not implemented.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Variant {
+    Chip8,     // COSMAC VIP
+    SuperChip, // SCHIP 1.1 on the HP48
+    XoChip,    // Octo
+}
+
+// Behaviours that differ between CHIP-8 interpreters.
+// Quirks::default() is what this emulator has always done.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Quirks {
+    pub shift_uses_vy: bool,           // 8XY6/8XYE shift VY into VX instead of shifting VX
+    pub load_store_increments_i: bool, // FX55/FX65 leave I pointing past the last register
+    pub jump_uses_vx: bool,            // BXNN jumps to XNN + VX instead of NNN + V0
+    pub vf_reset: bool,                // 8XY1/8XY2/8XY3 clear VF
+    pub clip_sprites: bool,            // sprites are cut at the screen edge instead of wrapping
+}
+
+impl Quirks {
+    pub const NAMES: [&'static str; 5] = ["shift", "load-store", "jump", "vf-reset", "clip"];
+
+    pub fn for_variant(variant: Variant) -> Self {
+        match variant {
+            Variant::Chip8 => Quirks {
+                shift_uses_vy: true,
+                load_store_increments_i: true,
+                jump_uses_vx: false,
+                vf_reset: true,
+                clip_sprites: true,
+            },
+            Variant::SuperChip => Quirks {
+                shift_uses_vy: false,
+                load_store_increments_i: false,
+                jump_uses_vx: true,
+                vf_reset: false,
+                clip_sprites: true,
+            },
+            Variant::XoChip => Quirks {
+                shift_uses_vy: true,
+                load_store_increments_i: true,
+                jump_uses_vx: false,
+                vf_reset: false,
+                clip_sprites: false,
+            },
+        }
+    }
+
+    // The variant's quirks, or the defaults without one, and a list for apply_list on top
+    pub fn with_list(variant: Option<Variant>, list: Option<&str>) -> Result<Self, String> {
+        let mut quirks = variant.map(Quirks::for_variant).unwrap_or_default();
+        if let Some(list) = list {
+            quirks.apply_list(list)?;
+        }
+        Ok(quirks)
+    }
+
+    // Turn a quirk on or off by one of the names in NAMES, false for unknown names
+    pub fn set(&mut self, name: &str, on: bool) -> bool {
+        let quirk = match name {
+            "shift" => &mut self.shift_uses_vy,
+            "load-store" => &mut self.load_store_increments_i,
+            "jump" => &mut self.jump_uses_vx,
+            "vf-reset" => &mut self.vf_reset,
+            "clip" => &mut self.clip_sprites,
+            _ => return false,
+        };
+        *quirk = on;
+        true
+    }
+
+    // Applies a list like "shift,-clip": plain names turn quirks on, a leading '-' turns them off
+    pub fn apply_list(&mut self, list: &str) -> Result<(), String> {
+        for item in list.split(',').map(str::trim).filter(|item| !item.is_empty()) {
+            let (name, on) = match item.strip_prefix('-') {
+                Some(name) => (name, false),
+                None => (item.strip_prefix('+').unwrap_or(item), true),
+            };
+            if !self.set(name, on) {
+                return Err(format!("Unknown quirk '{}' (expected one of {})", name, Self::NAMES.join(", ")));
+            }
+        }
+        Ok(())
+    }
+
+    // What differs from `base`, as a list apply_list understands
+    pub fn diff(&self, base: &Quirks) -> String {
+        let items: Vec<String> = Self::NAMES
+            .iter()
+            .zip(self.enabled().into_iter().zip(base.enabled()))
+            .filter(|(_, (on, was))| on != was)
+            .map(|(name, (on, _))| if on {name.to_string()} else {format!("-{}", name)})
+            .collect();
+        items.join(",")
+    }
+
+    fn enabled(&self) -> [bool; 5] {
+        [self.shift_uses_vy, self.load_store_increments_i, self.jump_uses_vx, self.vf_reset, self.clip_sprites]
+    }
+}
+
+// The enabled quirks as a list apply_list understands
+impl fmt::Display for Quirks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names: Vec<&str> = Self::NAMES
+            .iter()
+            .zip(self.enabled())
+            .filter(|(_, on)| *on)
+            .map(|(name, _)| *name)
+            .collect();
+        write!(f, "{}", names.join(","))
+    }
+}
+
+impl FromStr for Variant {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "chip8" | "chip-8" => Ok(Variant::Chip8),
+            "schip" | "superchip" => Ok(Variant::SuperChip),
+            "xochip" | "xo-chip" => Ok(Variant::XoChip),
+            _ => Err(format!("Unknown variant '{}' (expected chip8, schip or xochip)", s)),
+        }
+    }
+}
+
+impl fmt::Display for Variant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Variant::Chip8 => "chip8",
+            Variant::SuperChip => "schip",
+            Variant::XoChip => "xochip",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quirk_lists() {
+        let mut quirks = Quirks::for_variant(Variant::Chip8);
+        quirks.apply_list(" -clip, +jump,shift ,").unwrap();
+        assert!(!quirks.clip_sprites && quirks.jump_uses_vx && quirks.shift_uses_vy);
+        assert_eq!(quirks.to_string(), "shift,load-store,jump,vf-reset");
+        assert_eq!(quirks.diff(&Quirks::for_variant(Variant::Chip8)), "jump,-clip");
+
+        let mut again = Quirks::for_variant(Variant::Chip8);
+        again.apply_list(&quirks.diff(&again)).unwrap();
+        assert_eq!(again, quirks);
+
+        let before = quirks;
+        assert_eq!(
+            quirks.apply_list("vf-reset,wrap"),
+            Err("Unknown quirk 'wrap' (expected one of shift, load-store, jump, vf-reset, clip)".to_string())
+        );
+        assert_eq!(quirks.diff(&before), "");
+
+        assert_eq!(Quirks::with_list(None, None), Ok(Quirks::default()));
+        assert_eq!(Quirks::with_list(Some(Variant::Chip8), Some("jump,-clip")), Ok(quirks));
+        assert!(Quirks::with_list(Some(Variant::XoChip), Some("wrap")).is_err());
+    }
+}
@@ -0,0 +1,463 @@
+// RGBA framebuffer rendering
+//
+// Frontends that just want pixels can call EMU::render_rgba instead of walking
+// get_display() themselves. Rendering works on palette indices (0 = off, 1 = first plane,
+// 2 = second plane, 3 = both, like XO-CHIP), so any resolution and any number of planes
+// go through the same code.
+//
+// Scale2x/Scale3x/EPX only compare indices for equality, so they never blend colors.
+
+use crate::{EMU, SCREEN_HEIGHT, SCREEN_WIDTH};
+use std::fmt;
+use std::str::FromStr;
+
+pub const BYTES_PER_PIXEL: usize = 4;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Palette {
+    pub colors: [[u8; 4]; 4], // RGBA for palette index 0-3
+}
+
+impl Palette {
+    // white on black, the second plane in greys
+    pub const MONO: Palette = Palette {
+        colors: [
+            [0x00, 0x00, 0x00, 0xFF],
+            [0xFF, 0xFF, 0xFF, 0xFF],
+            [0xAA, 0xAA, 0xAA, 0xFF],
+            [0x55, 0x55, 0x55, 0xFF],
+        ],
+    };
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette::MONO
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScaleFilter {
+    Nearest, // any scale
+    Scale2x, // AdvMAME2x, scale has to be a multiple of 2
+    Scale3x, // AdvMAME3x, scale has to be a multiple of 3
+    Epx,     // Eric's Pixel Expansion, scale has to be a multiple of 2
+}
+
+impl ScaleFilter {
+    // how much the smoothing pass itself scales up
+    fn factor(self) -> usize {
+        match self {
+            ScaleFilter::Nearest => 1,
+            ScaleFilter::Scale2x | ScaleFilter::Epx => 2,
+            ScaleFilter::Scale3x => 3,
+        }
+    }
+
+    // Whether pictures can be scaled `scale` times with this filter
+    pub fn check_scale(self, scale: usize) -> Result<(), RenderError> {
+        if scale == 0 || !scale.is_multiple_of(self.factor()) {
+            return Err(RenderError::BadScale { scale, filter: self });
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum RenderError {
+    BadScale { scale: usize, filter: ScaleFilter },
+    BufferSize { expected: usize, actual: usize },
+    PixelCount { expected: usize, actual: usize }, // palette indices for width x height
+}
+
+impl fmt::Display for RenderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RenderError::BadScale { scale, filter } => {
+                write!(f, "scale {} is not a multiple of {} as {} needs", scale, filter.factor(), filter)
+            },
+            RenderError::BufferSize { expected, actual } => {
+                write!(f, "RGBA buffer is {} bytes, expected {}", actual, expected)
+            },
+            RenderError::PixelCount { expected, actual } => {
+                write!(f, "picture is {} pixels, expected {}", actual, expected)
+            },
+        }
+    }
+}
+
+impl std::error::Error for RenderError {}
+
+// Bytes needed for a width x height picture scaled up `scale` times
+pub fn rgba_len(width: usize, height: usize, scale: usize) -> usize {
+    width * scale * height * scale * BYTES_PER_PIXEL
+}
+
+// Renders palette indices (one byte per pixel, row by row) into `out`, which has to be
+// exactly rgba_len(width, height, scale) bytes
+pub fn render_indexed(
+    pixels: &[u8],
+    width: usize,
+    height: usize,
+    out: &mut [u8],
+    palette: &Palette,
+    scale: usize,
+    filter: ScaleFilter,
+) -> Result<(), RenderError> {
+    let expected = rgba_len(width, height, scale);
+    if out.len() != expected {
+        return Err(RenderError::BufferSize { expected, actual: out.len() });
+    }
+
+    let scaled = scale_indexed(pixels, width, height, scale, filter)?;
+    for (px, &index) in out.chunks_exact_mut(BYTES_PER_PIXEL).zip(&scaled) {
+        px.copy_from_slice(&palette.colors[(index & 3) as usize]);
+    }
+    Ok(())
+}
+
+// Scales palette indices up without turning them into colors, for indexed formats like GIF
+pub fn scale_indexed(pixels: &[u8], width: usize, height: usize, scale: usize, filter: ScaleFilter) -> Result<Vec<u8>, RenderError> {
+    filter.check_scale(scale)?;
+    if pixels.len() != width * height {
+        return Err(RenderError::PixelCount { expected: width * height, actual: pixels.len() });
+    }
+    let factor = filter.factor();
+
+    let smoothed;
+    let (src, src_width) = match filter {
+        ScaleFilter::Nearest => (pixels, width),
+        ScaleFilter::Scale2x => {
+            smoothed = scale2x(pixels, width, height);
+            (smoothed.as_slice(), width * 2)
+        },
+        ScaleFilter::Epx => {
+            smoothed = epx(pixels, width, height);
+            (smoothed.as_slice(), width * 2)
+        },
+        ScaleFilter::Scale3x => {
+            smoothed = scale3x(pixels, width, height);
+            (smoothed.as_slice(), width * 3)
+        },
+    };
+
+    // nearest neighbour for whatever scale the smoothing pass didn't cover
+    let rest = scale / factor;
+    let out_width = width * scale;
+    let mut out = vec![0; out_width * height * scale];
+    for (y, row) in out.chunks_exact_mut(out_width).enumerate() {
+        let src_row = &src[(y / rest) * src_width..][..src_width];
+        for (x, index) in row.iter_mut().enumerate() {
+            *index = src_row[x / rest];
+        }
+    }
+    Ok(out)
+}
+
+impl EMU {
+    // `out` has to be rgba_len(SCREEN_WIDTH, SCREEN_HEIGHT, scale) bytes
+    pub fn render_rgba(&self, out: &mut [u8], palette: &Palette, scale: usize, filter: ScaleFilter) -> Result<(), RenderError> {
+        render_indexed(&self.palette_indices(), SCREEN_WIDTH, SCREEN_HEIGHT, out, palette, scale, filter)
+    }
+
+    // The screen as palette indices, one byte per pixel
+    pub fn palette_indices(&self) -> Vec<u8> {
+        self.screen.iter().map(|&pixel| pixel as u8).collect()
+    }
+}
+
+// neighbour at (x + dx, y + dy), repeating the border
+fn at(pixels: &[u8], width: usize, height: usize, x: usize, y: usize, dx: isize, dy: isize) -> u8 {
+    let x = (x as isize + dx).clamp(0, width as isize - 1) as usize;
+    let y = (y as isize + dy).clamp(0, height as isize - 1) as usize;
+    pixels[x + width * y]
+}
+
+//   A        E0 E1
+// C P B  =>  E2 E3
+//   D
+fn scale2x(pixels: &[u8], width: usize, height: usize) -> Vec<u8> {
+    let mut out = vec![0; width * height * 4];
+    let out_width = width * 2;
+    for y in 0..height {
+        for x in 0..width {
+            let p = pixels[x + width * y];
+            let a = at(pixels, width, height, x, y, 0, -1);
+            let b = at(pixels, width, height, x, y, 1, 0);
+            let c = at(pixels, width, height, x, y, -1, 0);
+            let d = at(pixels, width, height, x, y, 0, 1);
+
+            let (mut e0, mut e1, mut e2, mut e3) = (p, p, p, p);
+            if a != d && c != b {
+                if c == a {e0 = a;}
+                if a == b {e1 = b;}
+                if c == d {e2 = c;}
+                if d == b {e3 = d;}
+            }
+
+            let (ox, oy) = (x * 2, y * 2);
+            out[ox + out_width * oy] = e0;
+            out[ox + 1 + out_width * oy] = e1;
+            out[ox + out_width * (oy + 1)] = e2;
+            out[ox + 1 + out_width * (oy + 1)] = e3;
+        }
+    }
+    out
+}
+
+// The original formulation: copy a neighbour pair into the corner between them, unless
+// three or more neighbours agree. Gives the same picture as Scale2x.
+fn epx(pixels: &[u8], width: usize, height: usize) -> Vec<u8> {
+    let mut out = vec![0; width * height * 4];
+    let out_width = width * 2;
+    for y in 0..height {
+        for x in 0..width {
+            let p = pixels[x + width * y];
+            let a = at(pixels, width, height, x, y, 0, -1);
+            let b = at(pixels, width, height, x, y, 1, 0);
+            let c = at(pixels, width, height, x, y, -1, 0);
+            let d = at(pixels, width, height, x, y, 0, 1);
+
+            let mut e = [p; 4];
+            if c == a {e[0] = a;}
+            if a == b {e[1] = b;}
+            if d == c {e[2] = c;}
+            if b == d {e[3] = d;}
+            let neighbours = [a, b, c, d];
+            if neighbours.iter().any(|&n| neighbours.iter().filter(|&&m| m == n).count() >= 3) {
+                e = [p; 4];
+            }
+
+            let (ox, oy) = (x * 2, y * 2);
+            out[ox + out_width * oy] = e[0];
+            out[ox + 1 + out_width * oy] = e[1];
+            out[ox + out_width * (oy + 1)] = e[2];
+            out[ox + 1 + out_width * (oy + 1)] = e[3];
+        }
+    }
+    out
+}
+
+// A B C      E0 E1 E2
+// D E F  =>  E3 E4 E5
+// G H I      E6 E7 E8
+fn scale3x(pixels: &[u8], width: usize, height: usize) -> Vec<u8> {
+    let mut out = vec![0; width * height * 9];
+    let out_width = width * 3;
+    for y in 0..height {
+        for x in 0..width {
+            let n = |dx, dy| at(pixels, width, height, x, y, dx, dy);
+            let (a, b, c) = (n(-1, -1), n(0, -1), n(1, -1));
+            let (d, e, f) = (n(-1, 0), n(0, 0), n(1, 0));
+            let (g, h, i) = (n(-1, 1), n(0, 1), n(1, 1));
+
+            let mut o = [e; 9];
+            if b != h && d != f {
+                o[0] = if d == b {d} else {e};
+                o[1] = if (d == b && e != c) || (b == f && e != a) {b} else {e};
+                o[2] = if b == f {f} else {e};
+                o[3] = if (d == b && e != g) || (d == h && e != a) {d} else {e};
+                o[5] = if (b == f && e != i) || (h == f && e != c) {f} else {e};
+                o[6] = if d == h {d} else {e};
+                o[7] = if (d == h && e != i) || (h == f && e != g) {h} else {e};
+                o[8] = if h == f {f} else {e};
+            }
+
+            for (k, &value) in o.iter().enumerate() {
+                out[x * 3 + k % 3 + out_width * (y * 3 + k / 3)] = value;
+            }
+        }
+    }
+    out
+}
+
+impl FromStr for ScaleFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "nearest" => Ok(ScaleFilter::Nearest),
+            "scale2x" => Ok(ScaleFilter::Scale2x),
+            "scale3x" => Ok(ScaleFilter::Scale3x),
+            "epx" => Ok(ScaleFilter::Epx),
+            _ => Err(format!("Unknown scale filter '{}' (expected nearest, scale2x, scale3x or epx)", s)),
+        }
+    }
+}
+
+impl fmt::Display for ScaleFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ScaleFilter::Nearest => "nearest",
+            ScaleFilter::Scale2x => "scale2x",
+            ScaleFilter::Scale3x => "scale3x",
+            ScaleFilter::Epx => "epx",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLACK: [u8; 4] = [0x00, 0x00, 0x00, 0xFF];
+    const WHITE: [u8; 4] = [0xFF, 0xFF, 0xFF, 0xFF];
+
+    // turns rendered RGBA back into palette indices, row by row
+    fn indices(out: &[u8], palette: &Palette) -> Vec<u8> {
+        out.chunks_exact(BYTES_PER_PIXEL)
+            .map(|px| palette.colors.iter().position(|c| c == px).unwrap() as u8)
+            .collect()
+    }
+
+    fn grid(rows: &[&str]) -> Vec<u8> {
+        rows.iter().flat_map(|row| row.bytes().map(|b| b - b'0')).collect()
+    }
+
+    fn render(pixels: &[u8], width: usize, height: usize, scale: usize, filter: ScaleFilter) -> Vec<u8> {
+        let mut out = vec![0; rgba_len(width, height, scale)];
+        render_indexed(pixels, width, height, &mut out, &Palette::MONO, scale, filter).unwrap();
+        indices(&out, &Palette::MONO)
+    }
+
+    #[test]
+    fn nearest_repeats_every_pixel() {
+        let pixels = grid(&["10", "01"]);
+        let out = render(&pixels, 2, 2, 2, ScaleFilter::Nearest);
+        assert_eq!(out, grid(&["1100", "1100", "0011", "0011"]));
+    }
+
+    #[test]
+    fn nearest_maps_every_plane_to_its_color() {
+        let palette = Palette::MONO;
+        let mut out = vec![0; rgba_len(4, 1, 1)];
+        render_indexed(&[0, 1, 2, 3], 4, 1, &mut out, &palette, 1, ScaleFilter::Nearest).unwrap();
+        assert_eq!(&out[0..4], &BLACK);
+        assert_eq!(&out[4..8], &WHITE);
+        assert_eq!(&out[8..12], &palette.colors[2]);
+        assert_eq!(&out[12..16], &palette.colors[3]);
+    }
+
+    #[test]
+    fn scale2x_smooths_a_diagonal() {
+        let pixels = grid(&["0000", "0100", "0010", "0000"]);
+        let out = render(&pixels, 4, 4, 2, ScaleFilter::Scale2x);
+        assert_eq!(out, grid(&[
+            "00000000",
+            "00000000",
+            "00110000",
+            "00111000",
+            "00011100",
+            "00001100",
+            "00000000",
+            "00000000",
+        ]));
+    }
+
+    #[test]
+    fn scale2x_rounds_the_corners_of_a_block() {
+        let pixels = grid(&["0000", "0110", "0110", "0000"]);
+        let out = render(&pixels, 4, 4, 2, ScaleFilter::Scale2x);
+        assert_eq!(out, grid(&[
+            "00000000",
+            "00000000",
+            "00011000",
+            "00111100",
+            "00111100",
+            "00011000",
+            "00000000",
+            "00000000",
+        ]));
+    }
+
+    #[test]
+    fn epx_matches_scale2x() {
+        let pixels = grid(&["10110", "01001", "11100", "00101", "10011"]);
+        assert_eq!(
+            render(&pixels, 5, 5, 2, ScaleFilter::Epx),
+            render(&pixels, 5, 5, 2, ScaleFilter::Scale2x),
+        );
+    }
+
+    #[test]
+    fn scale3x_smooths_a_diagonal() {
+        let pixels = grid(&["0000", "0100", "0010", "0000"]);
+        let out = render(&pixels, 4, 4, 3, ScaleFilter::Scale3x);
+        assert_eq!(out, grid(&[
+            "000000000000",
+            "000000000000",
+            "000000000000",
+            "000111000000",
+            "000111000000",
+            "000111100000",
+            "000001111000",
+            "000000111000",
+            "000000111000",
+            "000000000000",
+            "000000000000",
+            "000000000000",
+        ]));
+    }
+
+    #[test]
+    fn smoothing_is_followed_by_nearest_for_bigger_scales() {
+        let pixels = grid(&["100", "010", "001"]);
+        let smoothed = render(&pixels, 3, 3, 2, ScaleFilter::Scale2x);
+        let out = render(&pixels, 3, 3, 4, ScaleFilter::Scale2x);
+        assert_eq!(out, render(&smoothed, 6, 6, 2, ScaleFilter::Nearest));
+    }
+
+    #[test]
+    fn lores_and_hires_render_the_same_way() {
+        // a hires picture that is a lores one doubled renders like the lores one at twice the scale
+        let lores = grid(&["1001", "0110"]);
+        let hires: Vec<u8> = (0..4)
+            .flat_map(|y| (0..8).map(move |x| (x, y)))
+            .map(|(x, y)| lores[x / 2 + 4 * (y / 2)])
+            .collect();
+        assert_eq!(render(&lores, 4, 2, 4, ScaleFilter::Nearest), render(&hires, 8, 4, 2, ScaleFilter::Nearest));
+    }
+
+    #[test]
+    fn rejects_bad_scales_and_buffers() {
+        let mut out = vec![0; rgba_len(2, 2, 3)];
+        assert_eq!(
+            render_indexed(&[0; 4], 2, 2, &mut out, &Palette::MONO, 3, ScaleFilter::Scale2x),
+            Err(RenderError::BadScale { scale: 3, filter: ScaleFilter::Scale2x }),
+        );
+        assert_eq!(
+            render_indexed(&[0; 4], 2, 2, &mut out, &Palette::MONO, 2, ScaleFilter::Nearest),
+            Err(RenderError::BufferSize { expected: rgba_len(2, 2, 2), actual: rgba_len(2, 2, 3) }),
+        );
+        let mut out = vec![0; rgba_len(2, 2, 2)];
+        for filter in [ScaleFilter::Nearest, ScaleFilter::Scale2x, ScaleFilter::Epx] {
+            assert_eq!(
+                render_indexed(&[0; 3], 2, 2, &mut out, &Palette::MONO, 2, filter),
+                Err(RenderError::PixelCount { expected: 4, actual: 3 }),
+            );
+        }
+        assert!(scale_indexed(&[0; 5], 2, 2, 3, ScaleFilter::Scale3x).is_err());
+    }
+
+    #[test]
+    fn renders_the_emulator_screen() {
+        let mut emu = EMU::new();
+        // I = font for 0, draw it at (0, 0)
+        emu.load(&[0x60, 0x00, 0xF0, 0x29, 0xD0, 0x05]);
+        for _ in 0..3 {
+            emu.tick();
+        }
+
+        let mut out = vec![0; rgba_len(SCREEN_WIDTH, SCREEN_HEIGHT, 1)];
+        emu.render_rgba(&mut out, &Palette::MONO, 1, ScaleFilter::Nearest).unwrap();
+        let pixel = |x: usize, y: usize| &out[(x + SCREEN_WIDTH * y) * BYTES_PER_PIXEL..][..BYTES_PER_PIXEL];
+        // top row of the 0 glyph is 0xF0
+        assert_eq!(pixel(0, 0), &WHITE);
+        assert_eq!(pixel(3, 0), &WHITE);
+        assert_eq!(pixel(4, 0), &BLACK);
+        // middle rows are 0x90
+        assert_eq!(pixel(1, 1), &BLACK);
+        assert_eq!(pixel(3, 1), &WHITE);
+    }
+}
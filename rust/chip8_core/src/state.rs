@@ -0,0 +1,149 @@
+// Save states
+//
+// Layout (big endian):
+//   "C8ST" | version | pc | i | sp | V0-VF | stack | dt | st | ram | screen, one bit per pixel
+// Key state, quirks and the random number generator are not part of a state.
+
+use crate::*;
+use std::fmt;
+
+const MAGIC: &[u8; 4] = b"C8ST";
+const VERSION: u8 = 1;
+const SCREEN_BYTES: usize = SCREEN_WIDTH * SCREEN_HEIGHT / 8;
+const STATE_SIZE: usize = 4 + 1 + 2 + 2 + 2 + NUM_REGS + STACK_SIZE * 2 + 1 + 1 + RAM_SIZE + SCREEN_BYTES;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum StateError {
+    NotAState,
+    UnsupportedVersion(u8),
+    WrongSize(usize),
+    Corrupt, // values that would crash the machine
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StateError::NotAState => write!(f, "not a CHIP-8 save state"),
+            StateError::UnsupportedVersion(v) => write!(f, "unsupported save state version {}", v),
+            StateError::WrongSize(len) => write!(f, "save state is {} bytes, expected {}", len, STATE_SIZE),
+            StateError::Corrupt => write!(f, "save state is corrupt"),
+        }
+    }
+}
+
+impl std::error::Error for StateError {}
+
+impl EMU {
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(STATE_SIZE);
+        data.extend_from_slice(MAGIC);
+        data.push(VERSION);
+        data.extend_from_slice(&self.program_counter.to_be_bytes());
+        data.extend_from_slice(&self.i_reg.to_be_bytes());
+        data.extend_from_slice(&self.sp.to_be_bytes());
+        data.extend_from_slice(&self.v_reg);
+        for addr in self.stack {
+            data.extend_from_slice(&addr.to_be_bytes());
+        }
+        data.push(self.dt);
+        data.push(self.st);
+        data.extend_from_slice(&self.ram);
+
+        for row in self.screen.chunks(8) {
+            let byte = row.iter().fold(0u8, |byte, &pixel| (byte << 1) | pixel as u8);
+            data.push(byte);
+        }
+        data
+    }
+
+    // The machine is left untouched when the state can't be loaded
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        if data.len() < 5 || &data[0..4] != MAGIC {
+            return Err(StateError::NotAState);
+        }
+        if data[4] != VERSION {
+            return Err(StateError::UnsupportedVersion(data[4]));
+        }
+        if data.len() != STATE_SIZE {
+            return Err(StateError::WrongSize(data.len()));
+        }
+
+        let mut pos = 5;
+        let mut take = |len: usize| {
+            let bytes = &data[pos..pos + len];
+            pos += len;
+            bytes
+        };
+        let word = |bytes: &[u8]| u16::from_be_bytes([bytes[0], bytes[1]]);
+
+        let program_counter = word(take(2));
+        let i_reg = word(take(2));
+        let sp = word(take(2));
+        // the PC has to leave room for both bytes of the opcode
+        if program_counter as usize + 1 >= RAM_SIZE || i_reg as usize >= RAM_SIZE || sp as usize > STACK_SIZE {
+            return Err(StateError::Corrupt);
+        }
+
+        self.program_counter = program_counter;
+        self.i_reg = i_reg;
+        self.sp = sp;
+        self.v_reg.copy_from_slice(take(NUM_REGS));
+        for (slot, bytes) in self.stack.iter_mut().zip(take(STACK_SIZE * 2).chunks(2)) {
+            *slot = word(bytes);
+        }
+        self.dt = take(1)[0];
+        self.st = take(1)[0];
+        self.ram.copy_from_slice(take(RAM_SIZE));
+        self.decoded.clear();
+
+        let screen = take(SCREEN_BYTES);
+        for (i, pixel) in self.screen.iter_mut().enumerate() {
+            *pixel = screen[i / 8] & (0b1000_0000 >> (i % 8)) != 0;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let mut emu = EMU::new();
+        emu.load(&[0x6A, 0x02, 0xA2, 0x34, 0x22, 0x08, 0x00, 0x00, 0xD0, 0x05]);
+        for _ in 0..4 {
+            emu.tick();
+        }
+        let saved = emu.save_state();
+        assert_eq!(saved.len(), STATE_SIZE);
+
+        let mut loaded = EMU::new();
+        loaded.load_state(&saved).unwrap();
+        assert_eq!(loaded.save_state(), saved);
+        assert_eq!((loaded.state().pc, loaded.state().i, loaded.state().stack), (0x20A, 0x234, &[0x206][..]));
+        assert_eq!(loaded.get_display(), emu.get_display());
+    }
+
+    #[test]
+    fn rejects_bad_states() {
+        let saved = EMU::new().save_state();
+        let mut emu = EMU::new();
+        assert_eq!(emu.load_state(b"C8"), Err(StateError::NotAState));
+        assert_eq!(emu.load_state(b"PNG\0\x01"), Err(StateError::NotAState));
+
+        let mut newer = saved.clone();
+        newer[4] = VERSION + 1;
+        assert_eq!(emu.load_state(&newer), Err(StateError::UnsupportedVersion(VERSION + 1)));
+        assert_eq!(emu.load_state(&saved[..saved.len() - 1]), Err(StateError::WrongSize(STATE_SIZE - 1)));
+
+        // pc, i and sp
+        for (at, value) in [(5, 0x0FFF), (5, 0x1000), (7, 0x1000), (9, STACK_SIZE as u16 + 1)] {
+            let mut corrupt = saved.clone();
+            corrupt[at..at + 2].copy_from_slice(&u16::to_be_bytes(value));
+            assert_eq!(emu.load_state(&corrupt), Err(StateError::Corrupt), "{:#X} at {}", value, at);
+        }
+        // nothing was loaded from those
+        assert_eq!(emu.save_state(), saved);
+    }
+}
@@ -0,0 +1,73 @@
+// Symbol maps: names for addresses in a ROM, from whatever assembled it. One label per line,
+// the address first, in hex with or without 0x:
+//
+//     0x200 main
+//     0x2A4 draw_paddle   # comments like this are fine
+//
+// Labels don't have to be in order. A label stands for the code from its address up to the
+// next label.
+
+use crate::parse_address;
+use std::collections::BTreeMap;
+
+#[derive(Clone, Debug, Default)]
+pub struct Symbols {
+    labels: BTreeMap<u16, String>,
+}
+
+impl Symbols {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut labels = BTreeMap::new();
+        for (n, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default();
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let [addr, name] = words[..] else {
+                if words.is_empty() {
+                    continue;
+                }
+                return Err(format!("line {}: expected an address and a label", n + 1));
+            };
+            let addr = parse_address(addr).map_err(|e| format!("line {}: {}", n + 1, e))?;
+            if let Some(old) = labels.insert(addr, name.to_string()) {
+                return Err(format!("line {}: {:#05X} is already labelled {}", n + 1, addr, old));
+            }
+        }
+        Ok(Symbols { labels })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.labels.is_empty()
+    }
+
+    // In address order
+    pub fn iter(&self) -> impl Iterator<Item = (u16, &str)> {
+        self.labels.iter().map(|(addr, name)| (*addr, name.as_str()))
+    }
+
+    // The label right at `addr`
+    pub fn get(&self, addr: u16) -> Option<&str> {
+        self.labels.get(&addr).map(String::as_str)
+    }
+
+    // The label whose code `addr` is in
+    pub fn containing(&self, addr: u16) -> Option<(u16, &str)> {
+        self.labels.range(..=addr).next_back().map(|(addr, name)| (*addr, name.as_str()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_and_look_up() {
+        let symbols = Symbols::parse("# PONG\n0x2A4 draw\n200 main  # start\n\n").unwrap();
+        assert_eq!(symbols.iter().collect::<Vec<_>>(), vec![(0x200, "main"), (0x2A4, "draw")]);
+        assert_eq!(symbols.get(0x2A4), Some("draw"));
+        assert_eq!(symbols.containing(0x2A3), Some((0x200, "main")));
+        assert_eq!(symbols.containing(0x100), None);
+        assert!(Symbols::parse("0x200").is_err());
+        assert!(Symbols::parse("main 0x200").is_err());
+        assert!(Symbols::parse("0x200 a\n0x200 b").is_err());
+    }
+}
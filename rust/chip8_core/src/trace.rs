@@ -0,0 +1,159 @@
+// Instruction traces, a line per instruction with the registers after it ran, for finding
+// where we go wrong compared with another emulator:
+//
+//     PC=0200 OP=6A02 V0=00 V1=00 ... VF=00 I=0000 SP=0 DT=00 ST=00 ; LD VA, #02
+//
+// Fields are KEY=hex separated by spaces, anything after ';' or '#' is a comment. Traces from other
+// emulators only need the fields they have in this form, diff() compares the ones both
+// traces have and ignores the rest, so converting one is mostly a matter of a sed script.
+
+use crate::disasm::disassemble;
+use crate::{MachineState, EMU};
+use std::fmt;
+use std::io::{self, BufRead, Write};
+use std::ops::RangeInclusive;
+
+// The trace line for the instruction `op` at `pc`, `state` being the machine after it ran
+pub fn line(pc: u16, op: u16, state: &MachineState) -> String {
+    let mut line = format!("PC={:04X} OP={:04X}", pc, op);
+    for (x, v) in state.v.iter().enumerate() {
+        line += &format!(" V{:X}={:02X}", x, v);
+    }
+    let mnemonic = disassemble(op).unwrap_or_else(|| "???".to_string());
+    line += &format!(" I={:04X} SP={:X} DT={:02X} ST={:02X} ; {}", state.i, state.sp, state.dt, state.st, mnemonic);
+    line
+}
+
+// Writes a trace line for every instruction with its address in one of the ranges, or for
+// every instruction if there are none
+pub struct Tracer<W: Write> {
+    out: W,
+    ranges: Vec<RangeInclusive<u16>>,
+}
+
+impl<W: Write> Tracer<W> {
+    pub fn new(out: W, ranges: Vec<RangeInclusive<u16>>) -> Self {
+        Tracer { out, ranges }
+    }
+
+    // Runs one instruction and traces it
+    pub fn tick(&mut self, emu: &mut EMU) -> io::Result<()> {
+        let pc = emu.program_counter;
+        let op = emu.state().opcode(pc);
+        emu.tick();
+        self.record(pc, op, emu)
+    }
+
+    // Traces the instruction `op` at `pc`, which `emu` has just run
+    pub fn record(&mut self, pc: u16, op: u16, emu: &EMU) -> io::Result<()> {
+        if self.ranges.is_empty() || self.ranges.iter().any(|range| range.contains(&pc)) {
+            writeln!(self.out, "{}", line(pc, op, &emu.state()))?;
+        }
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+// Where two traces first disagree
+#[derive(Debug, PartialEq, Eq)]
+pub struct Divergence {
+    pub instruction: usize, // counting from 1, blank and comment lines aren't instructions
+    pub ours: Option<String>, // None if the trace ended already
+    pub theirs: Option<String>,
+    pub fields: Vec<String>, // the keys that differ
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let show = |line: &Option<String>| line.clone().unwrap_or_else(|| "(end of trace)".to_string());
+        writeln!(f, "traces differ at instruction {}", self.instruction)?;
+        writeln!(f, "  ours:   {}", show(&self.ours))?;
+        writeln!(f, "  theirs: {}", show(&self.theirs))?;
+        if !self.fields.is_empty() {
+            writeln!(f, "  in {}", self.fields.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+// KEY=value pairs before any comment, keys in upper case and values as numbers where they
+// are hex, so "pc=0x200" in a reference trace still matches "PC=0200"
+fn fields(line: &str) -> Vec<(String, String)> {
+    let line = line.split([';', '#']).next().unwrap_or_default();
+    line.split_whitespace()
+        .filter_map(|word| word.split_once('='))
+        .map(|(key, value)| {
+            let digits = value.trim_start_matches("0x").trim_start_matches("0X");
+            let value = u32::from_str_radix(digits, 16).map_or_else(|_| value.to_string(), |n| n.to_string());
+            (key.to_ascii_uppercase(), value)
+        })
+        .collect()
+}
+
+fn trace_lines(input: impl BufRead) -> impl Iterator<Item = io::Result<String>> {
+    input.lines().filter(|line| line.as_ref().map_or(true, |line| !fields(line).is_empty()))
+}
+
+// Compares two traces line by line, skipping blank lines and comment lines
+pub fn diff(ours: impl BufRead, theirs: impl BufRead) -> io::Result<Option<Divergence>> {
+    let mut ours = trace_lines(ours);
+    let mut theirs = trace_lines(theirs);
+    let mut instruction = 0;
+    loop {
+        instruction += 1;
+        let (a, b) = (ours.next().transpose()?, theirs.next().transpose()?);
+        let (a_line, b_line) = match (&a, &b) {
+            (None, None) => return Ok(None),
+            (Some(a), Some(b)) => (a, b),
+            _ => return Ok(Some(Divergence { instruction, ours: a, theirs: b, fields: Vec::new() })),
+        };
+        let theirs_fields = fields(b_line);
+        let differ: Vec<String> = fields(a_line)
+            .into_iter()
+            .filter(|(key, value)| theirs_fields.iter().any(|(k, v)| k == key && v != value))
+            .map(|(key, _)| key)
+            .collect();
+        if !differ.is_empty() {
+            return Ok(Some(Divergence { instruction, ours: a, theirs: b, fields: differ }));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trace_lines() {
+        let mut emu = EMU::new();
+        emu.load(&[0x6A, 0x02, 0x12, 0x00]);
+        let mut tracer = Tracer::new(Vec::new(), vec![0x202..=0x202]);
+        for _ in 0..3 {
+            tracer.tick(&mut emu).unwrap();
+        }
+        let out = String::from_utf8(tracer.finish().unwrap()).unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].starts_with("PC=0202 OP=1200 V0=00"));
+        assert!(lines[0].contains(" VA=02 "));
+        assert!(lines[0].ends_with(" I=0000 SP=0 DT=00 ST=00 ; JP #200"));
+    }
+
+    #[test]
+    fn first_divergence() {
+        let ours = "PC=0200 OP=6A02 VA=02 ; LD VA, #02\nPC=0202 OP=7A01 VA=03\nPC=0204 OP=1200 VA=03\n";
+        let theirs = "# from somewhere else\npc=0x200 va=0x2 dt=5\n\npc=0x202 va=0x4\n";
+        let divergence = diff(ours.as_bytes(), theirs.as_bytes()).unwrap().unwrap();
+        assert_eq!(divergence.instruction, 2);
+        assert_eq!(divergence.fields, vec!["VA"]);
+
+        let shorter = "PC=0200 OP=6A02 VA=02\n";
+        let divergence = diff(shorter.as_bytes(), ours.as_bytes()).unwrap().unwrap();
+        assert_eq!((divergence.instruction, divergence.ours), (2, None));
+        assert_eq!(diff(ours.as_bytes(), ours.as_bytes()).unwrap(), None);
+    }
+}
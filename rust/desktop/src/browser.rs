@@ -0,0 +1,205 @@
+// In-app ROM browser (F4): recently played games, then everything in the ROM directory,
+// with titles and descriptions from the catalog. Drawn with the built-in font in the
+// current palette, into a picture the same size as the emulator screen.
+
+use crate::catalog::Catalog;
+use crate::font::{Canvas, CELL_HEIGHT, CELL_WIDTH};
+use crate::palette::Palette;
+use sdl2::keyboard::Keycode;
+use sdl2::pixels::Color;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// ROM files usually have no extension, or one of these
+const ROM_EXTENSIONS: [&str; 6] = ["ch8", "c8", "sc8", "xo8", "rom", "bin"];
+
+// the font is scaled so about this many characters fit across the window
+const COLUMNS: usize = 40;
+const DESCRIPTION_LINES: usize = 3;
+
+struct Item {
+    path: PathBuf,
+    title: String,
+    description: String,
+}
+
+enum Row {
+    Header(&'static str),
+    Rom(Item),
+}
+
+pub struct Browser {
+    rows: Vec<Row>,
+    selected: usize, // always a Row::Rom, unless there are none
+    top: usize,      // first row on screen
+    pixels: Vec<u8>,
+}
+
+impl Browser {
+    pub fn new(dir: &Path, catalog: &Catalog, recent: &[PathBuf]) -> Browser {
+        let item = |path: &Path| {
+            let file = path.file_name().unwrap_or_default().to_string_lossy();
+            let entry = catalog.get(&file);
+            Item {
+                path: path.to_path_buf(),
+                title: entry.map_or(file.to_string(), |e| e.title.clone()),
+                description: entry.map(|e| e.description.clone()).unwrap_or_default(),
+            }
+        };
+
+        let mut rows = Vec::new();
+        if !recent.is_empty() {
+            rows.push(Row::Header("Recently played"));
+            rows.extend(recent.iter().map(|p| Row::Rom(item(p))));
+        }
+
+        let mut files: Vec<PathBuf> = fs::read_dir(dir)
+            .map(|entries| entries.filter_map(|e| e.ok()).map(|e| e.path()).filter(|p| p.is_file()).collect())
+            .unwrap_or_default();
+        // leaves out save states, screenshots and hidden files that end up next to the ROMs
+        files.retain(|p| is_rom_file(p));
+        files.sort();
+        rows.push(Row::Header("All games"));
+        rows.extend(files.iter().map(|p| Row::Rom(item(p))));
+
+        let mut browser = Browser { rows, selected: 0, top: 0, pixels: Vec::new() };
+        browser.move_by(0);
+        browser
+    }
+
+    // Up/Down, Page Up/Page Down and Home/End move, Enter picks the selected ROM
+    pub fn handle_key(&mut self, key: Keycode) -> Option<PathBuf> {
+        match key {
+            Keycode::Up => self.move_by(-1),
+            Keycode::Down => self.move_by(1),
+            Keycode::PageUp => self.move_by(-10),
+            Keycode::PageDown => self.move_by(10),
+            Keycode::Home => self.move_by(-(self.rows.len() as isize)),
+            Keycode::End => self.move_by(self.rows.len() as isize),
+            Keycode::Return | Keycode::KpEnter => {
+                if let Some(Row::Rom(item)) = self.rows.get(self.selected) {
+                    return Some(item.path.clone());
+                }
+            },
+            _ => (),
+        }
+        None
+    }
+
+    // Moves `steps` ROMs up or down, skipping the headers and stopping at either end
+    fn move_by(&mut self, steps: isize) {
+        let roms: Vec<usize> = (0..self.rows.len()).filter(|&i| matches!(self.rows[i], Row::Rom(_))).collect();
+        if roms.is_empty() {
+            return;
+        }
+        let current = roms.iter().position(|&i| i >= self.selected).unwrap_or(0) as isize;
+        let target = (current + steps).clamp(0, roms.len() as isize - 1);
+        self.selected = roms[target as usize];
+    }
+
+    pub fn render(&mut self, width: u32, height: u32, palette: &Palette) -> &[u8] {
+        let (width, height) = (width as usize, height as usize);
+        let rgb = |c: Color| [c.r, c.g, c.b];
+        let (bg, fg) = (rgb(palette.off()), rgb(palette.on()));
+        let scale = (width / (CELL_WIDTH * COLUMNS)).max(1);
+        let (cell_w, cell_h) = (CELL_WIDTH * scale, CELL_HEIGHT * scale);
+        let columns = width / cell_w;
+        let lines = height / cell_h;
+        // title, blank, list, blank, description, hints
+        let list_lines = lines.saturating_sub(DESCRIPTION_LINES + 4).max(1);
+
+        // keep the selection on screen, with the header above the first ROM
+        if self.selected < self.top + 1 {
+            self.top = self.selected.saturating_sub(1);
+        } else if self.selected >= self.top + list_lines {
+            self.top = self.selected + 1 - list_lines;
+        }
+
+        self.pixels.resize(width * height * 3, 0);
+        let mut canvas = Canvas { pixels: &mut self.pixels, width, height };
+        canvas.fill_rect(0, 0, width, height, bg);
+        canvas.text(0, 0, "Chip-8 games", scale, fg);
+
+        for (line, row) in self.rows.iter().enumerate().skip(self.top).take(list_lines) {
+            let y = (line - self.top + 2) * cell_h;
+            match row {
+                Row::Header(title) => canvas.text(0, y, &format!("- {} -", title), scale, fg),
+                Row::Rom(item) if line == self.selected => {
+                    canvas.fill_rect(0, y - scale, width, cell_h, fg);
+                    canvas.text(cell_w, y, &item.title, scale, bg);
+                },
+                Row::Rom(item) => canvas.text(cell_w, y, &item.title, scale, fg),
+            }
+        }
+
+        if let Some(Row::Rom(item)) = self.rows.get(self.selected) {
+            let y = (list_lines + 3) * cell_h;
+            for (n, line) in wrap(&item.description, columns).iter().take(DESCRIPTION_LINES).enumerate() {
+                canvas.text(0, y + n * cell_h, line, scale, fg);
+            }
+        } else {
+            canvas.text(0, 2 * cell_h, "No ROMs found", scale, fg);
+        }
+        canvas.text(0, (lines.max(1) - 1) * cell_h, "Enter: play  Esc: back", scale, fg);
+        &self.pixels
+    }
+}
+
+pub fn is_rom_file(path: &Path) -> bool {
+    if path.file_name().unwrap_or_default().to_string_lossy().starts_with('.') {
+        return false;
+    }
+    match path.extension() {
+        None => true,
+        Some(ext) => ROM_EXTENSIONS.iter().any(|e| ext.eq_ignore_ascii_case(e)),
+    }
+}
+
+// Greedy word wrap, words longer than a line are cut
+fn wrap(text: &str, columns: usize) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    let mut line = String::new();
+    let columns = columns.max(1);
+    let chars: Vec<char> = text.chars().collect();
+    let pieces = chars
+        .split(|c| c.is_whitespace())
+        .flat_map(|word| word.chunks(columns))
+        .map(|piece| piece.iter().collect::<String>());
+    for word in pieces {
+        if !line.is_empty() && line.chars().count() + 1 + word.chars().count() > columns {
+            lines.push(std::mem::take(&mut line));
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(&word);
+    }
+    if !line.is_empty() {
+        lines.push(line);
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rom_files() {
+        for rom in ["ROMS/PONG", "ROMS/pong.ch8", "tetris.C8", "a/b/octo.xo8", "game.BIN"] {
+            assert!(is_rom_file(Path::new(rom)), "{}", rom);
+        }
+        for other in ["ROMS/.hidden", "ROMS/.DS_Store", "notes.txt", "game_catalog.txt", "shot.png"] {
+            assert!(!is_rom_file(Path::new(other)), "{}", other);
+        }
+    }
+
+    #[test]
+    fn word_wrap() {
+        assert_eq!(wrap("Move the paddle with 1 and 4", 12), ["Move the", "paddle with", "1 and 4"]);
+        assert_eq!(wrap("  spaced   out  ", 20), ["spaced out"]);
+        assert_eq!(wrap("a supercalifragilistic word", 8), ["a", "supercal", "ifragili", "stic", "word"]);
+        assert!(wrap("", 8).is_empty());
+        assert_eq!(wrap("ab", 0), ["a", "b"]);
+    }
+}
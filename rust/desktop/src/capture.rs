@@ -0,0 +1,72 @@
+// F12 screenshots and F10 GIF recordings
+//
+// Files go next to the ROM, named after it and the time they were taken,
+// e.g. PONG-1760000000000.png (milliseconds, so quick repeats don't overwrite)
+
+use chip8_core::capture::{write_png, GifRecorder};
+use chip8_core::{Palette, ScaleFilter, EMU};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub struct Capture {
+    scale: usize,
+    filter: ScaleFilter,
+    recording: Option<(PathBuf, GifRecorder<BufWriter<File>>)>,
+}
+
+impl Capture {
+    pub fn new(scale: u32, filter: ScaleFilter) -> Result<Capture, String> {
+        filter.check_scale(scale as usize).map_err(|e| format!("Invalid capture scale: {}", e))?;
+        Ok(Capture { scale: scale as usize, filter, recording: None })
+    }
+
+    pub fn screenshot(&self, rom: &Path, emu: &EMU, palette: &Palette) -> Result<PathBuf, String> {
+        let path = file_name(rom, "png");
+        let file = File::create(&path).map_err(|e| format!("Unable to create {}: {}", path.display(), e))?;
+        write_png(BufWriter::new(file), emu, palette, self.scale, self.filter)
+            .map_err(|e| format!("Unable to write {}: {}", path.display(), e))?;
+        Ok(path)
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    // The palette is fixed for the whole recording
+    pub fn start_recording(&mut self, rom: &Path, palette: &Palette) -> Result<&Path, String> {
+        let path = file_name(rom, "gif");
+        let file = File::create(&path).map_err(|e| format!("Unable to create {}: {}", path.display(), e))?;
+        let recorder = GifRecorder::new(BufWriter::new(file), palette, self.scale, self.filter)
+            .map_err(|e| format!("Unable to record {}: {}", path.display(), e))?;
+        Ok(&self.recording.insert((path, recorder)).0)
+    }
+
+    pub fn stop_recording(&mut self) -> Result<PathBuf, String> {
+        let Some((path, recorder)) = self.recording.take() else {
+            return Err("Not recording".to_string());
+        };
+        recorder.finish().map_err(|e| format!("Unable to write {}: {}", path.display(), e))?;
+        Ok(path)
+    }
+
+    // Call once per emulated frame; a failed write ends the recording
+    pub fn frame(&mut self, emu: &EMU) -> Result<(), String> {
+        let Some((path, recorder)) = &mut self.recording else {
+            return Ok(());
+        };
+        if let Err(e) = recorder.add_frame(emu) {
+            let msg = format!("Recording to {} stopped: {}", path.display(), e);
+            self.recording = None;
+            return Err(msg);
+        }
+        Ok(())
+    }
+}
+
+fn file_name(rom: &Path, extension: &str) -> PathBuf {
+    let stem = rom.file_stem().unwrap_or_default().to_string_lossy();
+    let time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis();
+    rom.with_file_name(format!("{}-{}.{}", stem, time, extension))
+}
@@ -0,0 +1,75 @@
+// game_catalog.txt: one ROM per line as "FILE | Title | Description", '#' starts a comment.
+// Title and description are optional, so a plain list of file names still works.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+
+pub struct Entry {
+    pub title: String,
+    pub description: String,
+}
+
+#[derive(Default)]
+pub struct Catalog {
+    entries: HashMap<String, Entry>, // by file name
+}
+
+impl Catalog {
+    // A missing catalog is just empty
+    pub fn load(path: &Path) -> Result<Catalog, String> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Catalog::default()),
+            Err(e) => return Err(format!("Unable to read {}: {}", path.display(), e)),
+        };
+        Ok(Catalog::parse(&text))
+    }
+
+    pub fn parse(text: &str) -> Catalog {
+        let mut entries = HashMap::new();
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut fields = line.splitn(3, '|').map(str::trim);
+            let file = fields.next().unwrap_or_default();
+            let title = fields.next().filter(|t| !t.is_empty()).unwrap_or(file);
+            let description = fields.next().unwrap_or_default();
+            entries.insert(file.to_string(), Entry { title: title.to_string(), description: description.to_string() });
+        }
+        Catalog { entries }
+    }
+
+    pub fn get(&self, file: &str) -> Option<&Entry> {
+        self.entries.get(file)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_lines() {
+        let catalog = Catalog::parse(
+            "# FILE | Title | Description\n\
+             PONG | Pong | Two paddles | and a ball\n\
+             \n\
+             \tTETRIS|  | Falling blocks  \n\
+             MAZE\n\
+             UFO | UFO\n",
+        );
+        let entry = |file: &str| catalog.get(file).map(|e| (e.title.as_str(), e.description.as_str()));
+        assert_eq!(entry("PONG"), Some(("Pong", "Two paddles | and a ball")));
+        // no title means the file name
+        assert_eq!(entry("TETRIS"), Some(("TETRIS", "Falling blocks")));
+        assert_eq!(entry("MAZE"), Some(("MAZE", "")));
+        assert_eq!(entry("UFO"), Some(("UFO", "")));
+        assert!(entry("# FILE").is_none());
+        assert!(entry("").is_none());
+        assert_eq!(catalog.entries.len(), 4);
+    }
+}
@@ -0,0 +1,152 @@
+// Command line options
+
+use crate::crt::EffectSettings;
+use crate::romdb::RomInfo;
+use chip8_core::{FlickerFilter, Quirks, ScaleFilter, Variant};
+use clap::Parser;
+use std::path::PathBuf;
+
+#[derive(Parser)]
+#[command(version, about = "A CHIP-8 emulator")]
+pub struct Args {
+    /// CHIP-8 ROM to run, the ROM browser opens without one
+    pub rom: Option<PathBuf>,
+
+    /// Directory the ROM browser (F4) lists, by default the one the ROM is in or ./ROMS
+    #[arg(long, value_name = "DIR")]
+    pub roms: Option<PathBuf>,
+
+    /// Titles and descriptions for the ROM browser, by default game_catalog.txt next to
+    /// the ROM directory
+    #[arg(long, value_name = "FILE")]
+    pub catalog: Option<PathBuf>,
+
+    /// Instructions executed per second, a multiple of 60 so every frame runs as many
+    /// [default: 600, or what the ROM database says]
+    #[arg(long, value_parser = parse_speed)]
+    pub speed: Option<u32>,
+
+    /// Window pixels per CHIP-8 pixel
+    #[arg(long, default_value_t = 15, value_parser = clap::value_parser!(u32).range(1..=64))]
+    pub scale: u32,
+
+    /// Speed-up while Tab is held
+    #[arg(long, value_name = "N", default_value_t = 4, value_parser = clap::value_parser!(u32).range(2..=64))]
+    pub fast_forward: u32,
+
+    /// Slow motion (F9) runs at 1/N speed
+    #[arg(long, value_name = "N", default_value_t = 4, value_parser = clap::value_parser!(u32).range(2..=64))]
+    pub slow_motion: u32,
+
+    /// Start in fullscreen
+    #[arg(long)]
+    pub fullscreen: bool,
+
+    /// Emulate the quirks of this platform: chip8, schip or xochip
+    #[arg(long)]
+    pub variant: Option<Variant>,
+
+    /// Quirks to turn on or off on top of the variant, e.g. "shift,-clip"
+    /// (shift, load-store, jump, vf-reset, clip)
+    #[arg(long, value_name = "LIST")]
+    pub quirks: Option<String>,
+
+    /// Palette name (classic, green, amber, lcd, octo, high-contrast, colorblind),
+    /// or hex colors "fg,bg[,plane2,both]" like "33ff66,001100"
+    #[arg(long, value_name = "PALETTE")]
+    pub palette: Option<String>,
+
+    /// Flicker reduction: off, merge (show pixels lit in either of the last two frames)
+    /// or persistence[:N] (lit pixels fade out over N frames)
+    #[arg(long, default_value = "off")]
+    pub filter: FlickerFilter,
+
+    #[command(flatten)]
+    pub effects: EffectSettings,
+
+    /// Size of screenshots (F12) and GIF recordings (F10) in pixels per CHIP-8 pixel,
+    /// the window scale by default
+    #[arg(long, value_name = "SCALE", value_parser = clap::value_parser!(u32).range(1..=64))]
+    pub capture_scale: Option<u32>,
+
+    /// Pixel art smoothing for screenshots and recordings: nearest, scale2x, scale3x or epx
+    #[arg(long, value_name = "FILTER", default_value = "nearest")]
+    pub capture_filter: ScaleFilter,
+
+    /// Write every frame as raw RGBA to VIDEO and the beep as WAV to AUDIO, both at
+    /// exactly 60 fps for muxing with ffmpeg; files or named pipes
+    #[arg(long, num_args = 2, value_names = ["VIDEO", "AUDIO"])]
+    pub dump_av: Option<Vec<PathBuf>>,
+
+    /// No sound
+    #[arg(long)]
+    pub mute: bool,
+
+    /// Seed for the random number generator, for reproducible runs
+    #[arg(long)]
+    pub seed: Option<u64>,
+
+    /// Resume from a save state (F5 saves one next to the ROM)
+    #[arg(long, value_name = "FILE")]
+    pub load_state: Option<PathBuf>,
+
+    /// ROM database to use instead of ~/.config/chip8/romdb.toml, on top of the built-in one
+    #[arg(long, value_name = "FILE")]
+    pub romdb: Option<PathBuf>,
+
+    /// Write game_catalog.txt for the ROM directory from the ROM database and exit
+    #[arg(long, value_name = "FILE")]
+    pub write_catalog: Option<PathBuf>,
+
+    /// Keymap file to use instead of ~/.config/chip8/keymap.toml
+    #[arg(long, value_name = "FILE")]
+    pub keymap: Option<PathBuf>,
+
+    /// Match keys by the letter printed on them instead of their position
+    #[arg(long)]
+    pub keycodes: bool,
+}
+
+pub const DEFAULT_SPEED: u32 = 600;
+// at least an instruction per frame
+pub const MIN_SPEED: u32 = 60;
+pub const MAX_SPEED: u32 = 999_960; // the last multiple of 60 below a million
+
+// Frames run whole instructions, so anything between two multiples of MIN_SPEED would
+// quietly run at the lower one
+pub fn check_speed(speed: u32) -> Result<(), String> {
+    if !(MIN_SPEED..=MAX_SPEED).contains(&speed) {
+        return Err(format!("speed has to be between {} and {}", MIN_SPEED, MAX_SPEED));
+    }
+    if !speed.is_multiple_of(MIN_SPEED) {
+        let below = speed / MIN_SPEED * MIN_SPEED;
+        return Err(format!("speed has to be a multiple of {}, like {} or {}", MIN_SPEED, below, below + MIN_SPEED));
+    }
+    Ok(())
+}
+
+fn parse_speed(text: &str) -> Result<u32, String> {
+    let speed = text.parse().map_err(|_| format!("'{}' is not a number", text))?;
+    check_speed(speed)?;
+    Ok(speed)
+}
+
+impl Args {
+    // A variant given here replaces the ROM's variant and quirks from the database
+    pub fn quirks(&self, info: Option<&RomInfo>) -> Result<Quirks, String> {
+        match (self.variant, info) {
+            (None, Some(info)) => {
+                let mut quirks = Quirks::with_list(info.variant(), info.quirks.as_deref())?;
+                if let Some(list) = &self.quirks {
+                    quirks.apply_list(list)?;
+                }
+                Ok(quirks)
+            },
+            (variant, _) => Quirks::with_list(variant, self.quirks.as_deref()),
+        }
+    }
+
+    pub fn speed(&self, info: Option<&RomInfo>) -> u32 {
+        self.speed.or(info.and_then(|i| i.speed)).unwrap_or(DEFAULT_SPEED)
+    }
+}
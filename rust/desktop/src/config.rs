@@ -0,0 +1,32 @@
+// Settings from `<XDG config dir>/chip8/config.toml`, see `config.example.toml`.
+// Command line options win over anything set here.
+
+use crate::crt::EffectSettings;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    pub palette: Option<String>,
+    #[serde(default)]
+    pub palettes: HashMap<String, Vec<String>>,
+    #[serde(default)]
+    pub effects: EffectSettings,
+}
+
+pub fn default_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("chip8").join("config.toml"))
+}
+
+// A missing file is the same as an empty one
+pub fn load() -> Result<Config, String> {
+    let Some(path) = default_path().filter(|path| path.exists()) else {
+        return Ok(Config::default());
+    };
+    let text = fs::read_to_string(&path)
+        .map_err(|e| format!("Unable to read config {}: {}", path.display(), e))?;
+    toml::from_str(&text).map_err(|e| format!("Invalid config {}: {}", path.display(), e))
+}
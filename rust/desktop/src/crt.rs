@@ -0,0 +1,273 @@
+// Software post-processing for a retro look: scanlines, pixel grid, bloom, barrel
+// curvature and vignette. Everything is worked out on the CPU into an RGB24 buffer the
+// size of the window, which main uploads to a streaming texture once per frame.
+//
+// Effects only depend on where an output pixel lands, so that part (curvature, scanline,
+// grid and vignette shading) is computed once in `samples` and reused every frame.
+
+use crate::palette::Palette;
+use chip8_core::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use serde::Deserialize;
+use std::f32::consts::PI;
+
+pub const BYTES_PER_PIXEL: usize = 3;
+
+#[derive(Clone, Copy, Default)]
+pub struct Effect {
+    pub enabled: bool,
+    pub amount: f32, // 0.0 - 1.0
+}
+
+impl Effect {
+    fn on(amount: f32) -> Self {
+        Effect { enabled: amount > 0.0, amount }
+    }
+
+    fn strength(&self) -> f32 {
+        if self.enabled {self.amount} else {0.0}
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct Effects {
+    pub scanlines: Effect, // dark gaps between the lines of the picture
+    pub grid: Effect,      // dark lines around every CHIP-8 pixel
+    pub bloom: Effect,     // lit pixels glow into their surroundings
+    pub curvature: Effect, // bulging glass
+    pub vignette: Effect,  // darker corners
+}
+
+pub const PRESETS: [&str; 4] = ["off", "crt", "tv", "lcd"];
+
+// Effects from the `[effects]` table of the config file and from the command line,
+// on top of a preset. Strengths go from 0.0 (off) to 1.0.
+#[derive(Deserialize, Default, clap::Args)]
+#[serde(deny_unknown_fields)]
+pub struct EffectSettings {
+    /// Post-processing preset: off, crt, tv or lcd
+    #[arg(long = "crt", value_name = "PRESET")]
+    pub preset: Option<String>,
+
+    /// Scanline darkness, 0.0-1.0
+    #[arg(long, value_name = "AMOUNT")]
+    pub scanlines: Option<f32>,
+
+    /// Pixel grid line darkness, 0.0-1.0
+    #[arg(long, value_name = "AMOUNT")]
+    pub grid: Option<f32>,
+
+    /// Glow around lit pixels, 0.0-1.0
+    #[arg(long, value_name = "AMOUNT")]
+    pub bloom: Option<f32>,
+
+    /// Barrel curvature of the screen, 0.0-1.0
+    #[arg(long, value_name = "AMOUNT")]
+    pub curvature: Option<f32>,
+
+    /// Darkening of the corners, 0.0-1.0
+    #[arg(long, value_name = "AMOUNT")]
+    pub vignette: Option<f32>,
+}
+
+impl Effects {
+    pub fn preset(name: &str) -> Result<Effects, String> {
+        let [scanlines, grid, bloom, curvature, vignette] = match name {
+            "off" => [0.0, 0.0, 0.0, 0.0, 0.0],
+            "crt" => [0.35, 0.0, 0.4, 0.3, 0.3],
+            "tv" => [0.5, 0.0, 0.6, 0.7, 0.6],
+            "lcd" => [0.0, 0.5, 0.0, 0.0, 0.0],
+            _ => return Err(format!("Unknown effect preset '{}' (expected {})", name, PRESETS.join(", "))),
+        };
+        Ok(Effects {
+            scanlines: Effect::on(scanlines),
+            grid: Effect::on(grid),
+            bloom: Effect::on(bloom),
+            curvature: Effect::on(curvature),
+            vignette: Effect::on(vignette),
+        })
+    }
+
+    // later settings win
+    pub fn from_settings(settings: &[&EffectSettings]) -> Result<Effects, String> {
+        let preset = settings.iter().rev().find_map(|s| s.preset.as_deref()).unwrap_or("off");
+        let mut effects = Effects::preset(preset)?;
+        for s in settings {
+            for (effect, amount) in [
+                (&mut effects.scanlines, s.scanlines),
+                (&mut effects.grid, s.grid),
+                (&mut effects.bloom, s.bloom),
+                (&mut effects.curvature, s.curvature),
+                (&mut effects.vignette, s.vignette),
+            ] {
+                if let Some(amount) = amount {
+                    if !(0.0..=1.0).contains(&amount) {
+                        return Err(format!("Effect strength {} is out of range (expected 0.0-1.0)", amount));
+                    }
+                    *effect = Effect::on(amount);
+                }
+            }
+        }
+        Ok(effects)
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Sample {
+    x: f32, // position on the CHIP-8 screen, in CHIP-8 pixels
+    y: f32,
+    shade: f32, // scanline, grid and vignette darkening
+    inside: bool, // false for the corners curvature pushes off the glass
+}
+
+pub struct Pipeline {
+    scale: u32,
+    effects: Effects,
+    samples: Vec<Sample>,
+    glow: Vec<f32>,
+    pixels: Vec<u8>,
+}
+
+impl Pipeline {
+    pub fn new(scale: u32, effects: Effects) -> Self {
+        let mut pipeline = Pipeline {
+            scale,
+            effects,
+            samples: Vec::new(),
+            glow: vec![0.0; SCREEN_WIDTH * SCREEN_HEIGHT],
+            pixels: vec![0; (SCREEN_WIDTH * SCREEN_HEIGHT) * (scale * scale) as usize * BYTES_PER_PIXEL],
+        };
+        pipeline.build_samples();
+        pipeline
+    }
+
+    pub fn width(&self) -> u32 {
+        SCREEN_WIDTH as u32 * self.scale
+    }
+
+    pub fn height(&self) -> u32 {
+        SCREEN_HEIGHT as u32 * self.scale
+    }
+
+    pub fn pitch(&self) -> usize {
+        self.width() as usize * BYTES_PER_PIXEL
+    }
+
+    pub fn effects(&self) -> Effects {
+        self.effects
+    }
+
+    pub fn set_effects(&mut self, effects: Effects) {
+        self.effects = effects;
+        self.build_samples();
+    }
+
+    fn build_samples(&mut self) {
+        let (width, height) = (self.width(), self.height());
+        let scale = self.scale as f32;
+        let curvature = self.effects.curvature.strength() * 0.25;
+        let scanlines = self.effects.scanlines.strength();
+        let grid = self.effects.grid.strength();
+        let vignette = self.effects.vignette.strength();
+
+        self.samples.clear();
+        for py in 0..height {
+            for px in 0..width {
+                // -1.0 to 1.0 across the window
+                let u = (px as f32 + 0.5) / width as f32 * 2.0 - 1.0;
+                let v = (py as f32 + 0.5) / height as f32 * 2.0 - 1.0;
+                let r2 = u * u + v * v;
+
+                // barrel distortion, the edges show more of the picture than the middle
+                let bend = 1.0 + curvature * r2;
+                let (cu, cv) = (u * bend, v * bend);
+                let inside = cu.abs() <= 1.0 && cv.abs() <= 1.0;
+
+                let x = (cu + 1.0) * 0.5 * SCREEN_WIDTH as f32;
+                let y = (cv + 1.0) * 0.5 * SCREEN_HEIGHT as f32;
+                let (sx, sy) = (x.fract(), y.fract());
+
+                let mut shade = 1.0;
+                // beam is brightest in the middle of a line
+                shade *= 1.0 - scanlines * (1.0 - (PI * sy).sin());
+                // one window pixel wide line on the right and bottom of every CHIP-8 pixel
+                if sx * scale >= scale - 1.0 || sy * scale >= scale - 1.0 {
+                    shade *= 1.0 - grid;
+                }
+                shade *= (1.0 - vignette * r2 * 0.5).clamp(0.0, 1.0);
+
+                self.samples.push(Sample { x, y, shade, inside });
+            }
+        }
+    }
+
+    // `levels` is the brightness of every CHIP-8 pixel, as put out by DisplayFilter
+    pub fn render(&mut self, levels: &[u8], palette: &Palette) -> &[u8] {
+        let bloom = self.effects.bloom.strength();
+        if bloom > 0.0 {
+            self.blur(levels);
+        }
+
+        let (off, on) = (palette.off(), palette.on());
+        let off = [off.r as f32, off.g as f32, off.b as f32];
+        let on = [on.r as f32, on.g as f32, on.b as f32];
+
+        for (sample, out) in self.samples.iter().zip(self.pixels.chunks_exact_mut(BYTES_PER_PIXEL)) {
+            if !sample.inside {
+                out.fill(0);
+                continue;
+            }
+            let cx = (sample.x as usize).min(SCREEN_WIDTH - 1);
+            let cy = (sample.y as usize).min(SCREEN_HEIGHT - 1);
+            let level = levels[cx + SCREEN_WIDTH * cy] as f32 / 255.0;
+            let glow = if bloom > 0.0 {glow_at(&self.glow, sample.x, sample.y) * bloom} else {0.0};
+
+            for c in 0..BYTES_PER_PIXEL {
+                let base = off[c] + (on[c] - off[c]) * level;
+                out[c] = (base * sample.shade + on[c] * glow).min(255.0) as u8;
+            }
+        }
+        &self.pixels
+    }
+
+    // 5x5 binomial blur of the lit pixels, the source of the bloom
+    fn blur(&mut self, levels: &[u8]) {
+        const KERNEL: [f32; 5] = [1.0 / 16.0, 4.0 / 16.0, 6.0 / 16.0, 4.0 / 16.0, 1.0 / 16.0];
+        let (w, h) = (SCREEN_WIDTH as isize, SCREEN_HEIGHT as isize);
+
+        let mut rows = vec![0.0; SCREEN_WIDTH * SCREEN_HEIGHT];
+        for y in 0..h {
+            for x in 0..w {
+                let mut sum = 0.0;
+                for (k, weight) in KERNEL.iter().enumerate() {
+                    let sx = (x + k as isize - 2).clamp(0, w - 1);
+                    sum += weight * levels[(sx + w * y) as usize] as f32 / 255.0;
+                }
+                rows[(x + w * y) as usize] = sum;
+            }
+        }
+        for y in 0..h {
+            for x in 0..w {
+                let mut sum = 0.0;
+                for (k, weight) in KERNEL.iter().enumerate() {
+                    let sy = (y + k as isize - 2).clamp(0, h - 1);
+                    sum += weight * rows[(x + w * sy) as usize];
+                }
+                self.glow[(x + w * y) as usize] = sum;
+            }
+        }
+    }
+}
+
+// bilinear, so the glow is smooth rather than blocky
+fn glow_at(glow: &[f32], x: f32, y: f32) -> f32 {
+    let x = (x - 0.5).clamp(0.0, (SCREEN_WIDTH - 1) as f32);
+    let y = (y - 0.5).clamp(0.0, (SCREEN_HEIGHT - 1) as f32);
+    let (x0, y0) = (x as usize, y as usize);
+    let (x1, y1) = ((x0 + 1).min(SCREEN_WIDTH - 1), (y0 + 1).min(SCREEN_HEIGHT - 1));
+    let (fx, fy) = (x.fract(), y.fract());
+
+    let at = |x: usize, y: usize| glow[x + SCREEN_WIDTH * y];
+    let top = at(x0, y0) + (at(x1, y0) - at(x0, y0)) * fx;
+    let bottom = at(x0, y1) + (at(x1, y1) - at(x0, y1)) * fx;
+    top + (bottom - top) * fy
+}
@@ -0,0 +1,367 @@
+// Debugger window (Shift+F11): the registers, stack, timers and pressed keys, the code around
+// the PC and a page of RAM, redrawn every frame. Values that changed since the last stop are
+// drawn in yellow, or while the game runs, since the stop before it.
+//
+// While it's open the game runs through chip8_core's Debugger, so it stops at breakpoints and
+// before instructions that would crash the interpreter. Stopping pauses playback, F6 and F7
+// keep working. Click a line of code to set or clear a breakpoint there, the buttons along the
+// top step, continue and pause. The RAM view follows I until you scroll it, right click goes
+// back to following.
+
+use crate::font::{Canvas, CELL_HEIGHT, CELL_WIDTH};
+use crate::memview::MemoryView;
+use crate::playback::Playback;
+use chip8_core::debug::{Breakpoint, Debugger, Stop};
+use chip8_core::disasm::disassemble;
+use chip8_core::EMU;
+use sdl2::event::{Event, WindowEvent};
+use sdl2::mouse::MouseButton;
+use sdl2::pixels::PixelFormatEnum;
+use sdl2::render::{Canvas as WindowCanvas, Texture};
+use sdl2::video::Window;
+use sdl2::VideoSubsystem;
+
+const TEXT_SCALE: usize = 2;
+const CHAR: usize = CELL_WIDTH * TEXT_SCALE;
+const LINE: usize = CELL_HEIGHT * TEXT_SCALE;
+const WIDTH: usize = 720;
+const BUTTON_Y: usize = 6;
+const BUTTON_HEIGHT: usize = LINE + 6;
+const STATUS_Y: usize = BUTTON_Y + BUTTON_HEIGHT + 8;
+const TOP: usize = STATUS_Y + LINE + 10; // registers on the left, code on the right
+const CODE_X: usize = 28 * CHAR;
+const CODE_LINES: usize = 22;
+const CODE_BEFORE: u16 = 6; // lines above the PC
+const HEX_Y: usize = TOP + CODE_LINES * LINE + 10;
+const HEX_ROWS: usize = 8;
+const HEX_COLUMNS: usize = 16;
+const HEIGHT: usize = HEX_Y + (HEX_ROWS + 1) * LINE + 6;
+
+const BACKGROUND: [u8; 3] = [0, 0, 0];
+const TEXT: [u8; 3] = [200, 200, 200];
+const LABEL: [u8; 3] = [110, 110, 110];
+const CHANGED: [u8; 3] = [255, 210, 0];
+const BREAKPOINT: [u8; 3] = [230, 60, 60];
+const CURRENT: [u8; 3] = [40, 40, 90]; // behind the PC's line and the byte at I
+const BUTTON: [u8; 3] = [50, 50, 50];
+
+// CHIP-8 keys in keypad order
+const KEYPAD: [[usize; 4]; 4] = [[0x1, 0x2, 0x3, 0xC], [0x4, 0x5, 0x6, 0xD], [0x7, 0x8, 0x9, 0xE], [0xA, 0x0, 0xB, 0xF]];
+
+#[derive(Clone, Copy)]
+enum Button {
+    Step,
+    Continue,
+    Pause,
+    Clear,
+}
+
+const BUTTONS: [(Button, &str); 4] = [
+    (Button::Step, "Step"),
+    (Button::Continue, "Continue"),
+    (Button::Pause, "Pause"),
+    (Button::Clear, "Clear breakpoints"),
+];
+
+// The machine when it stopped, to see what changed
+#[derive(Clone)]
+struct Snapshot {
+    v: Vec<u8>,
+    i: u16,
+    pc: u16,
+    sp: u16,
+    dt: u8,
+    st: u8,
+    stack: Vec<u16>,
+    ram: Vec<u8>,
+}
+
+impl Snapshot {
+    fn of(chip8: &EMU) -> Self {
+        let state = chip8.state();
+        Snapshot {
+            v: state.v.to_vec(),
+            i: state.i,
+            pc: state.pc,
+            sp: state.sp,
+            dt: state.dt,
+            st: state.st,
+            stack: state.stack.to_vec(),
+            ram: state.ram.to_vec(),
+        }
+    }
+}
+
+pub struct DebugView {
+    canvas: WindowCanvas<Window>,
+    texture: Texture, // freed along with the canvas
+    pixels: Vec<u8>,
+    debugger: Debugger,
+    reason: Option<String>, // why it stopped, None if paused by hand
+    was_paused: bool,
+    last: Snapshot,         // at the last stop
+    previous: Snapshot,     // at the stop before that
+    hex_start: Option<usize>, // scrolled here, None follows I
+}
+
+impl DebugView {
+    pub fn new(video: &VideoSubsystem, chip8: &EMU, ticks_per_frame: usize) -> Result<Self, String> {
+        let window = video
+            .window("Debugger", WIDTH as u32, HEIGHT as u32)
+            .build()
+            .map_err(|e| format!("Unable to open the debugger window: {}", e))?;
+        let canvas = window.into_canvas().build().map_err(|e| format!("Unable to open the debugger window: {}", e))?;
+        let texture = canvas
+            .texture_creator()
+            .create_texture_streaming(PixelFormatEnum::RGB24, WIDTH as u32, HEIGHT as u32)
+            .map_err(|e| format!("Unable to open the debugger window: {}", e))?;
+        Ok(DebugView {
+            canvas,
+            texture,
+            pixels: vec![0; WIDTH * HEIGHT * 3],
+            debugger: Debugger::new(ticks_per_frame),
+            reason: None,
+            was_paused: false,
+            last: Snapshot::of(chip8),
+            previous: Snapshot::of(chip8),
+            hex_start: None,
+        })
+    }
+
+    pub fn window_id(&self) -> u32 {
+        self.canvas.window().id()
+    }
+
+    // Breakpoints belong to the ROM, a new one starts without any
+    pub fn restart(&mut self, chip8: &EMU, ticks_per_frame: usize) {
+        self.debugger = Debugger::new(ticks_per_frame);
+        self.reason = None;
+        self.last = Snapshot::of(chip8);
+        self.previous = Snapshot::of(chip8);
+        self.hex_start = None;
+    }
+
+    // Runs a frame's worth of instructions, ticking the timers like the game loop does.
+    // Returns false if it stopped before the end of the frame.
+    pub fn run_frame(&mut self, chip8: &mut EMU, ticks_per_frame: usize, mut memory: Option<&mut MemoryView>) -> bool {
+        for _ in 0..ticks_per_frame {
+            // when it stops at a breakpoint the instruction lights up a little early, it runs
+            // right after continuing anyway
+            if let Some(view) = &mut memory {
+                view.record(chip8);
+            }
+            let stop = self.debugger.run(chip8, 1);
+            if stop != Stop::Done {
+                self.reason = Some(stop.to_string());
+                return false;
+            }
+        }
+        true
+    }
+
+    // Events for this window. Returns false once it's been closed.
+    pub fn handle(&mut self, evt: &Event, chip8: &mut EMU, playback: &mut Playback) -> bool {
+        match *evt {
+            Event::Window{win_event: WindowEvent::Close, ..} => return false,
+            Event::MouseButtonDown{mouse_btn: MouseButton::Left, x, y, ..} => self.click(x, y, chip8, playback),
+            Event::MouseButtonDown{mouse_btn: MouseButton::Right, ..} => self.hex_start = None,
+            // up goes towards the start of RAM
+            Event::MouseWheel{y, ..} => {
+                let ram = chip8.state().ram.len();
+                let start = self.hex_start.unwrap_or_else(|| follow(chip8));
+                let start = start.saturating_add_signed(-(y as isize) * HEX_COLUMNS as isize);
+                self.hex_start = Some(start.min(ram - HEX_ROWS * HEX_COLUMNS));
+            },
+            _ => (),
+        }
+        true
+    }
+
+    fn click(&mut self, x: i32, y: i32, chip8: &mut EMU, playback: &mut Playback) {
+        let (Ok(x), Ok(y)) = (usize::try_from(x), usize::try_from(y)) else {
+            return;
+        };
+        if let Some(button) = buttons().find(|&(_, _, left, width)| {
+            (left..left + width).contains(&x) && (BUTTON_Y..BUTTON_Y + BUTTON_HEIGHT).contains(&y)
+        }) {
+            match button.0 {
+                Button::Step => {
+                    playback.set_paused(true);
+                    let stop = self.debugger.run(chip8, 1);
+                    self.reason = Some(match stop {
+                        Stop::Done => format!("stepped to {:#05X}", chip8.state().pc),
+                        stop => stop.to_string(),
+                    });
+                    self.stopped(chip8);
+                },
+                Button::Continue => playback.set_paused(false),
+                Button::Pause => playback.set_paused(true),
+                Button::Clear => {
+                    let all: Vec<u16> = self.debugger.breakpoints().map(|bp| bp.addr).collect();
+                    for addr in all {
+                        self.debugger.remove_breakpoint(addr);
+                    }
+                },
+            }
+            return;
+        }
+
+        // a line of code
+        if x >= CODE_X && (TOP..TOP + CODE_LINES * LINE).contains(&y) {
+            let addr = code_start(chip8) as usize + (y - TOP) / LINE * 2;
+            if addr + 1 < chip8.state().ram.len() && !self.debugger.remove_breakpoint(addr as u16) {
+                self.debugger.add_breakpoint(Breakpoint::at(addr as u16));
+            }
+        }
+    }
+
+    // Keeps the machine as it is now to compare with
+    fn stopped(&mut self, chip8: &EMU) {
+        self.previous = std::mem::replace(&mut self.last, Snapshot::of(chip8));
+        self.was_paused = true;
+    }
+
+    pub fn draw(&mut self, chip8: &EMU, paused: bool) {
+        if paused && !self.was_paused {
+            self.stopped(chip8);
+        }
+        if !paused {
+            self.reason = None;
+        }
+        self.was_paused = paused;
+        // while stopped the last stop is what's on screen
+        let before = if paused {&self.previous} else {&self.last};
+        let state = chip8.state();
+
+        let mut canvas = Canvas { pixels: &mut self.pixels, width: WIDTH, height: HEIGHT };
+        canvas.fill_rect(0, 0, WIDTH, HEIGHT, BACKGROUND);
+        for (_, label, left, width) in buttons() {
+            canvas.fill_rect(left, BUTTON_Y, width, BUTTON_HEIGHT, BUTTON);
+            canvas.text(left + CHAR, BUTTON_Y + 4, label, TEXT_SCALE, TEXT);
+        }
+        let status = match (paused, &self.reason) {
+            (false, _) => "running".to_string(),
+            (true, Some(reason)) => format!("stopped: {}", reason),
+            (true, None) => "paused".to_string(),
+        };
+        canvas.text(4, STATUS_Y, &status, TEXT_SCALE, TEXT);
+
+        // registers
+        let mut put = |column: usize, row: usize, text: &str, color: [u8; 3]| {
+            canvas.text(4 + column * CHAR, TOP + row * LINE, text, TEXT_SCALE, color);
+        };
+        let color = |changed: bool| if changed {CHANGED} else {TEXT};
+        for (n, &value) in state.v.iter().enumerate() {
+            let (column, row) = (n % 4 * 7, n / 4);
+            put(column, row, &format!("V{:X}", n), LABEL);
+            put(column + 3, row, &format!("{:02X}", value), color(value != before.v[n]));
+        }
+        put(0, 4, "I", LABEL);
+        put(3, 4, &format!("{:#05X}", state.i), color(state.i != before.i));
+        put(14, 4, "PC", LABEL);
+        put(17, 4, &format!("{:#05X}", state.pc), color(state.pc != before.pc));
+        put(0, 5, "SP", LABEL);
+        put(3, 5, &format!("{:02X}", state.sp), color(state.sp != before.sp));
+        put(7, 5, "DT", LABEL);
+        put(10, 5, &format!("{:02X}", state.dt), color(state.dt != before.dt));
+        put(14, 5, "ST", LABEL);
+        put(17, 5, &format!("{:02X}", state.st), color(state.st != before.st));
+
+        // innermost call first
+        put(0, 7, "Stack", LABEL);
+        if state.stack.is_empty() {
+            put(7, 7, "-", TEXT);
+        }
+        for (n, &addr) in state.stack.iter().enumerate().rev() {
+            let at = state.stack.len() - 1 - n;
+            let changed = before.stack.get(n) != Some(&addr);
+            put(at % 4 * 6, 8 + at / 4, &format!("{:#05X}", addr), color(changed));
+        }
+
+        put(0, 13, "Keys", LABEL);
+        for (row, keys) in KEYPAD.iter().enumerate() {
+            for (column, &key) in keys.iter().enumerate() {
+                let (x, y) = (4 + column * 2 * CHAR, TOP + (14 + row) * LINE);
+                if state.keys[key] {
+                    canvas.fill_rect(x - 2, y - 2, CHAR + 2, LINE - 2, TEXT);
+                }
+                let text = if state.keys[key] {BACKGROUND} else {LABEL};
+                canvas.text(x, y, &format!("{:X}", key), TEXT_SCALE, text);
+            }
+        }
+
+        let breakpoints: Vec<u16> = self.debugger.breakpoints().map(|bp| bp.addr).collect();
+        canvas.text(4, TOP + 19 * LINE, "Breakpoints", TEXT_SCALE, LABEL);
+        for (n, addr) in breakpoints.iter().take(8).enumerate() {
+            let (x, y) = (4 + n % 4 * 6 * CHAR, TOP + (20 + n / 4) * LINE);
+            canvas.text(x, y, &format!("{:#05X}", addr), TEXT_SCALE, BREAKPOINT);
+        }
+
+        // code around the PC, a line per two bytes
+        let start = code_start(chip8) as usize;
+        for line in 0..CODE_LINES {
+            let addr = start + line * 2;
+            if addr + 1 >= state.ram.len() {
+                break;
+            }
+            let y = TOP + line * LINE;
+            if addr == state.pc as usize {
+                canvas.fill_rect(CODE_X, y - 2, WIDTH - CODE_X, LINE, CURRENT);
+                canvas.text(CODE_X + CHAR, y, ">", TEXT_SCALE, TEXT);
+            }
+            let color = if breakpoints.contains(&(addr as u16)) {
+                canvas.text(CODE_X, y, "*", TEXT_SCALE, BREAKPOINT);
+                BREAKPOINT
+            } else {
+                TEXT
+            };
+            let op = state.opcode(addr as u16);
+            let text = disassemble(op).unwrap_or_else(|| format!("DW #{:04X}", op));
+            canvas.text(CODE_X + 3 * CHAR, y, &format!("{:#05X} {:04X}  {}", addr, op, text), TEXT_SCALE, color);
+        }
+
+        let hex_start = self.hex_start.unwrap_or_else(|| follow(chip8));
+        let header = if self.hex_start.is_some() {"Memory   right click: follow I"} else {"Memory   scroll to move"};
+        canvas.text(4, HEX_Y, header, TEXT_SCALE, LABEL);
+        for row in 0..HEX_ROWS {
+            let y = HEX_Y + (row + 1) * LINE;
+            let addr = hex_start + row * HEX_COLUMNS;
+            canvas.text(4, y, &format!("{:#05X}", addr), TEXT_SCALE, LABEL);
+            for column in 0..HEX_COLUMNS {
+                let (addr, x) = (addr + column, 4 + (7 + column * 3) * CHAR);
+                if addr == state.i as usize {
+                    canvas.fill_rect(x - 2, y - 2, 2 * CHAR + 2, LINE, CURRENT);
+                }
+                let changed = state.ram[addr] != before.ram[addr];
+                canvas.text(x, y, &format!("{:02X}", state.ram[addr]), TEXT_SCALE, color(changed));
+            }
+        }
+
+        self.texture.update(None, &self.pixels, WIDTH * 3).unwrap();
+        self.canvas.copy(&self.texture, None, None).unwrap();
+        self.canvas.present();
+    }
+}
+
+// Each button with its left edge and width, in a row along the top
+fn buttons() -> impl Iterator<Item = (Button, &'static str, usize, usize)> {
+    let mut left = 4;
+    BUTTONS.iter().map(move |&(button, label)| {
+        let width = (label.len() + 2) * CHAR;
+        left += width + 8;
+        (button, label, left - width - 8, width)
+    })
+}
+
+// First address in the code listing, keeping the PC's line a few lines down
+fn code_start(chip8: &EMU) -> u16 {
+    let pc = chip8.state().pc;
+    pc.saturating_sub(2 * CODE_BEFORE)
+}
+
+// First address in the RAM view when it follows I, which is on the second row
+fn follow(chip8: &EMU) -> usize {
+    let state = chip8.state();
+    let row = (state.i as usize / HEX_COLUMNS).saturating_sub(1) * HEX_COLUMNS;
+    row.min(state.ram.len() - HEX_ROWS * HEX_COLUMNS)
+}
@@ -0,0 +1,174 @@
+// Game controller -> CHIP-8 keypad mapping
+//
+// Up to two controllers are handled, the first one plugged in is player 1. Inputs are named
+// the way SDL names them in controller mappings: buttons ("a", "dpup", "leftshoulder", ...)
+// and stick directions ("leftx-", "lefty+", ...). Bindings live in the keymap file under
+// `[pad1]` / `[pad2]`, and per ROM under `[rom.<sha1>.pad1]`.
+
+use chip8_core::EMU;
+use sdl2::GameControllerSubsystem;
+use sdl2::controller::{Axis, Button, GameController};
+use sdl2::event::Event;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+pub const NUM_PLAYERS: usize = 2;
+
+// how far a stick has to move before it counts, out of 32767
+const DEFAULT_DEADZONE: i32 = 8000;
+
+// d-pad and left stick on the 5/7/8/9 diamond most games use, A and B next to it
+const DEFAULT_MAP: [(&str, usize); 10] = [
+    ("dpup", 0x5), ("dpdown", 0x8), ("dpleft", 0x7), ("dpright", 0x9),
+    ("lefty-", 0x5), ("lefty+", 0x8), ("leftx-", 0x7), ("leftx+", 0x9),
+    ("a", 0x6), ("b", 0x4),
+];
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum PadInput {
+    Button(Button),
+    Axis(Axis, bool), // true for the positive direction
+}
+
+impl PadInput {
+    fn parse(name: &str) -> Option<PadInput> {
+        if let Some(axis) = name.strip_suffix('+') {
+            return Axis::from_string(axis).map(|axis| PadInput::Axis(axis, true));
+        }
+        if let Some(axis) = name.strip_suffix('-') {
+            return Axis::from_string(axis).map(|axis| PadInput::Axis(axis, false));
+        }
+        Button::from_string(name).map(PadInput::Button)
+    }
+}
+
+// `[pad1]` table of the keymap file, input name = CHIP-8 key
+#[derive(Deserialize, Clone, Default)]
+pub struct PadFile {
+    deadzone: Option<i32>,
+    #[serde(flatten)]
+    inputs: HashMap<String, String>,
+}
+
+#[derive(Clone)]
+pub struct PadMap {
+    deadzone: i32,
+    bindings: HashMap<PadInput, usize>,
+}
+
+impl Default for PadMap {
+    fn default() -> Self {
+        let bindings = DEFAULT_MAP
+            .iter()
+            .map(|&(name, button)| (PadInput::parse(name).expect("default pad input"), button))
+            .collect();
+        PadMap { deadzone: DEFAULT_DEADZONE, bindings }
+    }
+}
+
+impl PadMap {
+    // entries in `file` replace the binding of the same input
+    pub fn apply(&mut self, file: &PadFile) -> Result<(), String> {
+        if let Some(deadzone) = file.deadzone {
+            if !(0..=32767).contains(&deadzone) {
+                return Err(format!("Invalid controller deadzone {} (expected 0-32767)", deadzone));
+            }
+            self.deadzone = deadzone;
+        }
+
+        for (name, button) in &file.inputs {
+            let input = PadInput::parse(name).ok_or_else(|| format!("Unknown controller input '{}'", name))?;
+            let button = usize::from_str_radix(button, 16)
+                .ok()
+                .filter(|&idx| idx < 16)
+                .ok_or_else(|| format!("Invalid CHIP-8 key '{}' (expected 0-F)", button))?;
+            self.bindings.insert(input, button);
+        }
+        Ok(())
+    }
+}
+
+struct Player {
+    controller: GameController,
+    held: HashMap<PadInput, usize>, // inputs currently holding a CHIP-8 key down
+}
+
+pub struct Gamepads {
+    subsystem: GameControllerSubsystem,
+    players: [Option<Player>; NUM_PLAYERS],
+}
+
+impl Gamepads {
+    pub fn new(subsystem: GameControllerSubsystem) -> Self {
+        Gamepads { subsystem, players: [None, None] }
+    }
+
+    // Returns false for events that are not about controllers
+    pub fn handle(&mut self, event: &Event, maps: &[PadMap; NUM_PLAYERS], emu: &mut EMU) -> bool {
+        match *event {
+            // also sent for every controller already plugged in at startup
+            Event::ControllerDeviceAdded{which, ..} => {
+                let Some(slot) = self.players.iter().position(|p| p.is_none()) else {
+                    return true;
+                };
+                match self.subsystem.open(which) {
+                    Ok(controller) => {
+                        println!("Controller '{}' connected as player {}", controller.name(), slot + 1);
+                        self.players[slot] = Some(Player { controller, held: HashMap::new() });
+                    },
+                    Err(e) => eprintln!("Unable to open controller {}: {}", which, e),
+                }
+            },
+            Event::ControllerDeviceRemoved{which, ..} => {
+                if let Some(slot) = self.slot(which) {
+                    let player = self.players[slot].take().unwrap();
+                    for (_, button) in player.held {
+                        emu.keypress(button, false);
+                    }
+                    println!("Player {} controller disconnected", slot + 1);
+                }
+            },
+            Event::ControllerButtonDown{which, button, ..} => {
+                if let Some(slot) = self.slot(which) {
+                    self.set(slot, PadInput::Button(button), true, &maps[slot], emu);
+                }
+            },
+            Event::ControllerButtonUp{which, button, ..} => {
+                if let Some(slot) = self.slot(which) {
+                    self.set(slot, PadInput::Button(button), false, &maps[slot], emu);
+                }
+            },
+            Event::ControllerAxisMotion{which, axis, value, ..} => {
+                if let Some(slot) = self.slot(which) {
+                    let deadzone = maps[slot].deadzone;
+                    let value = value as i32;
+                    self.set(slot, PadInput::Axis(axis, true), value > deadzone, &maps[slot], emu);
+                    self.set(slot, PadInput::Axis(axis, false), value < -deadzone, &maps[slot], emu);
+                }
+            },
+            _ => return false,
+        }
+        true
+    }
+
+    fn slot(&self, instance_id: u32) -> Option<usize> {
+        self.players.iter().position(|p| {
+            p.as_ref().is_some_and(|p| p.controller.instance_id() == instance_id)
+        })
+    }
+
+    fn set(&mut self, slot: usize, input: PadInput, pressed: bool, map: &PadMap, emu: &mut EMU) {
+        let player = self.players[slot].as_mut().unwrap();
+        if pressed {
+            if player.held.contains_key(&input) {
+                return;
+            }
+            if let Some(&button) = map.bindings.get(&input) {
+                player.held.insert(input, button);
+                emu.keypress(button, true);
+            }
+        } else if let Some(button) = player.held.remove(&input) {
+            emu.keypress(button, false);
+        }
+    }
+}
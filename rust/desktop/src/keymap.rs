@@ -0,0 +1,361 @@
+// Keyboard -> CHIP-8 keypad mapping
+//
+// The mapping is read from a TOML file, by default `<XDG config dir>/chip8/keymap.toml`.
+// Every CHIP-8 key (0-F) can be bound to several keyboard keys, and a ROM can get its
+// own bindings through a `[rom.<sha1 of the rom>]` table. See `keymap.example.toml`.
+//
+// Keys are matched by physical position (scancode) unless the file says `input = "keycode"`,
+// so the 4x4 block stays in place on AZERTY, Dvorak and friends.
+
+use crate::gamepad::{PadFile, PadMap, NUM_PLAYERS};
+use sdl2::keyboard::{Keycode, Scancode};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const NUM_BUTTONS: usize = 16;
+
+// CHIP-8 keypad, top left to bottom right
+const KEYPAD: [usize; NUM_BUTTONS] = [
+    0x1, 0x2, 0x3, 0xC,
+    0x4, 0x5, 0x6, 0xD,
+    0x7, 0x8, 0x9, 0xE,
+    0xA, 0x0, 0xB, 0xF,
+];
+
+// 1 2 3 4    1 2 3 C
+// Q W E R => 4 5 6 D
+// A S D F    7 8 9 E
+// Z X C V    A 0 B F
+// (positions on a US keyboard)
+const QWERTY: [(Scancode, usize); NUM_BUTTONS] = [
+    (Scancode::Num1, 0x1), (Scancode::Num2, 0x2), (Scancode::Num3, 0x3), (Scancode::Num4, 0xC),
+    (Scancode::Q, 0x4), (Scancode::W, 0x5), (Scancode::E, 0x6), (Scancode::R, 0xD),
+    (Scancode::A, 0x7), (Scancode::S, 0x8), (Scancode::D, 0x9), (Scancode::F, 0xE),
+    (Scancode::Z, 0xA), (Scancode::X, 0x0), (Scancode::C, 0xB), (Scancode::V, 0xF),
+];
+
+// Same 4x4 block, laid over the numeric keypad
+const NUMPAD: [(Scancode, usize); NUM_BUTTONS] = [
+    (Scancode::Kp7, 0x1), (Scancode::Kp8, 0x2), (Scancode::Kp9, 0x3), (Scancode::KpDivide, 0xC),
+    (Scancode::Kp4, 0x4), (Scancode::Kp5, 0x5), (Scancode::Kp6, 0x6), (Scancode::KpMultiply, 0xD),
+    (Scancode::Kp1, 0x7), (Scancode::Kp2, 0x8), (Scancode::Kp3, 0x9), (Scancode::KpMinus, 0xE),
+    (Scancode::Kp0, 0xA), (Scancode::KpPeriod, 0x0), (Scancode::KpEnter, 0xB), (Scancode::KpPlus, 0xF),
+];
+
+// COSMAC VIP style, every hex digit is typed with its own key
+const COSMAC: [(Scancode, usize); NUM_BUTTONS] = [
+    (Scancode::Num0, 0x0), (Scancode::Num1, 0x1), (Scancode::Num2, 0x2), (Scancode::Num3, 0x3),
+    (Scancode::Num4, 0x4), (Scancode::Num5, 0x5), (Scancode::Num6, 0x6), (Scancode::Num7, 0x7),
+    (Scancode::Num8, 0x8), (Scancode::Num9, 0x9), (Scancode::A, 0xA), (Scancode::B, 0xB),
+    (Scancode::C, 0xC), (Scancode::D, 0xD), (Scancode::E, 0xE), (Scancode::F, 0xF),
+];
+
+#[derive(Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum InputMode {
+    Scancode,
+    Keycode,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum Key {
+    Scan(Scancode),
+    Code(Keycode),
+}
+
+impl Key {
+    fn parse(mode: InputMode, name: &str) -> Option<Key> {
+        match mode {
+            InputMode::Scancode => Scancode::from_name(name).map(Key::Scan),
+            InputMode::Keycode => Keycode::from_name(name).map(Key::Code),
+        }
+    }
+
+    // what is printed on the key with the current keyboard layout
+    fn label(self) -> String {
+        match self {
+            Key::Scan(scancode) => match Keycode::from_scancode(scancode) {
+                Some(keycode) => keycode.name(),
+                None => scancode.name().to_string(),
+            },
+            Key::Code(keycode) => keycode.name(),
+        }
+    }
+}
+
+pub struct Keymap {
+    mode: InputMode,
+    bindings: HashMap<Key, usize>,
+    pub pads: [PadMap; NUM_PLAYERS],
+}
+
+impl Keymap {
+    pub fn button(&self, keycode: Option<Keycode>, scancode: Option<Scancode>) -> Option<usize> {
+        let key = match self.mode {
+            InputMode::Scancode => Key::Scan(scancode?),
+            InputMode::Keycode => Key::Code(keycode?),
+        };
+        self.bindings.get(&key).copied()
+    }
+
+    // The keypad drawn with the labels of the keys bound to it
+    pub fn help(&self) -> String {
+        let mut labels = vec![Vec::new(); NUM_BUTTONS];
+        for (&key, &button) in &self.bindings {
+            labels[button].push(key.label());
+        }
+
+        let mut help = String::new();
+        for (i, &button) in KEYPAD.iter().enumerate() {
+            labels[button].sort();
+            let keys = if labels[button].is_empty() { "-".to_string() } else { labels[button].join("/") };
+            help.push_str(&format!("{:X}: {:<12}", button, keys));
+            help.push(if i % 4 == 3 { '\n' } else { ' ' });
+        }
+        help
+    }
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct KeymapFile {
+    input: Option<InputMode>,
+    preset: Option<String>,
+    #[serde(default)]
+    keys: HashMap<String, KeyList>,
+    pad1: Option<PadFile>,
+    pad2: Option<PadFile>,
+    #[serde(default)]
+    rom: HashMap<String, RomKeymap>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RomKeymap {
+    preset: Option<String>,
+    #[serde(default)]
+    keys: HashMap<String, KeyList>,
+    pad1: Option<PadFile>,
+    pad2: Option<PadFile>,
+}
+
+// "5" = "W" or "5" = ["W", "Up"]
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum KeyList {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl KeyList {
+    fn names(&self) -> &[String] {
+        match self {
+            KeyList::One(name) => std::slice::from_ref(name),
+            KeyList::Many(names) => names,
+        }
+    }
+}
+
+pub fn default_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("chip8").join("keymap.toml"))
+}
+
+pub fn rom_hash(rom: &[u8]) -> String {
+    sha1_smol::Sha1::from(rom).digest().to_string()
+}
+
+// An explicit `path` has to exist, the default file is optional.
+// `mode` from the command line wins over the one in the file.
+pub fn load(path: Option<&Path>, mode: Option<InputMode>, rom: &[u8]) -> Result<Keymap, String> {
+    let file = match path {
+        Some(path) => read_file(path)?,
+        None => match default_path() {
+            Some(path) if path.exists() => read_file(&path)?,
+            _ => KeymapFile::default(),
+        },
+    };
+    from_file(file, mode, rom)
+}
+
+fn from_file(file: KeymapFile, mode: Option<InputMode>, rom: &[u8]) -> Result<Keymap, String> {
+    let mode = mode.or(file.input).unwrap_or(InputMode::Scancode);
+
+    let mut buttons = preset(mode, file.preset.as_deref().unwrap_or("qwerty"))?;
+    apply_keys(mode, &mut buttons, &file.keys)?;
+    let mut pads = [PadMap::default(), PadMap::default()];
+    apply_pads(&mut pads, [&file.pad1, &file.pad2])?;
+
+    // per ROM overrides start from their own preset when they name one
+    if let Some(rom_map) = file.rom.get(&rom_hash(rom)) {
+        if let Some(name) = &rom_map.preset {
+            buttons = preset(mode, name)?;
+        }
+        apply_keys(mode, &mut buttons, &rom_map.keys)?;
+        apply_pads(&mut pads, [&rom_map.pad1, &rom_map.pad2])?;
+    }
+
+    let mut keymap = build(mode, &buttons)?;
+    keymap.pads = pads;
+    Ok(keymap)
+}
+
+fn read_file(path: &Path) -> Result<KeymapFile, String> {
+    let text = fs::read_to_string(path)
+        .map_err(|e| format!("Unable to read keymap {}: {}", path.display(), e))?;
+    toml::from_str(&text).map_err(|e| format!("Invalid keymap {}: {}", path.display(), e))
+}
+
+// keys bound to every CHIP-8 button, indexed by button
+// keycode presets use the key printed at that spot on a US keyboard
+fn preset(mode: InputMode, name: &str) -> Result<Vec<Vec<Key>>, String> {
+    let table = match name {
+        "qwerty" => &QWERTY,
+        "numpad" => &NUMPAD,
+        "cosmac" => &COSMAC,
+        _ => return Err(format!("Unknown keymap preset '{}' (expected qwerty, numpad or cosmac)", name)),
+    };
+
+    let mut buttons = vec![Vec::new(); NUM_BUTTONS];
+    for &(scancode, button) in table.iter() {
+        let key = match mode {
+            InputMode::Scancode => Key::Scan(scancode),
+            InputMode::Keycode => Key::parse(mode, scancode.name()).expect("preset key has a keycode"),
+        };
+        buttons[button].push(key);
+    }
+    Ok(buttons)
+}
+
+// a button listed in `keys` loses the keys it had before
+fn apply_keys(mode: InputMode, buttons: &mut [Vec<Key>], keys: &HashMap<String, KeyList>) -> Result<(), String> {
+    for (button, list) in keys {
+        let idx = usize::from_str_radix(button, 16)
+            .ok()
+            .filter(|&idx| idx < NUM_BUTTONS)
+            .ok_or_else(|| format!("Invalid CHIP-8 key '{}' (expected 0-F)", button))?;
+
+        let mut bound = Vec::new();
+        for name in list.names() {
+            let key = Key::parse(mode, name).ok_or_else(|| format!("Unknown key name '{}'", name))?;
+            bound.push(key);
+        }
+        buttons[idx] = bound;
+    }
+    Ok(())
+}
+
+fn apply_pads(pads: &mut [PadMap; NUM_PLAYERS], files: [&Option<PadFile>; NUM_PLAYERS]) -> Result<(), String> {
+    for (pad, file) in pads.iter_mut().zip(files) {
+        if let Some(file) = file {
+            pad.apply(file)?;
+        }
+    }
+    Ok(())
+}
+
+fn build(mode: InputMode, buttons: &[Vec<Key>]) -> Result<Keymap, String> {
+    let mut bindings = HashMap::new();
+    let mut conflicts = Vec::new();
+
+    for (button, keys) in buttons.iter().enumerate() {
+        for &key in keys {
+            if let Some(other) = bindings.insert(key, button)
+                && other != button
+            {
+                conflicts.push(format!("'{}' is bound to both {:X} and {:X}", key.label(), other, button));
+            }
+        }
+    }
+
+    if !conflicts.is_empty() {
+        return Err(format!("Conflicting key bindings:\n  {}", conflicts.join("\n  ")));
+    }
+    Ok(Keymap { mode, bindings, pads: Default::default() })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keymap(toml: &str, rom: &[u8]) -> Result<Keymap, String> {
+        from_file(toml::from_str(toml).map_err(|e| e.to_string())?, None, rom)
+    }
+
+    fn scan(keymap: &Keymap, scancode: Scancode) -> Option<usize> {
+        keymap.button(None, Some(scancode))
+    }
+
+    #[test]
+    fn conflicts_are_reported() {
+        let mut buttons = vec![Vec::new(); NUM_BUTTONS];
+        buttons[1] = vec![Key::Scan(Scancode::A), Key::Scan(Scancode::A)];
+        assert!(build(InputMode::Scancode, &buttons).is_ok());
+        buttons[2] = vec![Key::Scan(Scancode::B), Key::Scan(Scancode::A)];
+        buttons[0xC] = vec![Key::Scan(Scancode::B)];
+        assert_eq!(
+            build(InputMode::Scancode, &buttons).err().unwrap(),
+            "Conflicting key bindings:\n  'A' is bound to both 1 and 2\n  'B' is bound to both 2 and C"
+        );
+
+        // Q is still bound to 4
+        let e = keymap("[keys]\n1 = \"Q\"\n", &[]).err().unwrap();
+        assert_eq!(e, "Conflicting key bindings:\n  'Q' is bound to both 1 and 4");
+    }
+
+    #[test]
+    fn keys_replace_the_preset() {
+        let default = keymap("", &[]).unwrap();
+        assert_eq!(scan(&default, Scancode::W), Some(0x5));
+        assert_eq!(scan(&default, Scancode::V), Some(0xF));
+        assert_eq!(scan(&default, Scancode::Up), None);
+
+        let map = keymap("[keys]\n5 = [\"Up\", \"I\"]\nf = \"W\"\n", &[]).unwrap();
+        assert_eq!(scan(&map, Scancode::W), Some(0xF));
+        assert_eq!((scan(&map, Scancode::Up), scan(&map, Scancode::I)), (Some(0x5), Some(0x5)));
+        assert_eq!(scan(&map, Scancode::V), None);
+        assert_eq!(scan(&map, Scancode::Q), Some(0x4));
+
+        let numpad = keymap("preset = \"numpad\"\n", &[]).unwrap();
+        assert_eq!((scan(&numpad, Scancode::Kp8), scan(&numpad, Scancode::W)), (Some(0x2), None));
+    }
+
+    #[test]
+    fn roms_override_the_global_keys() {
+        let rom = [0x12, 0x00];
+        let toml = format!(
+            "preset = \"numpad\"\n[keys]\n0 = \"Space\"\n[rom.{}]\npreset = \"qwerty\"\n[rom.{}.keys]\n2 = \"Up\"\n",
+            rom_hash(&rom),
+            rom_hash(&rom)
+        );
+        let other = keymap(&toml, &[0x00]).unwrap();
+        assert_eq!((scan(&other, Scancode::Kp8), scan(&other, Scancode::Space)), (Some(0x2), Some(0x0)));
+        assert_eq!(scan(&other, Scancode::Up), None);
+
+        // the ROM's own preset starts over, without the global keys
+        let map = keymap(&toml, &rom).unwrap();
+        assert_eq!((scan(&map, Scancode::Kp8), scan(&map, Scancode::Space)), (None, None));
+        assert_eq!((scan(&map, Scancode::Up), scan(&map, Scancode::Num2)), (Some(0x2), None));
+        assert_eq!(scan(&map, Scancode::X), Some(0x0));
+    }
+
+    #[test]
+    fn bad_names() {
+        assert_eq!(keymap("[keys]\ng = \"A\"\n", &[]).err().unwrap(), "Invalid CHIP-8 key 'g' (expected 0-F)");
+        assert_eq!(keymap("[keys]\n10 = \"A\"\n", &[]).err().unwrap(), "Invalid CHIP-8 key '10' (expected 0-F)");
+        assert_eq!(keymap("[keys]\n1 = \"Nope\"\n", &[]).err().unwrap(), "Unknown key name 'Nope'");
+        assert_eq!(
+            keymap("preset = \"dvorak\"\n", &[]).err().unwrap(),
+            "Unknown keymap preset 'dvorak' (expected qwerty, numpad or cosmac)"
+        );
+    }
+
+    #[test]
+    fn keycodes() {
+        let map = from_file(toml::from_str("[keys]\n5 = \"Up\"\n").unwrap(), Some(InputMode::Keycode), &[]).unwrap();
+        assert_eq!(map.button(Some(Keycode::Up), Some(Scancode::I)), Some(0x5));
+        assert_eq!(map.button(Some(Keycode::Q), None), Some(0x4));
+        assert_eq!(map.button(None, Some(Scancode::Q)), None);
+    }
+}
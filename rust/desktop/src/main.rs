@@ -1,69 +1,306 @@
+mod browser;
+mod capture;
+mod catalog;
+mod cli;
+mod config;
+mod crt;
+mod debugview;
+mod font;
+mod gamepad;
+mod keymap;
+mod memview;
+mod palette;
+mod playback;
+mod recent;
+mod romdb;
+
 use chip8_core::*;
-use std::fs::File;
-use std::io::Read;
-use std::env;
-use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
-use sdl2::pixels::Color;
-use sdl2::rect::Rect;
-use sdl2::render::Canvas;
+use browser::Browser;
+use capture::Capture;
+use catalog::Catalog;
+use clap::Parser;
+use cli::Args;
+use crt::{Effects, Pipeline};
+use debugview::DebugView;
+use gamepad::Gamepads;
+use memview::MemoryView;
+use palette::Palettes;
+use playback::Playback;
+use recent::Recent;
+use std::fmt::Display;
+use std::fs;
+use std::path::{Path, PathBuf};
+use sdl2::event::{Event, WindowEvent};
+use sdl2::keyboard::{Keycode, Mod};
+use sdl2::messagebox::{show_simple_message_box, MessageBoxFlag};
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::render::{Canvas, Texture};
 use sdl2::video::Window;
 use rodio::{OutputStream, Sink, Source};
-use std::time::Duration;
+use std::time::{Duration, Instant};
+
+const FRAMES_PER_SECOND: u32 = 60;
 
-const SCALE: u32 = 15;
-const WINDOW_WIDTH: u32 = (SCREEN_WIDTH as u32) * SCALE;
-const WINDOW_HEIGHT: u32 = (SCREEN_HEIGHT as u32) * SCALE;
-const TICKS_PER_FRAME: usize = 10;
+const HOTKEYS: &str = "F1 help    F2 palette    F3 screen effects    Ctrl+1-5 single effects
+F5 save state    F6 pause    F7 frame advance    F8 reset    F9 slow motion
+Tab (hold) fast-forward    F4 ROM browser    F10 record GIF    F12 screenshot    Esc quit
+F11 memory heatmap    Shift+F11 debugger";
 
 fn main() {
-    let args: Vec<_> = env::args().collect();
-    if args.len() != 2 {
-        println!("Usage: cargo run path/to/game");
+    let args = Args::parse();
+
+    // without a ROM we start in the browser
+    let mut rom = args.rom.clone();
+    let mut buffer = match &rom {
+        Some(path) => read_rom(path).unwrap_or_else(|e| exit_with(e)),
+        None => Vec::new(),
+    };
+    let roms_dir = args.roms.clone()
+        .or_else(|| rom.as_ref().and_then(|p| p.parent()).map(|dir| {
+            if dir.as_os_str().is_empty() {PathBuf::from(".")} else {dir.to_path_buf()}
+        }))
+        .unwrap_or_else(|| PathBuf::from("ROMS"));
+    let catalog_path = args.catalog.clone()
+        .unwrap_or_else(|| roms_dir.join("..").join("game_catalog.txt"));
+    let romdb = romdb::load(args.romdb.as_deref()).unwrap_or_else(|e| exit_with(e));
+    if let Some(path) = &args.write_catalog {
+        let text = romdb.catalog(&roms_dir).unwrap_or_else(|e| exit_with(e));
+        fs::write(path, text).unwrap_or_else(|e| exit_with(format!("Unable to write {}: {}", path.display(), e)));
+        println!("Wrote {}", path.display());
         return;
     }
+    let catalog = Catalog::load(&catalog_path).unwrap_or_else(|e| exit_with(e));
+    let mut recent = Recent::load();
+    if let Some(path) = &rom {
+        recent.add(path);
+    }
+
+    // settings for this ROM from the database, the command line wins over them
+    let mut rom_info = rom.as_ref().and_then(|_| rom_settings(&romdb, &buffer));
+    let mut quirks = args.quirks(rom_info.as_ref()).unwrap_or_else(|e| exit_with(e));
+    let config = config::load().unwrap_or_else(|e| exit_with(e));
+    let selected_palette = args.palette.as_deref()
+        .or(rom_info.as_ref().and_then(|i| i.palette.as_deref()))
+        .or(config.palette.as_deref());
+    let mut palettes = Palettes::new(&config.palettes, selected_palette).unwrap_or_else(|e| exit_with(e));
+    let effects = Effects::from_settings(&[&config.effects, &args.effects]).unwrap_or_else(|e| exit_with(e));
+    let input_mode = args.keycodes.then_some(keymap::InputMode::Keycode);
+    let mut keymap = keymap::load(args.keymap.as_deref(), input_mode, &buffer).unwrap_or_else(|e| exit_with(e));
+
+    let mut chip8 = start(&buffer, quirks, args.seed);
+    if let Some(path) = &args.load_state {
+        if rom.is_none() {
+            exit_with("--load-state needs the ROM it was saved from");
+        }
+        let state = fs::read(path)
+            .unwrap_or_else(|e| exit_with(format!("Unable to read save state {}: {}", path.display(), e)));
+        if let Err(e) = chip8.load_state(&state) {
+            exit_with(format!("Unable to load save state {}: {}", path.display(), e));
+        }
+    }
+    let mut ticks_per_frame = (args.speed(rom_info.as_ref()) / FRAMES_PER_SECOND) as usize;
 
     // Setup SDL
+    let scale = args.scale;
+    let capture_scale = args.capture_scale.unwrap_or(scale);
+    let mut capture = Capture::new(capture_scale, args.capture_filter)
+        .unwrap_or_else(|e| exit_with(e));
+    let mut dump = args.dump_av.as_ref().map(|paths| {
+        // our messages go to stdout
+        if paths[0] == Path::new("-") {
+            exit_with("The desktop app can't dump video to stdout, use a named pipe or the headless runner");
+        }
+        av::FileDump::create(&paths[0], &paths[1], &palettes.current().to_rgba(), capture_scale as usize, args.capture_filter)
+            .unwrap_or_else(|e| exit_with(format!("Unable to dump to {} and {}: {}", paths[0].display(), paths[1].display(), e)))
+    });
+    let window_width = (SCREEN_WIDTH as u32) * scale;
+    let window_height = (SCREEN_HEIGHT as u32) * scale;
+
     let sdl_context = sdl2::init().unwrap();
     let video_subsystem = sdl_context.video().unwrap();
-    let window = video_subsystem
-        .window("Chip-8 Emulator", WINDOW_WIDTH, WINDOW_HEIGHT)
-        .position_centered()
-        .opengl()
-        .build()
-        .unwrap();
+    let mut gamepads = Gamepads::new(sdl_context.game_controller().unwrap());
+    let mut title = window_title(rom.as_deref(), "");
+    let mut window_builder = video_subsystem.window(&title, window_width, window_height);
+    window_builder.position_centered().opengl();
+    if args.fullscreen {
+        window_builder.fullscreen_desktop();
+    }
+    let window = window_builder.build().unwrap();
 
     let mut canvas = window.into_canvas().present_vsync().build().unwrap();
+    // keeps the picture in proportion when fullscreen
+    canvas.set_logical_size(window_width, window_height).unwrap();
     canvas.clear();
     canvas.present();
 
+    // the whole picture is drawn in software and uploaded once per frame
+    let mut pipeline = Pipeline::new(scale, effects);
+    let mut effect_preset = 0;
+    let texture_creator = canvas.texture_creator();
+    let mut texture = texture_creator
+        .create_texture_streaming(PixelFormatEnum::RGB24, pipeline.width(), pipeline.height())
+        .unwrap();
+
     let mut event_pump = sdl_context.event_pump().unwrap();
 
     // Setup audio
-    let (_stream, stream_handle) = OutputStream::try_default().unwrap();
-    let sink = Sink::try_new(&stream_handle).unwrap();
-    
-    let mut chip8 = EMU::new();
-    let mut rom = File::open(&args[1]).expect("Unable to open file");
-    let mut buffer = Vec::new();
-    rom.read_to_end(&mut buffer).unwrap();
-    chip8.load(&buffer);
+    let audio = if args.mute {
+        None
+    } else {
+        let (stream, stream_handle) = OutputStream::try_default().unwrap();
+        let sink = Sink::try_new(&stream_handle).unwrap();
+        Some((stream, sink))
+    };
 
+    let mut display_filter = DisplayFilter::new(args.filter);
     let mut audio_playing = false;
+    let mut playback = Playback::new(args.fast_forward, args.slow_motion);
+    let mut browser = rom.is_none().then(|| Browser::new(&roms_dir, &catalog, recent.list()));
+    let mut memory_view: Option<MemoryView> = None;
+    let mut debug_view: Option<DebugView> = None;
 
     'gameloop: loop {
+        let mut next_rom = None;
         for evt in event_pump.poll_iter() {
+            if let Event::DropFile{filename, ..} = &evt {
+                next_rom = Some(PathBuf::from(filename));
+                continue;
+            }
+            // with another window open, closing the game window doesn't quit by itself
+            if let Event::Window{win_event: WindowEvent::Close, window_id, ..} = evt
+                && window_id == canvas.window().id() {
+                break 'gameloop;
+            }
+            // the game keys keep working in the memory window
+            if let Some(view) = &mut memory_view
+                && evt.get_window_id() == Some(view.window_id())
+                && !matches!(evt, Event::KeyDown{..} | Event::KeyUp{..}) {
+                if !view.handle(&evt) {
+                    memory_view = None;
+                }
+                continue;
+            }
+            if let Some(view) = &mut debug_view
+                && evt.get_window_id() == Some(view.window_id())
+                && !matches!(evt, Event::KeyDown{..} | Event::KeyUp{..}) {
+                if !view.handle(&evt, &mut chip8, &mut playback) {
+                    debug_view = None;
+                }
+                continue;
+            }
+            if gamepads.handle(&evt, &keymap.pads, &mut chip8) {
+                continue;
+            }
+            if let Some(b) = &mut browser {
+                match evt {
+                    Event::Quit{..} => break 'gameloop,
+                    // back to the game, if there is one
+                    Event::KeyDown{keycode: Some(Keycode::Escape | Keycode::F4), ..} => {
+                        if rom.is_none() {
+                            break 'gameloop;
+                        }
+                        browser = None;
+                    },
+                    Event::KeyDown{keycode: Some(key), ..} => next_rom = b.handle_key(key),
+                    _ => (),
+                }
+                continue;
+            }
+            // the browser is open whenever there's no ROM
+            let Some(rom) = &rom else {
+                continue;
+            };
             match evt {
                 Event::Quit{..} | Event::KeyDown{keycode: Some(Keycode::Escape), ..} => {
                     break 'gameloop;
                 },
-                Event::KeyDown{keycode: Some(key), ..} => {
-                    if let Some(k) = key_to_button(key) {
+                Event::KeyDown{keycode: Some(Keycode::F4), ..} => {
+                    browser = Some(Browser::new(&roms_dir, &catalog, recent.list()));
+                },
+                Event::KeyDown{keycode: Some(Keycode::F5), ..} => {
+                    let state_path = rom.with_extension("state");
+                    match fs::write(&state_path, chip8.save_state()) {
+                        Ok(()) => println!("Saved state to {}", state_path.display()),
+                        Err(e) => eprintln!("Unable to save state to {}: {}", state_path.display(), e),
+                    }
+                },
+                Event::KeyDown{keycode: Some(Keycode::F6 | Keycode::Pause), repeat: false, ..} => {
+                    playback.toggle_pause();
+                },
+                Event::KeyDown{keycode: Some(Keycode::F7), ..} => {
+                    playback.advance();
+                },
+                Event::KeyDown{keycode: Some(Keycode::F8), ..} => {
+                    // reset() clears RAM, the program included
+                    chip8.reset();
+                    if let Some(seed) = args.seed {
+                        chip8.seed(seed);
+                    }
+                    chip8.load(&buffer);
+                    println!("Reset");
+                },
+                Event::KeyDown{keycode: Some(Keycode::F9), repeat: false, ..} => {
+                    playback.toggle_slow_motion();
+                },
+                Event::KeyDown{keycode: Some(Keycode::Tab), ..} => {
+                    playback.set_fast_forward(true);
+                },
+                Event::KeyUp{keycode: Some(Keycode::Tab), ..} => {
+                    playback.set_fast_forward(false);
+                },
+                Event::KeyDown{keycode: Some(Keycode::F11), keymod, ..} if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) => {
+                    debug_view = match debug_view.take() {
+                        Some(_) => None,
+                        None => DebugView::new(&video_subsystem, &chip8, ticks_per_frame).map_err(|e| eprintln!("{}", e)).ok(),
+                    };
+                },
+                Event::KeyDown{keycode: Some(Keycode::F11), ..} => {
+                    memory_view = match memory_view.take() {
+                        Some(_) => None,
+                        None => MemoryView::new(&video_subsystem).map_err(|e| eprintln!("{}", e)).ok(),
+                    };
+                },
+                Event::KeyDown{keycode: Some(Keycode::F12), ..} => {
+                    match capture.screenshot(rom, &chip8, &palettes.current().to_rgba()) {
+                        Ok(path) => println!("Saved screenshot to {}", path.display()),
+                        Err(e) => eprintln!("{}", e),
+                    }
+                },
+                Event::KeyDown{keycode: Some(Keycode::F10), ..} => {
+                    if capture.is_recording() {
+                        match capture.stop_recording() {
+                            Ok(path) => println!("Saved recording to {}", path.display()),
+                            Err(e) => eprintln!("{}", e),
+                        }
+                    } else {
+                        match capture.start_recording(rom, &palettes.current().to_rgba()) {
+                            Ok(path) => println!("Recording to {}", path.display()),
+                            Err(e) => eprintln!("{}", e),
+                        }
+                    }
+                },
+                Event::KeyDown{keycode: Some(Keycode::F2), ..} => {
+                    println!("Palette: {}", palettes.cycle().name);
+                },
+                Event::KeyDown{keycode: Some(Keycode::F3), ..} => {
+                    effect_preset = (effect_preset + 1) % crt::PRESETS.len();
+                    pipeline.set_effects(Effects::preset(crt::PRESETS[effect_preset]).unwrap());
+                    println!("Effects: {}", crt::PRESETS[effect_preset]);
+                },
+                // Ctrl+1-5 toggle single effects
+                Event::KeyDown{keycode: Some(key), keymod, ..}
+                    if keymod.intersects(Mod::LCTRLMOD | Mod::RCTRLMOD) && toggle_effect(&mut pipeline, key) => {},
+                Event::KeyDown{keycode: Some(Keycode::F1), ..} => {
+                    let help = format!("{}CHIP-8 keypad:\n\n{}\n\n{}", rom_help(rom_info.as_ref()), keymap.help(), HOTKEYS);
+                    let _ = show_simple_message_box(MessageBoxFlag::INFORMATION, "Controls", &help, canvas.window());
+                },
+                Event::KeyDown{keycode, scancode, ..} => {
+                    if let Some(k) = keymap.button(keycode, scancode) {
                         chip8.keypress(k, true);
                     }
                 },
-                Event::KeyUp{keycode: Some(key), ..} => {
-                    if let Some(k) = key_to_button(key) {
+                Event::KeyUp{keycode, scancode, ..} => {
+                    if let Some(k) = keymap.button(keycode, scancode) {
                         chip8.keypress(k, false);
                     }
                 },
@@ -71,76 +308,230 @@ fn main() {
             }
         }
 
-        for _ in 0..TICKS_PER_FRAME {
-            chip8.tick();
+        if let Some(path) = next_rom {
+            match read_rom(&path) {
+                Ok(data) => {
+                    keymap = keymap::load(args.keymap.as_deref(), input_mode, &data).unwrap_or_else(|e| {
+                        eprintln!("{}, keeping the previous keymap", e);
+                        keymap
+                    });
+                    rom_info = rom_settings(&romdb, &data);
+                    quirks = args.quirks(rom_info.as_ref()).unwrap_or_else(|e| {
+                        eprintln!("{}", e);
+                        Quirks::default()
+                    });
+                    ticks_per_frame = (args.speed(rom_info.as_ref()) / FRAMES_PER_SECOND) as usize;
+                    let palette = args.palette.as_deref()
+                        .or(rom_info.as_ref().and_then(|i| i.palette.as_deref()))
+                        .or(config.palette.as_deref());
+                    if let Some(Err(e)) = palette.map(|p| palettes.select(p)) {
+                        eprintln!("{}", e);
+                    }
+                    chip8 = start(&data, quirks, args.seed);
+                    if let Some(view) = &mut debug_view {
+                        view.restart(&chip8, ticks_per_frame);
+                    }
+                    display_filter = DisplayFilter::new(display_filter.mode());
+                    buffer = data;
+                    recent.add(&path);
+                    println!("Loaded {}", path.display());
+                    rom = Some(path);
+                    browser = None;
+                },
+                Err(e) => eprintln!("{}", e),
+            }
         }
-        chip8.tick_timers();
 
-        // Handle audio
-        let should_beep = chip8.get_sound_timer() > 0;
-        
-        if should_beep {
-            if !audio_playing {
-                println!("Starting continuous beep - sound timer: {}", chip8.get_sound_timer());
-                // Create a continuous beep source
-                let beep_source = ContinuousBeepSource::new(440.0);
-                sink.append(beep_source);
-                sink.play();
-                audio_playing = true;
+        // the game waits while the browser is open
+        let frames = if browser.is_some() {0} else {playback.frames(Instant::now())};
+        for _ in 0..frames {
+            let stopped = match &mut debug_view {
+                // the debugger runs the frame, and can stop halfway through it
+                Some(view) => !view.run_frame(&mut chip8, ticks_per_frame, memory_view.as_mut()),
+                None => {
+                    for _ in 0..ticks_per_frame {
+                        match &mut memory_view {
+                            Some(view) => view.tick(&mut chip8),
+                            None => chip8.tick(),
+                        }
+                    }
+                    chip8.tick_timers();
+                    false
+                },
+            };
+            if let Some(view) = &mut memory_view {
+                view.end_frame();
             }
-        } else {
-            if audio_playing {
-                println!("Stopping beep");
+            display_filter.apply(chip8.get_display());
+            if let Err(e) = capture.frame(&chip8) {
+                eprintln!("{}", e);
+            }
+            if let Some(d) = &mut dump
+                && let Err(e) = d.frame(&chip8) {
+                eprintln!("Dump stopped: {}", e);
+                dump = None;
+            }
+            if stopped {
+                playback.set_paused(true);
+                break;
+            }
+        }
+
+        let status = if browser.is_some() {"ROM browser".to_string()} else {playback.status()};
+        if window_title(rom.as_deref(), &status) != title {
+            title = window_title(rom.as_deref(), &status);
+            let _ = canvas.window_mut().set_title(&title);
+        }
+
+        // Handle audio, a paused game holds its breath
+        let should_beep = chip8.get_sound_timer() > 0 && browser.is_none() && !playback.is_paused();
+
+        if let Some((_, sink)) = &audio {
+            if should_beep {
+                if !audio_playing {
+                    // Create a continuous beep source
+                    let beep_source = ContinuousBeepSource::new(440.0);
+                    sink.append(beep_source);
+                    sink.play();
+                    audio_playing = true;
+                }
+            } else if audio_playing {
                 sink.stop();
                 audio_playing = false;
             }
         }
 
-        draw_screen(&chip8, &mut canvas);
+        let pitch = pipeline.pitch();
+        let pixels = match &mut browser {
+            Some(b) => b.render(pipeline.width(), pipeline.height(), palettes.current()),
+            None => pipeline.render(display_filter.levels(), palettes.current()),
+        };
+        draw_screen(pixels, pitch, &mut canvas, &mut texture);
+        if let Some(view) = &mut memory_view {
+            view.draw(&chip8);
+        }
+        if let Some(view) = &mut debug_view {
+            view.draw(&chip8, playback.is_paused());
+        }
+        // vsync only holds back the drawing, without it (or above 60 Hz) wait for the next frame
+        if frames == 0 {
+            std::thread::sleep(playback.until_next_frame());
+        }
+    }
+
+    if let Some(dump) = dump
+        && let Err(e) = dump.close() {
+        eprintln!("Unable to finish dump: {}", e);
+    }
+
+    // don't leave a GIF without its trailer behind
+    if capture.is_recording() {
+        match capture.stop_recording() {
+            Ok(path) => println!("Saved recording to {}", path.display()),
+            Err(e) => eprintln!("{}", e),
+        }
     }
 }
 
-fn draw_screen(emu: &EMU, canvas: &mut Canvas<Window>) {
-    // Clear canvas as black
-    canvas.set_draw_color(Color::RGB(0, 0, 0));
-    canvas.clear();
+fn window_title(rom: Option<&Path>, status: &str) -> String {
+    let mut title = "Chip-8 Emulator".to_string();
+    if let Some(rom) = rom {
+        title += &format!(" - {}", rom.file_stem().unwrap_or_default().to_string_lossy());
+    }
+    if !status.is_empty() {
+        title += &format!(" [{}]", status);
+    }
+    title
+}
+
+// Settings for the ROM from the database, or guessed from its code if it's not in there
+fn rom_settings(romdb: &romdb::RomDb, rom: &[u8]) -> Option<romdb::RomInfo> {
+    let info = romdb.lookup(rom);
+    if romdb.get(rom).is_none()
+        && let Some(description) = info.as_ref().and_then(|i| i.description.as_deref())
+    {
+        println!("{}", description);
+    }
+    info
+}
 
-    let screen_buf = emu.get_display();
-    // Now set draw color to white, iterate through each point and see if it should be drawn
-    canvas.set_draw_color(Color::RGB(255, 255, 255));
-    for (i, pixel) in screen_buf.iter().enumerate() {
-        if *pixel {
-            // Convert our 1D array's index into a 2D (x,y) position
-            let x = (i % SCREEN_WIDTH) as u32;
-            let y = (i / SCREEN_WIDTH) as u32;
-            // Draw a rectangle at (x,y), scaled up by our SCALE value
-            let rect = Rect::new((x * SCALE) as i32, (y * SCALE) as i32, SCALE, SCALE);
-            canvas.fill_rect(rect).unwrap();
+// What the ROM database knows about the game, for the F1 help
+fn rom_help(info: Option<&romdb::RomInfo>) -> String {
+    let Some(info) = info else {
+        return String::new();
+    };
+    let mut help = info.byline();
+    if let Some(description) = &info.description {
+        if !help.is_empty() {
+            help += "\n";
         }
+        help += description;
+    }
+    if !info.keys.is_empty() {
+        help += "\n";
+        for (keys, action) in &info.keys {
+            help += &format!("\n{}: {}", keys, action);
+        }
+    }
+    help + "\n\n"
+}
+
+fn read_rom(path: &Path) -> Result<Vec<u8>, String> {
+    let buffer = fs::read(path).map_err(|e| format!("Unable to read ROM {}: {}", path.display(), e))?;
+    if buffer.is_empty() {
+        return Err(format!("ROM {} is empty", path.display()));
+    }
+    if buffer.len() > MAX_ROM_SIZE {
+        return Err(format!("ROM {} is {} bytes, at most {} fit in memory", path.display(), buffer.len(), MAX_ROM_SIZE));
     }
+    Ok(buffer)
+}
+
+// A fresh machine running `rom`
+fn start(rom: &[u8], quirks: Quirks, seed: Option<u64>) -> EMU {
+    let mut chip8 = EMU::new();
+    chip8.set_quirks(quirks);
+    if let Some(seed) = seed {
+        chip8.seed(seed);
+    }
+    chip8.load(rom);
+    chip8
+}
+
+fn exit_with(msg: impl Display) -> ! {
+    eprintln!("{}", msg);
+    std::process::exit(1);
+}
+
+// `pixels` is an RGB24 picture the size of the texture
+fn draw_screen(pixels: &[u8], pitch: usize, canvas: &mut Canvas<Window>, texture: &mut Texture) {
+    texture.update(None, pixels, pitch).unwrap();
+
+    // Clear canvas with the background, for the bars around a fullscreen picture
+    canvas.set_draw_color(Color::RGB(0, 0, 0));
+    canvas.clear();
+    canvas.copy(texture, None, None).unwrap();
     canvas.present();
 }
 
-fn key_to_button(key: Keycode) -> Option<usize> {
-    match key {
-        Keycode::Num1 => Some(0x1),
-        Keycode::Num2 => Some(0x2),
-        Keycode::Num3 => Some(0x3),
-        Keycode::Num4 => Some(0xC),
-        Keycode::Q => Some(0x4),
-        Keycode::W => Some(0x5),
-        Keycode::E => Some(0x6),
-        Keycode::R => Some(0xD),
-        Keycode::A => Some(0x7),
-        Keycode::S => Some(0x8),
-        Keycode::D => Some(0x9),
-        Keycode::F => Some(0xE),
-        Keycode::Z => Some(0xA),
-        Keycode::X => Some(0x0),
-        Keycode::C => Some(0xB),
-        Keycode::V => Some(0xF),
-        _ => None
+fn toggle_effect(pipeline: &mut Pipeline, key: Keycode) -> bool {
+    let mut effects = pipeline.effects();
+    let (name, effect) = match key {
+        Keycode::Num1 => ("scanlines", &mut effects.scanlines),
+        Keycode::Num2 => ("grid", &mut effects.grid),
+        Keycode::Num3 => ("bloom", &mut effects.bloom),
+        Keycode::Num4 => ("curvature", &mut effects.curvature),
+        Keycode::Num5 => ("vignette", &mut effects.vignette),
+        _ => return false,
+    };
+    effect.enabled = !effect.enabled;
+    // an effect that was never configured comes on at half strength
+    if effect.amount == 0.0 {
+        effect.amount = 0.5;
     }
+    println!("{}: {}", name, if effect.enabled {"on"} else {"off"});
+    pipeline.set_effects(effects);
+    true
 }
 
 // Continuous beep source that runs until manually stopped
@@ -0,0 +1,188 @@
+// Memory heatmap window (F11): all 4 KiB of RAM as a 64x64 grid, a cell per byte, lit red
+// when written, green when read and blue when run, fading over a couple of seconds. Hovering
+// over a cell shows what's there. The bar at the bottom goes back through the last
+// HISTORY frames, clicking or dragging on it picks one and right clicking goes back to live.
+
+use crate::font::{Canvas, CELL_HEIGHT};
+use chip8_core::heatmap::{Heatmap, EXECUTE, READ, WRITE};
+use chip8_core::EMU;
+use sdl2::event::{Event, WindowEvent};
+use sdl2::mouse::MouseButton;
+use sdl2::pixels::PixelFormatEnum;
+use sdl2::render::{Canvas as WindowCanvas, Texture};
+use sdl2::video::Window;
+use sdl2::VideoSubsystem;
+
+const HISTORY: usize = 600; // 10 seconds
+const COLUMNS: usize = 64;
+const CELL: usize = 8; // pixels per byte
+const GRID: usize = COLUMNS * CELL;
+const TEXT_SCALE: usize = 2;
+const INFO_Y: usize = GRID + 6;
+const BAR_Y: usize = INFO_Y + CELL_HEIGHT * TEXT_SCALE + 2;
+const BAR_HEIGHT: usize = 12;
+const STATUS_Y: usize = BAR_Y + BAR_HEIGHT + 6;
+const HEIGHT: usize = STATUS_Y + CELL_HEIGHT * TEXT_SCALE;
+
+const BACKGROUND: [u8; 3] = [0, 0, 0];
+const UNTOUCHED: [u8; 3] = [24, 24, 24];
+const TEXT: [u8; 3] = [200, 200, 200];
+const HIGHLIGHT: [u8; 3] = [255, 255, 255];
+
+pub struct MemoryView {
+    canvas: WindowCanvas<Window>,
+    texture: Texture, // freed along with the canvas
+    heatmap: Heatmap,
+    pixels: Vec<u8>,
+    hover: Option<usize>, // address under the mouse
+    back: usize,          // frames back from the newest, 0 is live
+    scrubbing: bool,      // dragging on the bar
+}
+
+impl MemoryView {
+    pub fn new(video: &VideoSubsystem) -> Result<Self, String> {
+        let window = video
+            .window("Memory", GRID as u32, HEIGHT as u32)
+            .build()
+            .map_err(|e| format!("Unable to open the memory window: {}", e))?;
+        let canvas = window.into_canvas().build().map_err(|e| format!("Unable to open the memory window: {}", e))?;
+        let texture = canvas
+            .texture_creator()
+            .create_texture_streaming(PixelFormatEnum::RGB24, GRID as u32, HEIGHT as u32)
+            .map_err(|e| format!("Unable to open the memory window: {}", e))?;
+        Ok(MemoryView {
+            canvas,
+            texture,
+            heatmap: Heatmap::new(HISTORY),
+            pixels: vec![0; GRID * HEIGHT * 3],
+            hover: None,
+            back: 0,
+            scrubbing: false,
+        })
+    }
+
+    pub fn window_id(&self) -> u32 {
+        self.canvas.window().id()
+    }
+
+    // Runs one instruction and notes what it touched
+    pub fn tick(&mut self, chip8: &mut EMU) {
+        self.heatmap.tick(chip8);
+    }
+
+    // Notes what the instruction about to run touches, for when something else runs it
+    pub fn record(&mut self, chip8: &EMU) {
+        self.heatmap.record(chip8);
+    }
+
+    pub fn end_frame(&mut self) {
+        self.heatmap.end_frame();
+        // an older frame on display stays on display
+        if self.back > 0 {
+            self.back = (self.back + 1).min(self.heatmap.history_len() - 1);
+        }
+    }
+
+    // Events for this window. Returns false once it's been closed.
+    pub fn handle(&mut self, evt: &Event) -> bool {
+        match *evt {
+            Event::Window{win_event: WindowEvent::Close, ..} => return false,
+            Event::Window{win_event: WindowEvent::Leave, ..} => self.hover = None,
+            Event::MouseMotion{x, y, ..} => {
+                self.hover = cell(x, y);
+                if self.scrubbing {
+                    self.scrub(x);
+                }
+            },
+            Event::MouseButtonDown{mouse_btn: MouseButton::Left, x, y, ..} if on_bar(y) => {
+                self.scrubbing = true;
+                self.scrub(x);
+            },
+            Event::MouseButtonUp{mouse_btn: MouseButton::Left, ..} => self.scrubbing = false,
+            Event::MouseButtonDown{mouse_btn: MouseButton::Right, ..} => self.back = 0,
+            // up goes back in time
+            Event::MouseWheel{y, ..} => {
+                let newest = self.heatmap.history_len().saturating_sub(1);
+                self.back = self.back.saturating_add_signed(y as isize).min(newest);
+            },
+            _ => (),
+        }
+        true
+    }
+
+    // Picks the frame under `x` on the bar, the right end being the newest
+    fn scrub(&mut self, x: i32) {
+        let frames = self.heatmap.history_len();
+        if frames == 0 {
+            return;
+        }
+        let at = (x.max(0) as usize * frames / GRID).min(frames - 1);
+        self.back = frames - 1 - at;
+    }
+
+    pub fn draw(&mut self, chip8: &EMU) {
+        let mut canvas = Canvas { pixels: &mut self.pixels, width: GRID, height: HEIGHT };
+        canvas.fill_rect(0, 0, GRID, HEIGHT, BACKGROUND);
+
+        let heat = self.heatmap.frame(self.back);
+        for addr in 0..COLUMNS * COLUMNS {
+            let color = heat.map_or(UNTOUCHED, |heat| {
+                let [r, g, b] = heat[addr];
+                [r.max(UNTOUCHED[0]), g.max(UNTOUCHED[1]), b.max(UNTOUCHED[2])]
+            });
+            let (x, y) = (addr % COLUMNS * CELL, addr / COLUMNS * CELL);
+            canvas.fill_rect(x + 1, y + 1, CELL - 1, CELL - 1, color);
+        }
+
+        if let Some(addr) = self.hover {
+            let (x, y) = (addr % COLUMNS * CELL, addr / COLUMNS * CELL);
+            canvas.fill_rect(x, y, CELL + 1, 1, HIGHLIGHT);
+            canvas.fill_rect(x, y + CELL, CELL + 1, 1, HIGHLIGHT);
+            canvas.fill_rect(x, y, 1, CELL + 1, HIGHLIGHT);
+            canvas.fill_rect(x + CELL, y, 1, CELL + 1, HIGHLIGHT);
+
+            let percent = |channel: usize| heat.map_or(0, |heat| heat[addr][channel] as usize * 100 / 255);
+            let info = format!(
+                "{:#05X} {:<7} = {:02X}   W {:>3}% R {:>3}% X {:>3}%",
+                addr, region(addr), chip8.state().ram[addr], percent(WRITE), percent(READ), percent(EXECUTE)
+            );
+            canvas.text(4, INFO_Y, &info, TEXT_SCALE, TEXT);
+        }
+
+        let frames = self.heatmap.history_len();
+        canvas.fill_rect(0, BAR_Y, GRID, BAR_HEIGHT, UNTOUCHED);
+        if frames > 0 {
+            let width = frames * GRID / HISTORY;
+            canvas.fill_rect(GRID - width, BAR_Y, width, BAR_HEIGHT, [60, 60, 60]);
+            let at = GRID - 1 - self.back * GRID / HISTORY;
+            canvas.fill_rect(at.saturating_sub(1), BAR_Y, 3, BAR_HEIGHT, HIGHLIGHT);
+        }
+        let status = match self.back {
+            0 => "live   drag the bar or scroll to go back".to_string(),
+            back => format!("{:.1} s ago   right click: live", back as f64 / 60.0),
+        };
+        canvas.text(4, STATUS_Y, &status, TEXT_SCALE, TEXT);
+
+        self.texture.update(None, &self.pixels, GRID * 3).unwrap();
+        self.canvas.copy(&self.texture, None, None).unwrap();
+        self.canvas.present();
+    }
+}
+
+fn cell(x: i32, y: i32) -> Option<usize> {
+    let (x, y) = (usize::try_from(x).ok()?, usize::try_from(y).ok()?);
+    (x < GRID && y < GRID).then(|| y / CELL * COLUMNS + x / CELL)
+}
+
+fn on_bar(y: i32) -> bool {
+    (BAR_Y as i32..(BAR_Y + BAR_HEIGHT) as i32).contains(&y)
+}
+
+// What the interpreter keeps at an address
+fn region(addr: usize) -> &'static str {
+    match addr {
+        0x000..=0x04F => "font",
+        0x050..=0x1FF => "unused",
+        _ => "program",
+    }
+}
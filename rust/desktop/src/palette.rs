@@ -0,0 +1,185 @@
+// Display colors
+//
+// A palette has four colors, indexed like XO-CHIP bitplanes:
+// 0 = pixel off, 1 = first plane, 2 = second plane, 3 = both planes.
+// Plain CHIP-8 only ever uses the first two.
+
+use sdl2::pixels::Color;
+use std::collections::HashMap;
+
+pub const NUM_COLORS: usize = 4;
+
+// name, [off, plane 1, plane 2, both planes]
+const PRESETS: [(&str, [u32; NUM_COLORS]); 7] = [
+    ("classic", [0x000000, 0xFFFFFF, 0xAAAAAA, 0x555555]),
+    ("green", [0x0A140A, 0x33FF66, 0x1F9E40, 0x0F4F20]), // P1 green phosphor
+    ("amber", [0x140C00, 0xFFB000, 0xB37B00, 0x593D00]), // P3 amber phosphor
+    ("lcd", [0x9BBC0F, 0x0F380F, 0x306230, 0x8BAC0F]),   // greenish pocket LCD
+    ("octo", [0x996600, 0xFFCC00, 0xFF6600, 0x662200]),  // Octo's defaults
+    ("high-contrast", [0x000000, 0xFFFFFF, 0xFFFF00, 0x00FFFF]),
+    ("colorblind", [0x000000, 0xE69F00, 0x56B4E9, 0xF0E442]), // Okabe-Ito, safe for all common color blindness
+];
+
+#[derive(Clone)]
+pub struct Palette {
+    pub name: String,
+    pub colors: [Color; NUM_COLORS],
+}
+
+impl Palette {
+    pub fn off(&self) -> Color {
+        self.colors[0]
+    }
+
+    pub fn on(&self) -> Color {
+        self.colors[1]
+    }
+
+    // for screenshots and recordings, which chip8_core renders itself
+    pub fn to_rgba(&self) -> chip8_core::Palette {
+        chip8_core::Palette { colors: self.colors.map(|c| [c.r, c.g, c.b, 0xFF]) }
+    }
+
+    // "fg,bg" or "fg,bg,plane2,both" in hex, missing colors are taken from `base`
+    pub fn from_hex_list(name: &str, list: &[&str], base: &Palette) -> Result<Palette, String> {
+        if !(2..=NUM_COLORS).contains(&list.len()) {
+            return Err(format!("Palette '{}' needs 2 to 4 colors, got {}", name, list.len()));
+        }
+
+        let mut colors = base.colors;
+        let order = [1, 0, 2, 3]; // foreground first, like --palette always took it
+        for (&idx, hex) in order.iter().zip(list) {
+            colors[idx] = parse_color(hex)?;
+        }
+        Ok(Palette { name: name.to_string(), colors })
+    }
+}
+
+// The palettes F2 cycles through: the presets, then the ones from the config file
+pub struct Palettes {
+    list: Vec<Palette>,
+    current: usize,
+}
+
+impl Palettes {
+    // `custom` are named lists of hex colors from the config file.
+    // `selected` is a palette name or a list of hex colors.
+    pub fn new(custom: &HashMap<String, Vec<String>>, selected: Option<&str>) -> Result<Palettes, String> {
+        let mut list: Vec<Palette> = PRESETS
+            .iter()
+            .map(|(name, colors)| Palette { name: name.to_string(), colors: colors.map(rgb) })
+            .collect();
+
+        let mut names: Vec<&String> = custom.keys().collect();
+        names.sort();
+        for name in names {
+            let hex: Vec<&str> = custom[name].iter().map(String::as_str).collect();
+            let palette = Palette::from_hex_list(name, &hex, &list[0])?;
+            match list.iter().position(|p| p.name == *name) {
+                Some(idx) => list[idx] = palette,
+                None => list.push(palette),
+            }
+        }
+
+        let mut palettes = Palettes { list, current: 0 };
+        if let Some(selected) = selected {
+            palettes.select(selected)?;
+        }
+        Ok(palettes)
+    }
+
+    // A palette name or a list of hex colors
+    pub fn select(&mut self, selected: &str) -> Result<(), String> {
+        self.current = match self.list.iter().position(|p| p.name == selected) {
+            Some(idx) => idx,
+            None if selected.contains(',') => {
+                let hex: Vec<&str> = selected.split(',').collect();
+                let palette = Palette::from_hex_list("custom", &hex, &self.list[0])?;
+                // a second custom palette replaces the first
+                self.list.retain(|p| p.name != "custom");
+                self.list.push(palette);
+                self.list.len() - 1
+            },
+            None => {
+                let names: Vec<&str> = self.list.iter().map(|p| p.name.as_str()).collect();
+                return Err(format!("Unknown palette '{}' (expected {} or hex colors)", selected, names.join(", ")));
+            },
+        };
+        Ok(())
+    }
+
+    pub fn current(&self) -> &Palette {
+        &self.list[self.current]
+    }
+
+    pub fn cycle(&mut self) -> &Palette {
+        self.current = (self.current + 1) % self.list.len();
+        self.current()
+    }
+}
+
+fn rgb(hex: u32) -> Color {
+    Color::RGB((hex >> 16) as u8, (hex >> 8) as u8, hex as u8)
+}
+
+// "rrggbb" or "#rrggbb"
+pub fn parse_color(hex: &str) -> Result<Color, String> {
+    let digits = hex.trim().trim_start_matches('#');
+    // from_str_radix would take a sign too
+    u32::from_str_radix(digits, 16)
+        .ok()
+        .filter(|_| digits.len() == 6 && digits.chars().all(|c| c.is_ascii_hexdigit()))
+        .map(rgb)
+        .ok_or_else(|| format!("Invalid color '{}' (expected rrggbb)", hex))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn colors() {
+        assert_eq!(parse_color("33ff66"), Ok(Color::RGB(0x33, 0xFF, 0x66)));
+        assert_eq!(parse_color(" #0A140A "), Ok(Color::RGB(0x0A, 0x14, 0x0A)));
+        for bad in ["fff", "33ff660", "33gg66", "+fffff", ""] {
+            assert_eq!(parse_color(bad), Err(format!("Invalid color '{}' (expected rrggbb)", bad)));
+        }
+    }
+
+    #[test]
+    fn hex_lists() {
+        let base = Palettes::new(&HashMap::new(), None).unwrap().current().clone();
+        let two = Palette::from_hex_list("mine", &["33ff66", "001100"], &base).unwrap();
+        assert_eq!((two.on(), two.off()), (Color::RGB(0x33, 0xFF, 0x66), Color::RGB(0x00, 0x11, 0x00)));
+        assert_eq!(two.colors[2..], base.colors[2..]);
+
+        let four = Palette::from_hex_list("mine", &["ffffff", "000000", "ff0000", "0000ff"], &base).unwrap();
+        assert_eq!(four.colors[2..], [Color::RGB(0xFF, 0, 0), Color::RGB(0, 0, 0xFF)]);
+        assert_eq!(four.to_rgba().colors[2], [0xFF, 0, 0, 0xFF]);
+
+        assert_eq!(Palette::from_hex_list("mine", &["ffffff"], &base).err().unwrap(), "Palette 'mine' needs 2 to 4 colors, got 1");
+        assert!(Palette::from_hex_list("mine", &["ffffff", "nope"], &base).is_err());
+    }
+
+    #[test]
+    fn selecting_and_cycling() {
+        let custom = HashMap::from([
+            ("green".to_string(), vec!["00ff00".to_string(), "000000".to_string()]),
+            ("zebra".to_string(), vec!["ffffff".to_string(), "000000".to_string()]),
+        ]);
+        let mut palettes = Palettes::new(&custom, Some("green")).unwrap();
+        // the config file replaces a preset of the same name and adds new ones at the end
+        assert_eq!(palettes.current().on(), Color::RGB(0, 0xFF, 0));
+        assert_eq!(palettes.list.len(), PRESETS.len() + 1);
+
+        palettes.select("colorblind").unwrap();
+        assert_eq!(palettes.cycle().name, "zebra");
+        assert_eq!(palettes.cycle().name, "classic");
+
+        palettes.select("ff0000,000000").unwrap();
+        palettes.select("00ff00,000000").unwrap();
+        assert_eq!((palettes.current().name.as_str(), palettes.current().on()), ("custom", Color::RGB(0, 0xFF, 0)));
+        assert_eq!(palettes.list.len(), PRESETS.len() + 2);
+        assert!(palettes.select("sepia").unwrap_err().starts_with("Unknown palette 'sepia' (expected classic, green"));
+    }
+}
@@ -0,0 +1,156 @@
+// Pause, frame advance, fast-forward and slow motion
+//
+// The game loop runs once per vsync, whatever the monitor's refresh rate is, or as fast as
+// it can without vsync. Playback decides how many emulated frames fit into each pass by the
+// wall clock, 60 a second: none while paused, a few times as many while fast-forwarding, and
+// a few times fewer in slow motion.
+
+use std::time::{Duration, Instant};
+
+const FRAME: Duration = Duration::from_nanos(1_000_000_000 / 60);
+// after a stall, like dragging the window, the game carries on instead of catching up
+const MAX_BEHIND: u32 = 4; // frames
+
+pub struct Playback {
+    paused: bool,
+    advance: bool,      // run a single frame while paused
+    fast_forward: bool, // held down
+    slow_motion: bool,
+    fast_factor: u32,
+    slow_factor: u32,
+    last: Option<Instant>, // the previous pass
+    due: Duration,         // wall time that hasn't been emulated yet
+}
+
+impl Playback {
+    pub fn new(fast_factor: u32, slow_factor: u32) -> Self {
+        Playback {
+            paused: false,
+            advance: false,
+            fast_forward: false,
+            slow_motion: false,
+            fast_factor,
+            slow_factor,
+            last: None,
+            due: Duration::ZERO,
+        }
+    }
+
+    pub fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+    }
+
+    // Pauses if needed, then steps one frame
+    pub fn advance(&mut self) {
+        self.paused = true;
+        self.advance = true;
+    }
+
+    pub fn set_fast_forward(&mut self, held: bool) {
+        self.fast_forward = held;
+    }
+
+    pub fn toggle_slow_motion(&mut self) {
+        self.slow_motion = !self.slow_motion;
+        self.due = Duration::ZERO;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    // Emulated frames to run in the pass of the game loop starting at `now`
+    pub fn frames(&mut self, now: Instant) -> u32 {
+        let elapsed = self.last.map_or(Duration::ZERO, |last| now.saturating_duration_since(last));
+        self.last = Some(now);
+        if self.paused {
+            self.due = Duration::ZERO;
+            return std::mem::take(&mut self.advance) as u32;
+        }
+
+        let frame = self.frame_time();
+        self.due += elapsed;
+        let frames = (self.due.as_nanos() / frame.as_nanos()) as u32;
+        self.due -= frame * frames;
+        let frames = frames.min(MAX_BEHIND);
+        if self.fast_forward {frames * self.fast_factor} else {frames}
+    }
+
+    // How long until frames() has something to run, for sleeping when vsync doesn't
+    pub fn until_next_frame(&self) -> Duration {
+        if self.paused {
+            return FRAME;
+        }
+        self.frame_time().saturating_sub(self.due).min(FRAME)
+    }
+
+    // Wall time per emulated frame
+    fn frame_time(&self) -> Duration {
+        if self.slow_motion {FRAME * self.slow_factor} else {FRAME}
+    }
+
+    // e.g. "paused" or "fast x4", empty at normal speed
+    pub fn status(&self) -> String {
+        if self.paused {
+            "paused".to_string()
+        } else if self.fast_forward {
+            format!("fast x{}", self.fast_factor)
+        } else if self.slow_motion {
+            format!("slow 1/{}", self.slow_factor)
+        } else {
+            String::new()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Frames run by passes of the game loop `every` apart
+    fn passes(playback: &mut Playback, every: Duration, count: u32) -> Vec<u32> {
+        let start = *playback.last.get_or_insert_with(Instant::now);
+        (1..=count).map(|n| playback.frames(start + every * n)).collect()
+    }
+
+    #[test]
+    fn frames_follow_the_clock() {
+        // 60 Hz, 144 Hz and no vsync all come out at 60 frames a second
+        for (every, count) in [(FRAME, 60), (Duration::from_secs(1) / 144, 144), (Duration::from_millis(1), 1000)] {
+            let mut playback = Playback::new(4, 4);
+            let frames: u32 = passes(&mut playback, every, count).iter().sum();
+            // 1/144 s rounds down to whole nanoseconds
+            assert!((59..=60).contains(&frames), "{:?}: {}", every, frames);
+        }
+
+        let mut playback = Playback::new(4, 4);
+        assert_eq!(passes(&mut playback, Duration::from_secs(1) / 120, 4), [0, 1, 0, 1]);
+        // a second long hiccup doesn't run a second's worth of frames at once
+        assert_eq!(passes(&mut playback, Duration::from_secs(1), 1), [MAX_BEHIND]);
+    }
+
+    #[test]
+    fn fast_slow_and_paused() {
+        let mut playback = Playback::new(3, 4);
+        playback.set_fast_forward(true);
+        assert_eq!(passes(&mut playback, FRAME, 2), [3, 3]);
+        playback.set_fast_forward(false);
+
+        playback.toggle_slow_motion();
+        assert_eq!(passes(&mut playback, FRAME, 8), [0, 0, 0, 1, 0, 0, 0, 1]);
+        playback.toggle_slow_motion();
+
+        playback.toggle_pause();
+        assert_eq!(passes(&mut playback, FRAME, 2), [0, 0]);
+        playback.advance();
+        assert_eq!(passes(&mut playback, FRAME, 2), [1, 0]);
+        // no catching up on the time spent paused
+        playback.toggle_pause();
+        assert_eq!(passes(&mut playback, FRAME, 2), [1, 1]);
+        assert_eq!(playback.status(), "");
+    }
+}
@@ -0,0 +1,46 @@
+// Recently played ROMs, newest first, kept in ~/.config/chip8/recent.txt (one path per line)
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const MAX_RECENT: usize = 10;
+
+pub struct Recent {
+    path: Option<PathBuf>,
+    list: Vec<PathBuf>,
+}
+
+impl Recent {
+    // The list is a convenience, so an unreadable file just means no recent games
+    pub fn load() -> Recent {
+        let path = dirs::config_dir().map(|dir| dir.join("chip8").join("recent.txt"));
+        let list = path.as_ref()
+            .and_then(|p| fs::read_to_string(p).ok())
+            .map(|text| text.lines().filter(|l| !l.trim().is_empty()).map(PathBuf::from).collect())
+            .unwrap_or_default();
+        Recent { path, list }
+    }
+
+    pub fn list(&self) -> &[PathBuf] {
+        &self.list
+    }
+
+    pub fn add(&mut self, rom: &Path) {
+        let rom = rom.canonicalize().unwrap_or_else(|_| rom.to_path_buf());
+        self.list.retain(|p| *p != rom);
+        self.list.insert(0, rom);
+        self.list.truncate(MAX_RECENT);
+
+        let Some(path) = &self.path else {
+            return;
+        };
+        let text: String = self.list.iter().map(|p| format!("{}\n", p.display())).collect();
+        let result = match path.parent() {
+            Some(dir) => fs::create_dir_all(dir).and_then(|_| fs::write(path, text)),
+            None => fs::write(path, text),
+        };
+        if let Err(e) = result {
+            eprintln!("Unable to save recent games to {}: {}", path.display(), e);
+        }
+    }
+}
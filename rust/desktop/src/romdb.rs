@@ -0,0 +1,198 @@
+// ROM metadata database, keyed by the SHA-1 of the ROM bytes so renamed files are still
+// recognized. `romdb.toml` is built in, `<XDG config dir>/chip8/romdb.toml` (or --romdb)
+// adds entries or overrides single fields of built-in ones.
+
+use crate::browser::is_rom_file;
+use crate::cli::check_speed;
+use crate::keymap::rom_hash;
+use chip8_core::{analyze, Quirks, Variant};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const BUILTIN: &str = include_str!("../romdb.toml");
+// below this the analyzer's guess isn't used
+const GUESS_CONFIDENCE: f32 = 0.5;
+
+#[derive(Deserialize, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct RomInfo {
+    pub file: Option<String>,
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub year: Option<u32>,
+    pub description: Option<String>,
+    pub variant: Option<String>,
+    pub quirks: Option<String>,
+    pub speed: Option<u32>,
+    pub palette: Option<String>,
+    #[serde(default)]
+    pub keys: BTreeMap<String, String>, // CHIP-8 key(s) -> what they do
+}
+
+impl RomInfo {
+    // fields set in `other` win
+    fn merge(&mut self, other: RomInfo) {
+        macro_rules! take {
+            ($($field:ident),*) => { $(if other.$field.is_some() { self.$field = other.$field; })* };
+        }
+        take!(file, title, author, year, description, variant, quirks, speed, palette);
+        self.keys.extend(other.keys);
+    }
+
+    pub fn variant(&self) -> Option<Variant> {
+        // checked when the database is loaded
+        self.variant.as_deref().and_then(|v| v.parse().ok())
+    }
+
+    // "Pong by Paul Vervalin, 1990"
+    pub fn byline(&self) -> String {
+        let mut line = self.title.clone().unwrap_or_default();
+        if let Some(author) = &self.author {
+            line += &format!(" by {}", author);
+        }
+        if let Some(year) = self.year {
+            line += &format!(", {}", year);
+        }
+        line
+    }
+
+    fn check(&self, hash: &str) -> Result<(), String> {
+        let variant = self.variant.as_deref().map(str::parse).transpose().map_err(|e| format!("ROM {}: {}", hash, e))?;
+        Quirks::with_list(variant, self.quirks.as_deref()).map_err(|e| format!("ROM {}: {}", hash, e))?;
+        if let Some(speed) = self.speed {
+            check_speed(speed).map_err(|e| format!("ROM {}: {}", hash, e))?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct RomDbFile {
+    #[serde(default)]
+    rom: HashMap<String, RomInfo>,
+}
+
+pub struct RomDb {
+    roms: HashMap<String, RomInfo>,
+}
+
+pub fn default_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("chip8").join("romdb.toml"))
+}
+
+// An explicit `path` has to exist, the default file is optional
+pub fn load(path: Option<&Path>) -> Result<RomDb, String> {
+    let mut db = RomDb { roms: HashMap::new() };
+    db.add(parse(BUILTIN, "built-in ROM database")?);
+
+    let user = match path {
+        Some(path) => Some(path.to_path_buf()),
+        None => default_path().filter(|path| path.exists()),
+    };
+    if let Some(path) = user {
+        let text = fs::read_to_string(&path)
+            .map_err(|e| format!("Unable to read ROM database {}: {}", path.display(), e))?;
+        db.add(parse(&text, &path.display().to_string())?);
+    }
+    Ok(db)
+}
+
+fn parse(text: &str, name: &str) -> Result<RomDbFile, String> {
+    let file: RomDbFile = toml::from_str(text).map_err(|e| format!("Invalid ROM database {}: {}", name, e))?;
+    for (hash, info) in &file.rom {
+        info.check(hash).map_err(|e| format!("Invalid ROM database {}: {}", name, e))?;
+    }
+    Ok(file)
+}
+
+impl RomDb {
+    fn add(&mut self, file: RomDbFile) {
+        for (hash, info) in file.rom {
+            self.roms.entry(hash.to_ascii_lowercase()).or_default().merge(info);
+        }
+    }
+
+    pub fn get(&self, rom: &[u8]) -> Option<&RomInfo> {
+        self.roms.get(&rom_hash(rom))
+    }
+
+    // The database entry, or for unknown ROMs the variant and quirks the code looks like it
+    // was written for, if the analyzer is sure enough
+    pub fn lookup(&self, rom: &[u8]) -> Option<RomInfo> {
+        if let Some(info) = self.get(rom) {
+            return Some(info.clone());
+        }
+        let analysis = analyze(rom);
+        if analysis.confidence < GUESS_CONFIDENCE {
+            return None;
+        }
+        let quirks = analysis.quirks.diff(&Quirks::for_variant(analysis.variant));
+        let description = format!(
+            "Not in the ROM database, guessed {} ({:.0}% sure) with quirks: {}",
+            analysis.variant,
+            analysis.confidence * 100.0,
+            if analysis.quirks == Quirks::default() {"none".to_string()} else {analysis.quirks.to_string()},
+        );
+        Some(RomInfo {
+            description: Some(description),
+            variant: Some(analysis.variant.to_string()),
+            quirks: Some(quirks),
+            ..RomInfo::default()
+        })
+    }
+
+    // game_catalog.txt for the ROMs in `dir`, matched by content rather than file name
+    pub fn catalog(&self, dir: &Path) -> Result<String, String> {
+        let mut files: Vec<PathBuf> = fs::read_dir(dir)
+            .map_err(|e| format!("Unable to list {}: {}", dir.display(), e))?
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|p| p.is_file() && is_rom_file(p))
+            .collect();
+        files.sort();
+
+        let mut out = String::from("# Generated from romdb.toml with --write-catalog, edit that instead\n");
+        out += "# FILE | Title | Description\n";
+        for path in files {
+            let name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+            let rom = fs::read(&path).map_err(|e| format!("Unable to read {}: {}", path.display(), e))?;
+            let Some(info) = self.get(&rom) else {
+                out += &format!("{}\n", name);
+                continue;
+            };
+            let mut description = info.description.clone().unwrap_or_default();
+            match (&info.author, info.year) {
+                (Some(author), Some(year)) => description += &format!(" ({}, {})", author, year),
+                (Some(author), None) => description += &format!(" ({})", author),
+                _ => (),
+            }
+            let title = info.title.as_deref().unwrap_or(&name);
+            out += &format!("{} | {} | {}\n", name, title, description.trim());
+        }
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checks_speeds() {
+        let db = |speed: u32| parse(&format!("[rom.abc]\nspeed = {}\n", speed), "test");
+        assert!(db(60).is_ok());
+        assert!(db(999_960).is_ok());
+        for speed in [0, 1, 59, 1_000_001, 1_000_020] {
+            let e = db(speed).err().unwrap();
+            assert_eq!(e, "Invalid ROM database test: ROM abc: speed has to be between 60 and 999960");
+        }
+        for (speed, below, above) in [(119, 60, 120), (700, 660, 720)] {
+            let e = db(speed).err().unwrap();
+            assert_eq!(e, format!("Invalid ROM database test: ROM abc: speed has to be a multiple of 60, like {} or {}", below, above));
+        }
+        assert!(parse(BUILTIN, "built-in").is_ok());
+    }
+}
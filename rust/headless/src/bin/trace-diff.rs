@@ -0,0 +1,40 @@
+// Compares two instruction traces, ours from `headless --trace` and one from another
+// emulator in the same KEY=value form, and shows where they first disagree.
+// Exits with 1 if they do, so it can be used in scripts.
+
+use chip8_core::trace::diff;
+use clap::Parser;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::PathBuf;
+
+#[derive(Parser)]
+#[command(version, about = "Finds the first instruction where two CHIP-8 traces differ")]
+struct Args {
+    /// Trace written by headless --trace
+    ours: PathBuf,
+
+    /// Trace from the emulator to compare with
+    theirs: PathBuf,
+}
+
+fn main() {
+    let args = Args::parse();
+    let open = |path: &PathBuf| {
+        File::open(path).map(BufReader::new).unwrap_or_else(|e| {
+            eprintln!("Unable to read {}: {}", path.display(), e);
+            std::process::exit(2);
+        })
+    };
+    match diff(open(&args.ours), open(&args.theirs)) {
+        Ok(None) => println!("The traces agree"),
+        Ok(Some(divergence)) => {
+            print!("{}", divergence);
+            std::process::exit(1);
+        },
+        Err(e) => {
+            eprintln!("Unable to compare the traces: {}", e);
+            std::process::exit(2);
+        },
+    }
+}
@@ -0,0 +1,340 @@
+// Runs a ROM without a window or sound for a fixed number of frames, as fast as possible.
+// Handy for scripted screenshots and README demos, and for checking a ROM still behaves.
+// With --monitor or --script it runs the ROM under the debugger instead.
+
+mod monitor;
+
+use chip8_core::capture::{write_png, GifRecorder};
+use chip8_core::cfg::Cfg;
+use chip8_core::coverage::Coverage;
+use chip8_core::profile::Profiler;
+use chip8_core::symbols::Symbols;
+use chip8_core::trace::Tracer;
+use chip8_core::*;
+use clap::Parser;
+use std::fmt::Display;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+
+const FRAMES_PER_SECOND: u32 = 60;
+
+#[derive(Parser)]
+#[command(version, about = "Runs a CHIP-8 ROM without a window")]
+struct Args {
+    /// CHIP-8 ROM to run
+    rom: PathBuf,
+
+    /// Frames to run, at 60 frames per emulated second
+    #[arg(long, default_value_t = 600)]
+    frames: u64,
+
+    /// Instructions executed per second, a multiple of 60 so every frame runs as many
+    #[arg(long, default_value_t = 600, value_parser = parse_speed)]
+    speed: u32,
+
+    /// Seed for the random number generator, for reproducible runs
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Emulate the quirks of this platform: chip8, schip or xochip
+    #[arg(long)]
+    variant: Option<Variant>,
+
+    /// Quirks to turn on or off on top of the variant, e.g. "shift,-clip"
+    #[arg(long, value_name = "LIST")]
+    quirks: Option<String>,
+
+    /// Write the screen after the last frame to this PNG
+    #[arg(long, value_name = "FILE")]
+    screenshot: Option<PathBuf>,
+
+    /// Record every frame into this animated GIF
+    #[arg(long, value_name = "FILE")]
+    gif: Option<PathBuf>,
+
+    /// Write every frame as raw RGBA to VIDEO ("-" for stdout) and the beep as WAV to
+    /// AUDIO, both at exactly 60 fps for muxing with ffmpeg
+    #[arg(long, num_args = 2, value_names = ["VIDEO", "AUDIO"])]
+    dump_av: Option<Vec<PathBuf>>,
+
+    /// Pixels per CHIP-8 pixel in screenshots, recordings and dumps
+    #[arg(long, default_value_t = 4, value_parser = clap::value_parser!(u32).range(1..=64))]
+    scale: u32,
+
+    /// Pixel art smoothing: nearest, scale2x, scale3x or epx
+    #[arg(long, default_value = "nearest")]
+    filter: ScaleFilter,
+
+    /// Debug the ROM from a command prompt instead of running it
+    #[arg(long)]
+    monitor: bool,
+
+    /// Run the debugger commands in this file, one per line, then exit
+    #[arg(long, value_name = "FILE")]
+    script: Option<PathBuf>,
+
+    /// Print the variant and quirks the ROM's code looks like it was written for, and exit
+    #[arg(long)]
+    analyze: bool,
+
+    /// Print the disassembly of the code reachable from the entry point, and exit
+    #[arg(long)]
+    disassemble: bool,
+
+    /// Write the control flow graph as Graphviz DOT, and exit
+    #[arg(long, value_name = "FILE")]
+    cfg: Option<PathBuf>,
+
+    /// Write which subroutine calls which as Graphviz DOT, and exit
+    #[arg(long, value_name = "FILE")]
+    call_graph: Option<PathBuf>,
+
+    /// Count where the ROM spends its time and print a report after the last frame
+    #[arg(long)]
+    profile: bool,
+
+    /// Write the profile's call stacks in the folded format flamegraph.pl and inferno read
+    #[arg(long, value_name = "FILE", requires = "profile")]
+    folded: Option<PathBuf>,
+
+    /// Write a disassembly of the code that ran, annotated with how often each instruction ran
+    #[arg(long, value_name = "FILE", requires = "profile")]
+    annotate: Option<PathBuf>,
+
+    /// Note which instructions ran and which sprite bytes were drawn, and print what never was
+    #[arg(long)]
+    coverage: bool,
+
+    /// Write the coverage in the lcov format as well, with ROM addresses for line numbers
+    #[arg(long, value_name = "FILE", requires = "coverage")]
+    lcov: Option<PathBuf>,
+
+    /// Labels for the coverage report, disassembly and graphs, one "ADDRESS LABEL" per line
+    #[arg(long, value_name = "FILE")]
+    symbols: Option<PathBuf>,
+
+    /// Write a line per instruction with the registers after it ran to this file ("-" for
+    /// stdout), for comparing with another emulator using trace-diff
+    #[arg(long, value_name = "FILE")]
+    trace: Option<PathBuf>,
+
+    /// Only trace instructions in this address range, e.g. 0x200-0x2FF (hex). Can be given more than once.
+    #[arg(long, value_name = "START-END", value_parser = address_range, requires = "trace")]
+    trace_range: Vec<RangeInclusive<u16>>,
+}
+
+fn main() {
+    let args = Args::parse();
+
+    let buffer = match fs::read(&args.rom) {
+        Ok(buffer) => buffer,
+        Err(e) => exit_with(format!("Unable to read ROM {}: {}", args.rom.display(), e)),
+    };
+    if buffer.is_empty() {
+        exit_with(format!("ROM {} is empty", args.rom.display()));
+    }
+    if buffer.len() > MAX_ROM_SIZE {
+        exit_with(format!("ROM {} is {} bytes, at most {} fit in memory", args.rom.display(), buffer.len(), MAX_ROM_SIZE));
+    }
+
+    let symbols = match &args.symbols {
+        Some(path) => fs::read_to_string(path)
+            .map_err(|e| e.to_string())
+            .and_then(|text| Symbols::parse(&text))
+            .unwrap_or_else(|e| exit_with(format!("Unable to read symbols from {}: {}", path.display(), e))),
+        None => Symbols::default(),
+    };
+
+    if args.analyze {
+        print!("{}", analyze(&buffer));
+        return;
+    }
+    if args.disassemble || args.cfg.is_some() || args.call_graph.is_some() {
+        if args.disassemble {
+            for line in disasm::program(&buffer, &symbols) {
+                println!("{}", line);
+            }
+        }
+        let cfg = Cfg::new(&buffer);
+        if let Some(path) = &args.cfg {
+            fs::write(path, cfg.dot(&symbols)).unwrap_or_else(|e| exit_with(format!("Unable to write {}: {}", path.display(), e)));
+        }
+        if let Some(path) = &args.call_graph {
+            fs::write(path, cfg.call_graph_dot(&symbols)).unwrap_or_else(|e| exit_with(format!("Unable to write {}: {}", path.display(), e)));
+        }
+        return;
+    }
+
+    let quirks = Quirks::with_list(args.variant, args.quirks.as_deref()).unwrap_or_else(|e| exit_with(e));
+
+    let mut chip8 = EMU::new();
+    chip8.set_quirks(quirks);
+    if let Some(seed) = args.seed {
+        chip8.seed(seed);
+    }
+    chip8.load(&buffer);
+    let ticks_per_frame = (args.speed / FRAMES_PER_SECOND) as usize;
+
+    if args.monitor || args.script.is_some() {
+        let mut monitor = monitor::Monitor::new(chip8, ticks_per_frame);
+        let result = match &args.script {
+            Some(path) => monitor.script(path),
+            None => monitor.interactive(),
+        };
+        result.unwrap_or_else(|e| exit_with(e));
+        return;
+    }
+
+    let scale = args.scale as usize;
+    args.filter.check_scale(scale).unwrap_or_else(|e| exit_with(format!("Invalid --scale: {}", e)));
+
+    let mut recorder = args.gif.as_ref().map(|path| {
+        let file = File::create(path).unwrap_or_else(|e| exit_with(format!("Unable to create {}: {}", path.display(), e)));
+        GifRecorder::new(BufWriter::new(file), &Palette::MONO, scale, args.filter)
+            .unwrap_or_else(|e| exit_with(format!("Unable to record {}: {}", path.display(), e)))
+    });
+
+    let mut dump = args.dump_av.as_ref().map(|paths| {
+        let dump = av::FileDump::create(&paths[0], &paths[1], &Palette::MONO, scale, args.filter)
+            .unwrap_or_else(|e| exit_with(format!("Unable to dump to {} and {}: {}", paths[0].display(), paths[1].display(), e)));
+        eprintln!("Dumping {}x{} RGBA at {} fps and {} Hz mono audio", dump.width(), dump.height(), av::FRAMES_PER_SECOND, av::SAMPLE_RATE);
+        dump
+    });
+
+    let mut profiler = args.profile.then(Profiler::new);
+    let mut coverage = args.coverage.then(Coverage::new);
+    let mut tracer = args.trace.as_ref().map(|path| {
+        let out: Box<dyn Write> = if path == Path::new("-") {
+            Box::new(io::stdout().lock())
+        } else {
+            let file = File::create(path).unwrap_or_else(|e| exit_with(format!("Unable to create {}: {}", path.display(), e)));
+            Box::new(file)
+        };
+        Tracer::new(BufWriter::new(out), args.trace_range.clone())
+    });
+
+    for _ in 0..args.frames {
+        for _ in 0..ticks_per_frame {
+            let pc = chip8.state().pc;
+            let op = chip8.state().opcode(pc);
+            if let Some(coverage) = &mut coverage {
+                coverage.record(&chip8);
+            }
+            chip8.tick();
+            if let Some(profiler) = &mut profiler {
+                profiler.record(pc, op, chip8.state().pc);
+            }
+            if let Some(tracer) = &mut tracer {
+                tracer.record(pc, op, &chip8).unwrap_or_else(|e| exit_with(format!("Unable to write the trace: {}", e)));
+            }
+        }
+        chip8.tick_timers();
+        if let Some(recorder) = &mut recorder {
+            recorder.add_frame(&chip8).unwrap_or_else(|e| exit_with(format!("Unable to record frame: {}", e)));
+        }
+        if let Some(dump) = &mut dump {
+            dump.frame(&chip8).unwrap_or_else(|e| exit_with(format!("Unable to dump frame: {}", e)));
+        }
+    }
+
+    if let Some(tracer) = tracer {
+        tracer.finish().unwrap_or_else(|e| exit_with(format!("Unable to write the trace: {}", e)));
+    }
+
+    if let Some(dump) = dump {
+        dump.close().unwrap_or_else(|e| exit_with(format!("Unable to finish dump: {}", e)));
+    }
+
+    if let (Some(recorder), Some(path)) = (recorder, &args.gif) {
+        recorder.finish().unwrap_or_else(|e| exit_with(format!("Unable to write {}: {}", path.display(), e)));
+    }
+    if let Some(path) = &args.screenshot {
+        let file = File::create(path).unwrap_or_else(|e| exit_with(format!("Unable to create {}: {}", path.display(), e)));
+        write_png(BufWriter::new(file), &chip8, &Palette::MONO, scale, args.filter)
+            .unwrap_or_else(|e| exit_with(format!("Unable to write {}: {}", path.display(), e)));
+    }
+
+    // when the trace or the video goes to stdout the reports go to stderr, out of its way
+    let stdout_taken = args.trace.as_deref() == Some(Path::new("-"))
+        || args.dump_av.as_ref().is_some_and(|paths| paths[0] == Path::new("-"));
+    let report = |text: String| if stdout_taken {eprint!("{}", text)} else {print!("{}", text)};
+
+    if let Some(profiler) = &profiler {
+        let ram = chip8.state().ram;
+        report(profiler.report(ram));
+        if let Some(path) = &args.folded {
+            fs::write(path, profiler.folded()).unwrap_or_else(|e| exit_with(format!("Unable to write {}: {}", path.display(), e)));
+        }
+        if let Some(path) = &args.annotate {
+            fs::write(path, profiler.annotate(ram)).unwrap_or_else(|e| exit_with(format!("Unable to write {}: {}", path.display(), e)));
+        }
+    }
+
+    if let Some(coverage) = &coverage {
+        report(coverage.report(buffer.len(), &symbols));
+        if let Some(path) = &args.lcov {
+            let lcov = coverage.lcov(&args.rom.to_string_lossy(), buffer.len(), &symbols);
+            fs::write(path, lcov).unwrap_or_else(|e| exit_with(format!("Unable to write {}: {}", path.display(), e)));
+        }
+    }
+}
+
+// "0x200-0x2FF", or a single address. Addresses are in hex with or without 0x, here, in the
+// monitor and in symbol files.
+fn address_range(text: &str) -> Result<RangeInclusive<u16>, String> {
+    let (start, end) = match text.split_once('-') {
+        Some((start, end)) => (parse_address(start)?, parse_address(end)?),
+        None => (parse_address(text)?, parse_address(text)?),
+    };
+    if start > end {
+        return Err(format!("{:#05X} comes after {:#05X}", start, end));
+    }
+    Ok(start..=end)
+}
+
+// Frames run whole instructions, so anything between two multiples of 60 would quietly run
+// at the lower one
+fn parse_speed(text: &str) -> Result<u32, String> {
+    let speed: u32 = text.parse().map_err(|_| format!("'{}' is not a number", text))?;
+    if !(FRAMES_PER_SECOND..=999_960).contains(&speed) {
+        return Err("speed has to be between 60 and 999960".to_string());
+    }
+    if !speed.is_multiple_of(FRAMES_PER_SECOND) {
+        let below = speed / FRAMES_PER_SECOND * FRAMES_PER_SECOND;
+        return Err(format!("speed has to be a multiple of 60, like {} or {}", below, below + FRAMES_PER_SECOND));
+    }
+    Ok(speed)
+}
+
+fn exit_with(msg: impl Display) -> ! {
+    eprintln!("{}", msg);
+    std::process::exit(1);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn address_ranges() {
+        assert_eq!(address_range("0x200-0x2FF"), Ok(0x200..=0x2FF));
+        assert_eq!(address_range("200-2ff"), Ok(0x200..=0x2FF));
+        assert_eq!(address_range("0x2A4"), Ok(0x2A4..=0x2A4));
+        assert_eq!(address_range("0x300-0x200"), Err("0x300 comes after 0x200".to_string()));
+        assert_eq!(address_range("0x200-0x1000"), Err("0x1000 is outside of RAM".to_string()));
+        assert!(address_range("0x200-").is_err());
+        assert!(address_range("main-0x300").is_err());
+    }
+
+    #[test]
+    fn speeds() {
+        assert_eq!(parse_speed("600"), Ok(600));
+        assert_eq!(parse_speed("999960"), Ok(999_960));
+        assert_eq!(parse_speed("700"), Err("speed has to be a multiple of 60, like 660 or 720".to_string()));
+        assert_eq!(parse_speed("119"), Err("speed has to be a multiple of 60, like 60 or 120".to_string()));
+        assert_eq!(parse_speed("59"), Err("speed has to be between 60 and 999960".to_string()));
+        assert_eq!(parse_speed("fast"), Err("'fast' is not a number".to_string()));
+    }
+}
@@ -0,0 +1,523 @@
+// Text monitor around the core debugger, like MAME's debugger console. Reads commands with
+// line editing and history, or from a script file for sessions that have to be reproducible.
+
+use chip8_core::debug::{Breakpoint, Debugger, Stop, WatchKind, Watchpoint};
+use chip8_core::disasm::listing;
+use chip8_core::expr::{Expr, Message};
+use crate::address_range;
+use chip8_core::{parse_address, Register, EMU, SCREEN_HEIGHT, SCREEN_WIDTH};
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// `continue` gives up after this many frames without a stop, a minute of emulated time
+const CONTINUE_FRAMES: u64 = 3600;
+const HISTORY_FILE: &str = ".chip8_monitor_history";
+// instructions that can be taken back unless the journal command says otherwise
+const JOURNAL: usize = 100_000;
+
+const HELP: &str = "\
+break [ADDR [OPTIONS]]    set a breakpoint, or list them
+delete ADDR               remove a breakpoint
+watch [r|w|rw ADDR[-END] [OPTIONS]]
+                          set a watchpoint on reads, writes or both, or list them
+unwatch N                 remove watchpoint N
+step [N]                  run N instructions (1)
+continue [FRAMES]         run until something stops it, at most FRAMES frames (3600)
+reverse-step [N]          take back N instructions (1)
+reverse-continue          take back instructions up to the previous breakpoint
+who ADDR                  the last instruction that wrote ADDR
+journal [N]               keep the last N instructions for going back (100000), 0 for none
+regs                      registers, timers and the call stack
+mem ADDR [LEN]            hex dump of LEN bytes (64)
+disasm [ADDR] [COUNT]     disassemble COUNT instructions (10) from ADDR (the PC)
+set REG|ADDR VALUE        set V0-VF, I, PC, SP, DT, ST or a byte of memory
+keys [KEY down|up]        press or release a key, or show the pressed ones
+screen                    the screen as text
+help                      this list
+quit                      leave
+Addresses, and values for I and PC, are hex with or without 0x. Other numbers are decimal, or hex with 0x. An empty line repeats step, continue and going back.
+Changing registers or memory by hand starts a new journal.
+
+Breakpoint and watchpoint OPTIONS:
+  if COND                 only when COND is true, e.g. if v0 == 3 && mem[i] > 0x40
+  log \"MESSAGE\"           print MESSAGE and keep running, e.g. log \"v0={v0} i={i:x}\"
+COND can use v0-vf, i, pc, sp, dt, st, mem[ADDR], key[N], hits and C operators. Numbers in COND
+are decimal unless they start with 0x, inside mem[] too: mem[0x300], not mem[300].";
+
+pub struct Monitor {
+    emu: EMU,
+    debugger: Debugger,
+    ticks_per_frame: usize,
+    repeat: Option<String>,
+}
+
+impl Monitor {
+    pub fn new(emu: EMU, ticks_per_frame: usize) -> Self {
+        let mut debugger = Debugger::new(ticks_per_frame);
+        debugger.set_journal(JOURNAL);
+        Monitor { emu, debugger, ticks_per_frame, repeat: None }
+    }
+
+    pub fn interactive(&mut self) -> Result<(), String> {
+        let mut editor = DefaultEditor::new().map_err(|e| format!("Unable to start the monitor: {}", e))?;
+        let history = history_path();
+        if let Some(path) = &history {
+            // there's none the first time
+            let _ = editor.load_history(path);
+        }
+        println!("CHIP-8 monitor, 'help' lists the commands");
+        println!("{}", self.here());
+        loop {
+            let line = match editor.readline("> ") {
+                Ok(line) => line,
+                Err(ReadlineError::Interrupted | ReadlineError::Eof) => break,
+                Err(e) => return Err(format!("Unable to read the command: {}", e)),
+            };
+            if !line.trim().is_empty() {
+                let _ = editor.add_history_entry(line.as_str());
+            }
+            if is_quit(&line) {
+                break;
+            }
+            match self.execute(&line) {
+                Ok(out) if out.is_empty() => (),
+                Ok(out) => println!("{}", out),
+                Err(e) => println!("error: {}", e),
+            }
+        }
+        if let Some(path) = &history
+            && let Err(e) = editor.save_history(path)
+        {
+            eprintln!("Unable to save the monitor history to {}: {}", path.display(), e);
+        }
+        Ok(())
+    }
+
+    // Runs every line of the script, echoing it first. Stops at the first error.
+    pub fn script(&mut self, path: &Path) -> Result<(), String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("Unable to read script {}: {}", path.display(), e))?;
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            println!("> {}", line);
+            if is_quit(line) {
+                break;
+            }
+            let out = self.execute(line).map_err(|e| format!("{}:{}: {}", path.display(), n + 1, e))?;
+            if !out.is_empty() {
+                println!("{}", out);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn execute(&mut self, line: &str) -> Result<String, String> {
+        let line = match (line.trim(), &self.repeat) {
+            ("", Some(last)) => last.clone(),
+            (line, _) => line.to_string(),
+        };
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let Some((&command, args)) = words.split_first() else {
+            return Ok(String::new());
+        };
+        self.repeat = None;
+
+        match (command, args) {
+            ("help" | "h" | "?", []) => Ok(HELP.to_string()),
+
+            ("break" | "b", []) => {
+                let list: Vec<String> = self.debugger.breakpoints()
+                    .map(|bp| describe(&format!("{:#05X}", bp.addr), &bp.when, &bp.log, bp.hits))
+                    .collect();
+                Ok(if list.is_empty() {"no breakpoints".to_string()} else {list.join("\n")})
+            },
+            ("break" | "b", [addr, ..]) => {
+                let addr = parse_address(addr)?;
+                let (when, log) = options(after_words(&line, 2))?;
+                let replaced = !self.debugger.add_breakpoint(Breakpoint { when, log, ..Breakpoint::at(addr) });
+                Ok(format!("breakpoint at {:#05X}{}", addr, if replaced {" replaced"} else {""}))
+            },
+            ("delete" | "d", [addr]) => {
+                let addr = parse_address(addr)?;
+                if !self.debugger.remove_breakpoint(addr) {
+                    return Err(format!("no breakpoint at {:#05X}", addr));
+                }
+                Ok(String::new())
+            },
+
+            ("watch" | "w", []) => {
+                let list: Vec<String> = self.debugger.watchpoints()
+                    .map(|(n, w)| describe(&format!("{}: {} {:#05X}-{:#05X}", n, w.kind, w.start, w.end), &w.when, &w.log, w.hits))
+                    .collect();
+                Ok(if list.is_empty() {"no watchpoints".to_string()} else {list.join("\n")})
+            },
+            ("watch" | "w", [kind, range, ..]) => {
+                let kind: WatchKind = kind.parse()?;
+                let (start, end) = address_range(range)?.into_inner();
+                let (when, log) = options(after_words(&line, 3))?;
+                let n = self.debugger.add_watchpoint(Watchpoint { when, log, ..Watchpoint::new(kind, start, end) });
+                Ok(format!("watchpoint {}: {} {:#05X}-{:#05X}", n, kind, start, end))
+            },
+            ("unwatch", [n]) => {
+                let n = number(n)?;
+                if !self.debugger.remove_watchpoint(n as usize) {
+                    return Err(format!("no watchpoint {}", n));
+                }
+                Ok(String::new())
+            },
+
+            ("step" | "s", _) => {
+                let count = match args {
+                    [] => 1,
+                    [n] => number(n)? as u64,
+                    _ => return Err("usage: step [N]".to_string()),
+                };
+                self.repeat = Some(line.clone());
+                Ok(self.run(count))
+            },
+            ("continue" | "c", _) => {
+                let frames = match args {
+                    [] => CONTINUE_FRAMES,
+                    [n] => number(n)? as u64,
+                    _ => return Err("usage: continue [FRAMES]".to_string()),
+                };
+                self.repeat = Some(line.clone());
+                Ok(self.run(frames * self.ticks_per_frame as u64))
+            },
+
+            ("reverse-step" | "rs", _) => {
+                let count = match args {
+                    [] => 1,
+                    [n] => number(n)? as u64,
+                    _ => return Err("usage: reverse-step [N]".to_string()),
+                };
+                self.repeat = Some(line.clone());
+                let stop = self.debugger.reverse_step(&mut self.emu, count);
+                Ok(self.stopped(stop))
+            },
+            ("reverse-continue" | "rc", []) => {
+                self.repeat = Some(line.clone());
+                let stop = self.debugger.reverse_continue(&mut self.emu);
+                Ok(self.stopped(stop))
+            },
+            ("who", [addr]) => {
+                let addr = parse_address(addr)?;
+                match self.debugger.last_write(addr) {
+                    Some((pc, instruction)) => {
+                        let code = listing(self.emu.state().ram, pc, 1).pop().unwrap_or_default();
+                        Ok(format!("{:#05X} was last written by instruction {}:\n{}", addr, instruction, code))
+                    },
+                    None => Ok(format!("{:#05X} wasn't written as far as the journal goes", addr)),
+                }
+            },
+            ("journal", []) => {
+                let (len, limit) = self.debugger.journal_len();
+                Ok(format!("{} of at most {} instructions can be taken back", len, limit))
+            },
+            ("journal", [n]) => {
+                self.debugger.set_journal(number(n)? as usize);
+                Ok(String::new())
+            },
+
+            ("regs" | "r", []) => Ok(self.registers()),
+            ("mem" | "m", [addr, len @ ..]) if len.len() <= 1 => {
+                let addr = parse_address(addr)? as usize;
+                let len = len.first().map(|n| number(n)).transpose()?.unwrap_or(64) as usize;
+                let ram = self.emu.state().ram;
+                let end = (addr + len).min(ram.len());
+                let lines: Vec<String> = (addr..end).step_by(16)
+                    .map(|row| {
+                        let bytes: Vec<String> = ram[row..(row + 16).min(end)].iter().map(|b| format!("{:02X}", b)).collect();
+                        format!("{:#05X}  {}", row, bytes.join(" "))
+                    })
+                    .collect();
+                Ok(lines.join("\n"))
+            },
+            ("disasm" | "u", _) if args.len() <= 2 => {
+                let pc = self.emu.state().pc;
+                let addr = args.first().map(|a| parse_address(a)).transpose()?.unwrap_or(pc);
+                let count = args.get(1).map(|n| number(n)).transpose()?.unwrap_or(10) as usize;
+                let breakpoints: Vec<u16> = self.debugger.breakpoints().map(|bp| bp.addr).collect();
+                let lines: Vec<String> = listing(self.emu.state().ram, addr, count)
+                    .into_iter()
+                    .enumerate()
+                    .map(|(n, line)| {
+                        let at = addr + 2 * n as u16;
+                        let mark = if at == pc {'>'} else if breakpoints.contains(&at) {'*'} else {' '};
+                        format!("{} {}", mark, line)
+                    })
+                    .collect();
+                Ok(lines.join("\n"))
+            },
+            ("set", [target, value]) => {
+                match target.parse::<Register>() {
+                    // I and PC hold addresses, so they're hex like everywhere else
+                    Ok(reg @ (Register::I | Register::Pc)) => {
+                        self.debugger.set_register(&mut self.emu, reg, parse_address(value)?).map_err(|e| e.to_string())?;
+                    },
+                    Ok(reg) => {
+                        let value = number(value)?;
+                        let value = u16::try_from(value).map_err(|_| format!("{} is too big", value))?;
+                        self.debugger.set_register(&mut self.emu, reg, value).map_err(|e| e.to_string())?;
+                    },
+                    Err(_) => {
+                        let addr = parse_address(target).map_err(|_| format!("'{}' is neither a register nor an address", target))?;
+                        let value = number(value)?;
+                        let byte = u8::try_from(value).map_err(|_| format!("{:#X} doesn't fit in a byte", value))?;
+                        self.debugger.write_memory(&mut self.emu, addr, &[byte]).map_err(|e| e.to_string())?;
+                    },
+                }
+                Ok(String::new())
+            },
+
+            ("keys" | "k", []) => {
+                let pressed: Vec<String> = self.emu.state().keys.iter().enumerate()
+                    .filter(|(_, down)| **down)
+                    .map(|(key, _)| format!("{:X}", key))
+                    .collect();
+                Ok(if pressed.is_empty() {"no keys pressed".to_string()} else {pressed.join(" ")})
+            },
+            ("keys" | "k", [key, state]) => {
+                let key = usize::from_str_radix(key.trim_start_matches("0x"), 16)
+                    .ok()
+                    .filter(|&key| key < 16)
+                    .ok_or_else(|| format!("'{}' is not a key, expected 0-F", key))?;
+                let down = match *state {
+                    "down" => true,
+                    "up" => false,
+                    _ => return Err(format!("'{}' should be down or up", state)),
+                };
+                self.emu.keypress(key, down);
+                Ok(String::new())
+            },
+
+            ("screen", []) => {
+                let display = self.emu.get_display();
+                let lines: Vec<String> = display.chunks(SCREEN_WIDTH).take(SCREEN_HEIGHT)
+                    .map(|row| row.iter().map(|&on| if on {'#'} else {'.'}).collect())
+                    .collect();
+                Ok(lines.join("\n"))
+            },
+
+            _ => Err(format!("unknown command or wrong arguments: '{}', try help", line)),
+        }
+    }
+
+    fn run(&mut self, count: u64) -> String {
+        let stop = self.debugger.run(&mut self.emu, count);
+        self.stopped(stop)
+    }
+
+    fn stopped(&mut self, stop: Stop) -> String {
+        let mut out = self.debugger.take_log();
+        if stop != Stop::Done {
+            out.push(stop.to_string());
+        }
+        out.push(self.here());
+        out.join("\n")
+    }
+
+    // The next instruction
+    fn here(&self) -> String {
+        let pc = self.emu.state().pc;
+        let line = listing(self.emu.state().ram, pc, 1).pop().unwrap_or_default();
+        format!("-> {}    (frame {}, instruction {})", line, self.debugger.frames(), self.debugger.instructions())
+    }
+
+    fn registers(&self) -> String {
+        let state = self.emu.state();
+        let reg = |r| state.register(r);
+        let mut out = format!(
+            "PC {:#05X}  I {:#05X}  SP {}  DT {}  ST {}\n",
+            reg(Register::Pc), reg(Register::I), reg(Register::Sp), reg(Register::Dt), reg(Register::St)
+        );
+        let v: Vec<String> = (0..16).map(|x| format!("V{:X} {:02X}", x, reg(Register::V(x)))).collect();
+        out += &v[..8].join("  ");
+        out += "\n";
+        out += &v[8..].join("  ");
+        let stack: Vec<String> = state.stack.iter().map(|a| format!("{:#05X}", a)).collect();
+        if !stack.is_empty() {
+            out += &format!("\nstack {}", stack.join(" "));
+        }
+        out
+    }
+}
+
+// "if COND" and/or "log MESSAGE", the message optionally in quotes
+fn options(text: &str) -> Result<(Option<Expr>, Option<Message>), String> {
+    let text = text.trim();
+    let (condition, log) = match text.strip_prefix("log ") {
+        Some(log) => ("", Some(log)),
+        None => match text.find(" log ") {
+            Some(at) => (&text[..at], Some(&text[at + 5..])),
+            None => (text, None),
+        },
+    };
+    let when = match condition.trim() {
+        "" => None,
+        condition => match condition.strip_prefix("if ") {
+            Some(expr) => Some(Expr::parse(expr)?),
+            None => return Err(format!("expected 'if' or 'log' but found '{}'", condition)),
+        },
+    };
+    let log = log
+        .map(|log| {
+            let log = log.trim();
+            let log = log.strip_prefix('"').and_then(|l| l.strip_suffix('"')).unwrap_or(log);
+            Message::parse(log)
+        })
+        .transpose()?;
+    Ok((when, log))
+}
+
+fn describe(what: &str, when: &Option<Expr>, log: &Option<Message>, hits: u64) -> String {
+    let mut text = what.to_string();
+    if let Some(when) = when {
+        text += &format!(" if {}", when);
+    }
+    if let Some(log) = log {
+        text += &format!(" log \"{}\"", log);
+    }
+    text + &format!(" ({} hits)", hits)
+}
+
+// `line` without its first `n` words
+fn after_words(line: &str, n: usize) -> &str {
+    let mut rest = line.trim_start();
+    for _ in 0..n {
+        rest = rest.trim_start_matches(|c: char| !c.is_whitespace()).trim_start();
+    }
+    rest
+}
+
+fn is_quit(line: &str) -> bool {
+    matches!(line.trim(), "quit" | "q" | "exit")
+}
+
+fn history_path() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(HISTORY_FILE))
+}
+
+// Decimal, or hex with 0x
+fn number(text: &str) -> Result<u32, String> {
+    let parsed = match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => text.parse(),
+    };
+    parsed.map_err(|_| format!("'{}' is not a number", text))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn monitor() -> Monitor {
+        let mut emu = EMU::new();
+        emu.load(&[
+            0x60, 0x05, // 200: LD V0, 5
+            0xA3, 0x00, // 202: LD I, 300
+            0xF1, 0x55, // 204: LD [I], V1, stores V0
+            0x70, 0x01, // 206: ADD V0, 1
+            0x12, 0x06, // 208: JP 206
+        ]);
+        Monitor::new(emu, 10)
+    }
+
+    #[test]
+    fn words_numbers_and_options() {
+        assert_eq!(after_words("break 0x206 if v0 == 3", 2), "if v0 == 3");
+        assert_eq!(after_words("  w   r 300-30F  log hi", 3), "log hi");
+        assert_eq!(after_words("step", 2), "");
+        assert_eq!(number("10"), Ok(10));
+        assert_eq!(number("0x1f"), Ok(31));
+        assert_eq!(number("0X10"), Ok(16));
+        assert_eq!(number("1f"), Err("'1f' is not a number".to_string()));
+
+        let show = |text: &str| options(text).map(|(when, log)| (when.map(|w| w.to_string()), log.map(|l| l.to_string())));
+        assert_eq!(show(""), Ok((None, None)));
+        assert_eq!(show("if v0 == 3"), Ok((Some("v0 == 3".to_string()), None)));
+        assert_eq!(show("log \"v0={v0}\""), Ok((None, Some("v0={v0}".to_string()))));
+        assert_eq!(show("if hits > 2 log v0={v0:x}"), Ok((Some("hits > 2".to_string()), Some("v0={v0:x}".to_string()))));
+        assert_eq!(show("when v0"), Err("expected 'if' or 'log' but found 'when v0'".to_string()));
+        assert!(show("if v0 ==").is_err());
+    }
+
+    #[test]
+    fn breakpoints_and_stepping() {
+        let mut monitor = monitor();
+        assert_eq!(monitor.execute("break 206"), Ok("breakpoint at 0x206".to_string()));
+        assert_eq!(monitor.execute("continue"), Ok("breakpoint at 0x206\n-> 0x206  7001  ADD V0, #01    (frame 0, instruction 3)".to_string()));
+        assert_eq!(monitor.execute("step"), Ok("-> 0x208  1206  JP #206    (frame 0, instruction 4)".to_string()));
+        // an empty line steps again
+        assert_eq!(monitor.execute(""), Ok("-> 0x206  7001  ADD V0, #01    (frame 0, instruction 5)".to_string()));
+        assert!(monitor.execute("regs").unwrap().contains("V0 06"));
+
+        assert_eq!(monitor.execute("b 0x206 if v0 == 7 log \"v0={v0}\""), Ok("breakpoint at 0x206 replaced".to_string()));
+        assert_eq!(monitor.execute("break"), Ok("0x206 if v0 == 7 log \"v0={v0}\" (0 hits)".to_string()));
+        monitor.execute("step 4").unwrap();
+        assert_eq!(monitor.execute("break"), Ok("0x206 if v0 == 7 log \"v0={v0}\" (2 hits)".to_string()));
+        assert_eq!(monitor.execute("delete 206"), Ok(String::new()));
+        assert_eq!(monitor.execute("delete 206"), Err("no breakpoint at 0x206".to_string()));
+        assert_eq!(monitor.execute("break"), Ok("no breakpoints".to_string()));
+
+        assert!(monitor.execute("reverse-step 2").unwrap().starts_with("-> 0x206"));
+        assert!(monitor.execute("regs").unwrap().contains("V0 07"));
+        assert_eq!(monitor.execute("break main"), Err("'main' is not an address".to_string()));
+        assert_eq!(monitor.execute("step 1 2"), Err("usage: step [N]".to_string()));
+    }
+
+    #[test]
+    fn watch_ranges() {
+        let mut monitor = monitor();
+        assert_eq!(monitor.execute("watch w 300-30f"), Ok("watchpoint 0: w 0x300-0x30F".to_string()));
+        assert_eq!(monitor.execute("watch rw 0x2A0 if v0 > 1"), Ok("watchpoint 1: rw 0x2A0-0x2A0".to_string()));
+        assert_eq!(monitor.execute("watch r 0x310-0x300"), Err("0x310 comes after 0x300".to_string()));
+        assert_eq!(monitor.execute("watch w 300-1000"), Err("0x1000 is outside of RAM".to_string()));
+        assert!(monitor.execute("watch x 300").is_err());
+        assert_eq!(monitor.execute("watch"), Ok("0: w 0x300-0x30F (0 hits)\n1: rw 0x2A0-0x2A0 if v0 > 1 (0 hits)".to_string()));
+
+        assert_eq!(
+            monitor.execute("continue"),
+            Ok("watchpoint 0: 0x300 written by the instruction at 0x204\n-> 0x206  7001  ADD V0, #01    (frame 0, instruction 3)".to_string())
+        );
+        assert_eq!(monitor.execute("mem 300 2"), Ok("0x300  05 00".to_string()));
+        assert_eq!(monitor.execute("unwatch 1"), Ok(String::new()));
+        assert_eq!(monitor.execute("unwatch 1"), Err("no watchpoint 1".to_string()));
+    }
+
+    #[test]
+    fn set_memory_and_keys() {
+        let mut monitor = monitor();
+        assert_eq!(monitor.execute("set v3 0x10"), Ok(String::new()));
+        assert_eq!(monitor.execute("set i 300"), Ok(String::new()));
+        assert!(monitor.execute("regs").unwrap().starts_with("PC 0x200  I 0x300  SP 0  DT 0  ST 0\n"));
+        assert_eq!(monitor.execute("set i 0x2A0"), Ok(String::new()));
+        assert_eq!(monitor.execute("set pc 204"), Ok(String::new()));
+        assert!(monitor.execute("regs").unwrap().starts_with("PC 0x204  I 0x2A0  SP 0  DT 0  ST 0\n"));
+        assert_eq!(monitor.execute("set pc 0x200"), Ok(String::new()));
+        assert_eq!(monitor.execute("set i main"), Err("'main' is not an address".to_string()));
+        assert_eq!(monitor.execute("set dt 10"), Ok(String::new()));
+        assert!(monitor.execute("regs").unwrap().contains("DT 10"));
+        assert!(monitor.execute("regs").unwrap().contains("V3 10"));
+        assert_eq!(monitor.execute("set i 0x1000"), Err("0x1000 is outside of RAM".to_string()));
+        assert_eq!(monitor.execute("set v0 256"), Err("0x100 doesn't fit in a byte".to_string()));
+        assert_eq!(monitor.execute("set 0x301 0xAB"), Ok(String::new()));
+        assert_eq!(monitor.execute("set zz 1"), Err("'zz' is neither a register nor an address".to_string()));
+        assert_eq!(monitor.execute("mem 300 3"), Ok("0x300  00 AB 00".to_string()));
+        assert_eq!(monitor.execute("mem 0xFFE"), Ok("0xFFE  00 00".to_string()));
+
+        assert_eq!(monitor.execute("keys"), Ok("no keys pressed".to_string()));
+        monitor.execute("keys 5 down").unwrap();
+        monitor.execute("k a down").unwrap();
+        assert_eq!(monitor.execute("keys"), Ok("5 A".to_string()));
+        monitor.execute("keys 5 up").unwrap();
+        assert_eq!(monitor.execute("keys"), Ok("A".to_string()));
+        assert_eq!(monitor.execute("keys 10 down"), Err("'10' is not a key, expected 0-F".to_string()));
+        assert_eq!(monitor.execute("keys 5 sideways"), Err("'sideways' should be down or up".to_string()));
+    }
+}